@@ -1 +0,0 @@
-DROP TABLE org;
\ No newline at end of file
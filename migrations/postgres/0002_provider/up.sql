@@ -0,0 +1,17 @@
+CREATE TABLE provider (
+    id INTEGER PRIMARY KEY,
+    client_id VARCHAR(64) NOT NULL,
+    client_secret BYTEA NOT NULL,
+    key_id INTEGER NOT NULL,
+    data_key BYTEA NOT NULL,
+    domain VARCHAR(2000) NOT NULL,
+    redirect_uri VARCHAR(2000),
+    scope VARCHAR(64),
+    org_id UUID NOT NULL REFERENCES org(id) ON DELETE CASCADE,
+    created TIMESTAMP WITH TIME ZONE NOT NULL,
+    UNIQUE (org_id, client_id)
+);
+
+-- notes:
+-- id is randomly generated
+-- client_secret is encrypted by data_key, which is wrapped by master key key_id
@@ -0,0 +1,15 @@
+CREATE TABLE user_provider (
+    id INTEGER PRIMARY KEY,
+    token_access BYTEA NOT NULL,
+    token_refresh BYTEA,
+    key_id INTEGER NOT NULL,
+    data_key BYTEA NOT NULL,
+    token_expires TIMESTAMP WITH TIME ZONE,
+    token_refresh_dead BOOLEAN NOT NULL DEFAULT FALSE,
+    provider_id INTEGER NOT NULL REFERENCES provider(id) ON DELETE CASCADE,
+    created TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+-- notes:
+-- id is randomly generated
+-- tokens are encrypted by data_key, which is wrapped by master key key_id
@@ -0,0 +1,9 @@
+CREATE TABLE oauth_state (
+    state VARCHAR(64) PRIMARY KEY,
+    code_verifier VARCHAR(128) NOT NULL,
+    provider_id INTEGER NOT NULL REFERENCES provider(id) ON DELETE CASCADE,
+    created TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+-- notes:
+-- rows are single-use and deleted once consumed or expired
@@ -0,0 +1,13 @@
+CREATE TABLE api_key (
+    id INTEGER PRIMARY KEY,
+    name VARCHAR(32) NOT NULL,
+    key_hash BYTEA NOT NULL UNIQUE,
+    org_id UUID NOT NULL REFERENCES org(id) ON DELETE CASCADE,
+    last_used TIMESTAMP WITH TIME ZONE,
+    expires TIMESTAMP WITH TIME ZONE,
+    created TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+-- notes:
+-- id is randomly generated
+-- key_hash is the sha256 of the key, which is only ever shown on creation
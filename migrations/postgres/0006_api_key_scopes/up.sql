@@ -0,0 +1,4 @@
+ALTER TABLE api_key ADD COLUMN scopes VARCHAR(256) NOT NULL DEFAULT 'providers:read providers:write users:read users:write users:delete';
+
+-- notes:
+-- scopes are space-separated, with existing keys keeping all the access they had before scopes
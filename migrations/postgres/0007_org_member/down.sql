@@ -0,0 +1,2 @@
+DROP TABLE org_invite;
+DROP TABLE org_member;
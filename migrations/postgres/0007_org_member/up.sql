@@ -0,0 +1,27 @@
+CREATE TABLE org_member (
+    id UUID PRIMARY KEY,
+    name VARCHAR(32) NOT NULL,
+    pw_hash BYTEA NOT NULL,
+    pw_salt BYTEA NOT NULL,
+    pw_created TIMESTAMP WITH TIME ZONE NOT NULL,
+    role VARCHAR(16) NOT NULL,
+    org_id UUID NOT NULL REFERENCES org(id) ON DELETE CASCADE,
+    created TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE TABLE org_invite (
+    id INTEGER PRIMARY KEY,
+    token_hash BYTEA NOT NULL UNIQUE,
+    role VARCHAR(16) NOT NULL,
+    org_id UUID NOT NULL REFERENCES org(id) ON DELETE CASCADE,
+    expires TIMESTAMP WITH TIME ZONE NOT NULL,
+    created TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+INSERT INTO org_member (id, name, pw_hash, pw_salt, pw_created, role, org_id, created)
+SELECT md5(random()::text || id::text)::uuid, 'owner', pw_hash, pw_salt, pw_created, 'owner', id, created
+FROM org;
+
+-- notes:
+-- every existing org gets an owner member sharing the org password, so that orgs always have an owner
+-- token_hash is the sha256 of the invite token, which is only ever shown on creation
@@ -0,0 +1,11 @@
+CREATE TABLE password_reset (
+    id INTEGER PRIMARY KEY,
+    token_hash BYTEA NOT NULL UNIQUE,
+    org_id UUID NOT NULL REFERENCES org(id) ON DELETE CASCADE,
+    expires TIMESTAMP WITH TIME ZONE NOT NULL,
+    created TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+-- notes:
+-- token_hash is the sha256 of the reset token, which is only ever given to the notifier
+-- an org has at most one outstanding reset, new ones are refused until it expires or is used
@@ -0,0 +1,3 @@
+DROP TABLE totp_recovery;
+DROP TABLE totp;
+ALTER TABLE org DROP COLUMN totp_required;
@@ -0,0 +1,23 @@
+ALTER TABLE org ADD COLUMN totp_required BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE totp (
+    id INTEGER PRIMARY KEY,
+    login_id UUID NOT NULL UNIQUE,
+    org_id UUID NOT NULL REFERENCES org(id) ON DELETE CASCADE,
+    secret BYTEA NOT NULL,
+    key_id INTEGER NOT NULL,
+    data_key BYTEA NOT NULL,
+    confirmed BOOLEAN NOT NULL,
+    last_step BIGINT NOT NULL,
+    created TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE TABLE totp_recovery (
+    code_hash BYTEA PRIMARY KEY,
+    totp_id INTEGER NOT NULL REFERENCES totp(id) ON DELETE CASCADE
+);
+
+-- notes:
+-- login_id is either an org or an org_member id, so member deletes remove their totp row themselves
+-- secret is encrypted like provider secrets, so it's rotated alongside them by --rotate-keys
+-- code_hash is the sha256 of a recovery code, which is only ever shown on confirming
@@ -0,0 +1,5 @@
+ALTER TABLE oauth_state ADD COLUMN binding_hash BYTEA NOT NULL DEFAULT '';
+
+-- notes:
+-- binding_hash is the sha256 of the binding given to whoever started the flow, which must be echoed back to finish it
+-- states from before bindings existed get an empty hash which nothing matches, so they can't be finished
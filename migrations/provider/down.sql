@@ -1 +0,0 @@
-DROP TABLE provider;
\ No newline at end of file
@@ -0,0 +1,12 @@
+CREATE TABLE org (
+    id BLOB PRIMARY KEY,
+    name VARCHAR(32) NOT NULL,
+    pw_hash BLOB NOT NULL,
+    pw_salt BLOB NOT NULL,
+    pw_created TEXT NOT NULL,
+    created TEXT NOT NULL
+);
+
+-- notes:
+-- id is a uuid stored as it's 16 raw bytes
+-- timestamps are stored in utc as `YYYY-MM-DD HH:MM:SS.SSS` text, which sorts chronologically
@@ -0,0 +1,15 @@
+CREATE TABLE user_provider (
+    id INTEGER PRIMARY KEY,
+    token_access BLOB NOT NULL,
+    token_refresh BLOB,
+    key_id INTEGER NOT NULL,
+    data_key BLOB NOT NULL,
+    token_expires TEXT,
+    token_refresh_dead BOOLEAN NOT NULL DEFAULT FALSE,
+    provider_id INTEGER NOT NULL REFERENCES provider(id) ON DELETE CASCADE,
+    created TEXT NOT NULL
+);
+
+-- notes:
+-- id is randomly generated
+-- tokens are encrypted by data_key, which is wrapped by master key key_id
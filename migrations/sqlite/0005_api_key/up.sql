@@ -0,0 +1,13 @@
+CREATE TABLE api_key (
+    id INTEGER PRIMARY KEY,
+    name VARCHAR(32) NOT NULL,
+    key_hash BLOB NOT NULL UNIQUE,
+    org_id BLOB NOT NULL REFERENCES org(id) ON DELETE CASCADE,
+    last_used TEXT,
+    expires TEXT,
+    created TEXT NOT NULL
+);
+
+-- notes:
+-- id is randomly generated
+-- key_hash is the sha256 of the key, which is only ever shown on creation
@@ -0,0 +1,27 @@
+CREATE TABLE org_member (
+    id BLOB PRIMARY KEY,
+    name VARCHAR(32) NOT NULL,
+    pw_hash BLOB NOT NULL,
+    pw_salt BLOB NOT NULL,
+    pw_created TEXT NOT NULL,
+    role VARCHAR(16) NOT NULL,
+    org_id BLOB NOT NULL REFERENCES org(id) ON DELETE CASCADE,
+    created TEXT NOT NULL
+);
+
+CREATE TABLE org_invite (
+    id INTEGER PRIMARY KEY,
+    token_hash BLOB NOT NULL UNIQUE,
+    role VARCHAR(16) NOT NULL,
+    org_id BLOB NOT NULL REFERENCES org(id) ON DELETE CASCADE,
+    expires TEXT NOT NULL,
+    created TEXT NOT NULL
+);
+
+INSERT INTO org_member (id, name, pw_hash, pw_salt, pw_created, role, org_id, created)
+SELECT randomblob(16), 'owner', pw_hash, pw_salt, pw_created, 'owner', id, created
+FROM org;
+
+-- notes:
+-- every existing org gets an owner member sharing the org password, so that orgs always have an owner
+-- token_hash is the sha256 of the invite token, which is only ever shown on creation
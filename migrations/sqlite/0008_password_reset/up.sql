@@ -0,0 +1,11 @@
+CREATE TABLE password_reset (
+    id INTEGER PRIMARY KEY,
+    token_hash BLOB NOT NULL UNIQUE,
+    org_id BLOB NOT NULL REFERENCES org(id) ON DELETE CASCADE,
+    expires TEXT NOT NULL,
+    created TEXT NOT NULL
+);
+
+-- notes:
+-- token_hash is the sha256 of the reset token, which is only ever given to the notifier
+-- an org has at most one outstanding reset, new ones are refused until it expires or is used
@@ -0,0 +1 @@
+ALTER TABLE oauth_state DROP COLUMN binding_hash;
@@ -1 +0,0 @@
-DROP TABLE user_provider;
\ No newline at end of file
@@ -1,6 +1,14 @@
+use crate::models::Scope;
 use actix_web::{http::StatusCode, HttpResponse};
+use serde::Serialize;
 use std::fmt;
 
+/// Media type of [Problem] response bodies, from RFC 7807
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Detail given in place of internal errors, which are only logged
+const REDACTED_DETAIL: &str = "An internal error occurred";
+
 /// Shortcut to `Result<T, AuthError>` for model internals
 pub type AuthResult<T, Id> = Result<T, AuthError<Id>>;
 
@@ -10,6 +18,13 @@ pub trait GetErrorCode {
     fn code(&self) -> StatusCode;
 }
 
+/// See [GetErrorSlug::slug] for more information
+pub trait GetErrorSlug {
+    /// Gets a stable machine-readable code for a given error instance, which
+    /// clients may match on instead of the human-readable message
+    fn slug(&self) -> &'static str;
+}
+
 #[derive(Debug, PartialEq)]
 pub struct AuthError<Id: fmt::Display + Clone> {
     pub kind: AuthErrorKind,
@@ -40,10 +55,54 @@ impl<Id: fmt::Display + Clone> GetErrorCode for AuthError<Id> {
     }
 }
 
-impl<Id: fmt::Display + Clone> Into<HttpResponse> for AuthError<Id> {
-    fn into(self) -> HttpResponse {
-        HttpResponse::new(self.code())
-            .set_body(actix_web::dev::Body::from_message(format!("{}", self)))
+impl<Id: fmt::Display + Clone> GetErrorSlug for AuthError<Id> {
+    fn slug(&self) -> &'static str {
+        self.kind.slug()
+    }
+}
+
+impl<Id: fmt::Display + Clone + Serialize> From<AuthError<Id>> for HttpResponse {
+    fn from(err: AuthError<Id>) -> Self {
+        if err.kind.is_internal() {
+            eprintln!("❌ {}", err);
+        }
+
+        HttpResponse::build(err.code())
+            .content_type(PROBLEM_CONTENT_TYPE)
+            .json(Problem::from(&err))
+    }
+}
+
+/// RFC 7807 problem details body for an [AuthError]
+#[derive(Serialize, Debug, PartialEq)]
+struct Problem<'a, Id: Serialize> {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    /// See [GetErrorSlug::slug]
+    code: &'static str,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a Id>,
+}
+
+impl<'a, Id: fmt::Display + Clone + Serialize> From<&'a AuthError<Id>> for Problem<'a, Id> {
+    fn from(err: &'a AuthError<Id>) -> Self {
+        let status = err.code();
+
+        Self {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Unknown"),
+            status: status.as_u16(),
+            code: err.slug(),
+            detail: if err.kind.is_internal() {
+                REDACTED_DETAIL.to_string()
+            } else {
+                err.kind.to_string()
+            },
+            id: err.id.as_ref(),
+        }
     }
 }
 
@@ -56,23 +115,53 @@ pub enum AuthErrorKind {
     ProviderError(ProviderError),
     /// See [UserError] for documentation
     UserError(UserError),
+    /// See [ApiKeyError] for documentation
+    ApiKeyError(ApiKeyError),
+    /// See [MemberError] for documentation
+    MemberError(MemberError),
+    /// See [TotpError] for documentation
+    TotpError(TotpError),
     /// Database error whilst handling a request, should not be exposed publicly
     DatabaseError(String),
     /// Argon2 could not properly hash given input
     HashError(argon2::Error),
+    /// Secrets could not be encrypted or decrypted, such as from an unknown or
+    /// incorrect master key
+    EncryptionError,
+    /// Too many password hashes are queued to handle the request in time
+    Busy,
     /// Unknown error occurred with optional extra info given, should not be
     /// exposed publicly
     UnknownError(Option<String>),
 }
 
+impl AuthErrorKind {
+    /// Checks if this error is internal, meaning it's details should only be
+    /// logged and never exposed publicly
+    pub fn is_internal(&self) -> bool {
+        matches!(
+            self,
+            AuthErrorKind::DatabaseError(_)
+                | AuthErrorKind::HashError(_)
+                | AuthErrorKind::EncryptionError
+                | AuthErrorKind::UnknownError(_)
+        )
+    }
+}
+
 impl fmt::Display for AuthErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             AuthErrorKind::OrgError(err) => write!(f, "{} for org", err),
             AuthErrorKind::UserError(err) => write!(f, "{} for user", err),
             AuthErrorKind::ProviderError(err) => write!(f, "{} for provider", err),
+            AuthErrorKind::ApiKeyError(err) => write!(f, "{} for api key", err),
+            AuthErrorKind::MemberError(err) => write!(f, "{} for member", err),
+            AuthErrorKind::TotpError(err) => write!(f, "{} for two-factor", err),
             AuthErrorKind::DatabaseError(err) => write!(f, "Database error, {}", err),
             AuthErrorKind::UnknownError(Some(err)) => write!(f, "Unknown error, {}", err),
+            AuthErrorKind::EncryptionError => write!(f, "Encryption error"),
+            AuthErrorKind::Busy => write!(f, "Server is too busy, try again later"),
             AuthErrorKind::UnknownError(None) | &AuthErrorKind::HashError(_) => {
                 write!(f, "Unknown error, no info known")
             }
@@ -86,9 +175,32 @@ impl GetErrorCode for AuthErrorKind {
             AuthErrorKind::OrgError(err) => err.code(),
             AuthErrorKind::ProviderError(err) => err.code(),
             AuthErrorKind::UserError(err) => err.code(),
+            AuthErrorKind::ApiKeyError(err) => err.code(),
+            AuthErrorKind::MemberError(err) => err.code(),
+            AuthErrorKind::TotpError(err) => err.code(),
             AuthErrorKind::DatabaseError(_)
             | AuthErrorKind::UnknownError(_)
-            | AuthErrorKind::HashError(_) => StatusCode::from_u16(500).unwrap(),
+            | AuthErrorKind::HashError(_)
+            | AuthErrorKind::EncryptionError => StatusCode::from_u16(500).unwrap(),
+            AuthErrorKind::Busy => StatusCode::from_u16(503).unwrap(),
+        }
+    }
+}
+
+impl GetErrorSlug for AuthErrorKind {
+    fn slug(&self) -> &'static str {
+        match self {
+            AuthErrorKind::OrgError(err) => err.slug(),
+            AuthErrorKind::ProviderError(err) => err.slug(),
+            AuthErrorKind::UserError(err) => err.slug(),
+            AuthErrorKind::ApiKeyError(err) => err.slug(),
+            AuthErrorKind::MemberError(err) => err.slug(),
+            AuthErrorKind::TotpError(err) => err.slug(),
+            AuthErrorKind::DatabaseError(_) => "database_error",
+            AuthErrorKind::HashError(_) => "hash_error",
+            AuthErrorKind::EncryptionError => "encryption_error",
+            AuthErrorKind::Busy => "busy",
+            AuthErrorKind::UnknownError(_) => "unknown_error",
         }
     }
 }
@@ -99,6 +211,18 @@ impl From<argon2::Error> for AuthErrorKind {
     }
 }
 
+impl From<chacha20poly1305::Error> for AuthErrorKind {
+    fn from(_: chacha20poly1305::Error) -> Self {
+        Self::EncryptionError
+    }
+}
+
+impl From<sqlx::Error> for AuthErrorKind {
+    fn from(err: sqlx::Error) -> Self {
+        Self::DatabaseError(err.to_string())
+    }
+}
+
 /// Specific errors for the [Org] model
 #[derive(Debug, PartialEq)]
 pub enum OrgError {
@@ -106,6 +230,16 @@ pub enum OrgError {
     NothingToPatch,
     /// The submitted UUID when querying was invalid
     InvalidUuidQuery(uuid::Error),
+    /// No org with the requested id exists
+    NotFound,
+    /// Credentials given for the org were incorrect
+    Unauthorized,
+    /// Credentials given for the org don't hold the scope needed
+    MissingScope(Scope),
+    /// The password reset token given is unknown or has already been used
+    ResetInvalid,
+    /// The password reset token given is too old to be used
+    ResetExpired,
 }
 
 impl fmt::Display for OrgError {
@@ -115,6 +249,13 @@ impl fmt::Display for OrgError {
             OrgError::InvalidUuidQuery(err) => {
                 write!(f, "The submitted UUID when querying was invalid ({})", err)
             }
+            OrgError::NotFound => write!(f, "Could not be found"),
+            OrgError::Unauthorized => write!(f, "Credentials given were incorrect"),
+            OrgError::MissingScope(scope) => {
+                write!(f, "Credentials given lack the {} scope", scope)
+            }
+            OrgError::ResetInvalid => write!(f, "Reset token is unknown or already used"),
+            OrgError::ResetExpired => write!(f, "Reset token has expired"),
         }
     }
 }
@@ -128,12 +269,32 @@ impl From<OrgError> for AuthErrorKind {
 impl GetErrorCode for OrgError {
     fn code(&self) -> StatusCode {
         StatusCode::from_u16(match self {
-            OrgError::NothingToPatch | OrgError::InvalidUuidQuery(_) => 400,
+            OrgError::NothingToPatch
+            | OrgError::InvalidUuidQuery(_)
+            | OrgError::ResetInvalid
+            | OrgError::ResetExpired => 400,
+            OrgError::Unauthorized => 401,
+            OrgError::MissingScope(_) => 403,
+            OrgError::NotFound => 404,
         })
         .unwrap()
     }
 }
 
+impl GetErrorSlug for OrgError {
+    fn slug(&self) -> &'static str {
+        match self {
+            OrgError::NothingToPatch => "org_nothing_to_patch",
+            OrgError::InvalidUuidQuery(_) => "org_invalid_uuid",
+            OrgError::NotFound => "org_not_found",
+            OrgError::Unauthorized => "org_unauthorized",
+            OrgError::MissingScope(_) => "org_missing_scope",
+            OrgError::ResetInvalid => "org_reset_invalid",
+            OrgError::ResetExpired => "org_reset_expired",
+        }
+    }
+}
+
 /// Specific errors for the [Provider] model
 #[derive(Debug, PartialEq)]
 pub enum ProviderError {
@@ -142,21 +303,42 @@ pub enum ProviderError {
     DomainTooLong,
     RedirectUriTooLong,
     ScopeTooLong,
+    /// No data was given to patch (update)
+    NothingToPatch,
+    /// No provider with the requested id exists for this org
+    NotFound,
+    /// A provider with the same id already exists for this org
+    AlreadyExists,
+    /// The provider's endpoints could not be reached
+    Unreachable,
+    /// The provider responded with something that couldn't be understood
+    InvalidResponse,
+    /// The provider rejected the grant with the given oauth error code, such as
+    /// an expired code or revoked refresh token
+    Rejected(String),
+    /// The provider rejected the client itself with the given oauth error code,
+    /// such as for a wrong client secret
+    Misconfigured(String),
 }
 
 impl fmt::Display for ProviderError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                ProviderError::IdTooLong => "Id (client_id) is too long",
-                ProviderError::SecretTooLong => "Secret (client_secret) is too long",
-                ProviderError::DomainTooLong => "Domain is too long",
-                ProviderError::RedirectUriTooLong => "Redirect URI is too long",
-                ProviderError::ScopeTooLong => "Scope is too long",
+        match self {
+            ProviderError::IdTooLong => write!(f, "Id (client_id) is too long"),
+            ProviderError::SecretTooLong => write!(f, "Secret (client_secret) is too long"),
+            ProviderError::DomainTooLong => write!(f, "Domain is too long"),
+            ProviderError::RedirectUriTooLong => write!(f, "Redirect URI is too long"),
+            ProviderError::ScopeTooLong => write!(f, "Scope is too long"),
+            ProviderError::NothingToPatch => write!(f, "No data was given to patch (update)"),
+            ProviderError::NotFound => write!(f, "Could not be found"),
+            ProviderError::AlreadyExists => write!(f, "Already exists"),
+            ProviderError::Unreachable => write!(f, "Could not be reached"),
+            ProviderError::InvalidResponse => write!(f, "Responded invalidly"),
+            ProviderError::Rejected(code) => write!(f, "Request was rejected ({})", code),
+            ProviderError::Misconfigured(code) => {
+                write!(f, "Client configuration was rejected ({})", code)
             }
-        )
+        }
     }
 }
 
@@ -168,7 +350,40 @@ impl From<ProviderError> for AuthErrorKind {
 
 impl GetErrorCode for ProviderError {
     fn code(&self) -> StatusCode {
-        todo!("provider response code")
+        StatusCode::from_u16(match self {
+            ProviderError::IdTooLong
+            | ProviderError::SecretTooLong
+            | ProviderError::DomainTooLong
+            | ProviderError::RedirectUriTooLong
+            | ProviderError::ScopeTooLong
+            | ProviderError::NothingToPatch
+            | ProviderError::Rejected(_) => 400,
+            ProviderError::NotFound => 404,
+            ProviderError::AlreadyExists => 409,
+            ProviderError::Unreachable
+            | ProviderError::InvalidResponse
+            | ProviderError::Misconfigured(_) => 502,
+        })
+        .unwrap()
+    }
+}
+
+impl GetErrorSlug for ProviderError {
+    fn slug(&self) -> &'static str {
+        match self {
+            ProviderError::IdTooLong => "provider_id_too_long",
+            ProviderError::SecretTooLong => "provider_secret_too_long",
+            ProviderError::DomainTooLong => "provider_domain_too_long",
+            ProviderError::RedirectUriTooLong => "provider_redirect_uri_too_long",
+            ProviderError::ScopeTooLong => "provider_scope_too_long",
+            ProviderError::NothingToPatch => "provider_nothing_to_patch",
+            ProviderError::NotFound => "provider_not_found",
+            ProviderError::AlreadyExists => "provider_already_exists",
+            ProviderError::Unreachable => "provider_unreachable",
+            ProviderError::InvalidResponse => "provider_invalid_response",
+            ProviderError::Rejected(_) => "provider_rejected",
+            ProviderError::Misconfigured(_) => "provider_misconfigured",
+        }
     }
 }
 
@@ -177,6 +392,19 @@ impl GetErrorCode for ProviderError {
 pub enum UserError {
     /// User's name is too long
     NameTooLong,
+    /// No user with the requested id exists for this org
+    NotFound,
+    /// No `state` was given when completing an authorization code flow
+    StateMissing,
+    /// The `state` given is unknown or has already been used
+    StateInvalid,
+    /// The `state` given is too old to be used
+    StateExpired,
+    /// No binding was given alongside `state`, or it isn't the one given to
+    /// whoever started the flow
+    BindingInvalid,
+    /// No refresh token is stored so the access token can't be refreshed
+    NoRefreshToken,
 }
 
 impl fmt::Display for UserError {
@@ -186,6 +414,12 @@ impl fmt::Display for UserError {
             "{}",
             match self {
                 UserError::NameTooLong => "Name is too long",
+                UserError::NotFound => "Could not be found",
+                UserError::StateMissing => "No state was given",
+                UserError::StateInvalid => "State is unknown or already used",
+                UserError::StateExpired => "State has expired",
+                UserError::BindingInvalid => "Binding is missing or wasn't issued alongside state",
+                UserError::NoRefreshToken => "No refresh token is stored",
             }
         )
     }
@@ -200,12 +434,216 @@ impl From<UserError> for AuthErrorKind {
 impl GetErrorCode for UserError {
     fn code(&self) -> StatusCode {
         StatusCode::from_u16(match self {
-            &UserError::NameTooLong => 400,
+            UserError::NameTooLong
+            | UserError::StateMissing
+            | UserError::StateInvalid
+            | UserError::StateExpired
+            | UserError::BindingInvalid => 400,
+            UserError::NotFound => 404,
+            UserError::NoRefreshToken => 409,
         })
         .unwrap()
     }
 }
 
+impl GetErrorSlug for UserError {
+    fn slug(&self) -> &'static str {
+        match self {
+            UserError::NameTooLong => "user_name_too_long",
+            UserError::NotFound => "user_not_found",
+            UserError::StateMissing => "user_state_missing",
+            UserError::StateInvalid => "user_state_invalid",
+            UserError::StateExpired => "user_state_expired",
+            UserError::BindingInvalid => "user_binding_invalid",
+            UserError::NoRefreshToken => "user_no_refresh_token",
+        }
+    }
+}
+
+/// Specific errors for the [ApiKey] model
+#[derive(Debug, PartialEq)]
+pub enum ApiKeyError {
+    /// Api key's name is too long
+    NameTooLong,
+    /// No scopes were given, so the key couldn't be used for anything
+    NoScopes,
+    /// Expiry given has already passed
+    AlreadyExpired,
+    /// No api key with the requested id exists for this org
+    NotFound,
+}
+
+impl fmt::Display for ApiKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ApiKeyError::NameTooLong => "Name is too long",
+                ApiKeyError::NoScopes => "No scopes were given",
+                ApiKeyError::AlreadyExpired => "Expiry has already passed",
+                ApiKeyError::NotFound => "Could not be found",
+            }
+        )
+    }
+}
+
+impl From<ApiKeyError> for AuthErrorKind {
+    fn from(err: ApiKeyError) -> Self {
+        AuthErrorKind::ApiKeyError(err)
+    }
+}
+
+impl GetErrorCode for ApiKeyError {
+    fn code(&self) -> StatusCode {
+        StatusCode::from_u16(match self {
+            ApiKeyError::NameTooLong | ApiKeyError::NoScopes | ApiKeyError::AlreadyExpired => 400,
+            ApiKeyError::NotFound => 404,
+        })
+        .unwrap()
+    }
+}
+
+impl GetErrorSlug for ApiKeyError {
+    fn slug(&self) -> &'static str {
+        match self {
+            ApiKeyError::NameTooLong => "api_key_name_too_long",
+            ApiKeyError::NoScopes => "api_key_no_scopes",
+            ApiKeyError::AlreadyExpired => "api_key_already_expired",
+            ApiKeyError::NotFound => "api_key_not_found",
+        }
+    }
+}
+
+/// Specific errors for the [OrgMember] and [OrgInvite] models
+#[derive(Debug, PartialEq)]
+pub enum MemberError {
+    /// Member's name is too long
+    NameTooLong,
+    /// A member with the same name already exists for this org
+    AlreadyExists,
+    /// No member with the requested id exists for this org
+    NotFound,
+    /// The change would leave the org without an owner
+    LastOwner,
+    /// The invite token given is unknown or has already been used
+    InviteInvalid,
+    /// The invite token given is too old to be used
+    InviteExpired,
+}
+
+impl fmt::Display for MemberError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                MemberError::NameTooLong => "Name is too long",
+                MemberError::AlreadyExists => "Already exists",
+                MemberError::NotFound => "Could not be found",
+                MemberError::LastOwner => "Org must keep at least one owner",
+                MemberError::InviteInvalid => "Invite is unknown or already used",
+                MemberError::InviteExpired => "Invite has expired",
+            }
+        )
+    }
+}
+
+impl From<MemberError> for AuthErrorKind {
+    fn from(err: MemberError) -> Self {
+        AuthErrorKind::MemberError(err)
+    }
+}
+
+impl GetErrorCode for MemberError {
+    fn code(&self) -> StatusCode {
+        StatusCode::from_u16(match self {
+            MemberError::NameTooLong | MemberError::InviteInvalid | MemberError::InviteExpired => {
+                400
+            }
+            MemberError::NotFound => 404,
+            MemberError::AlreadyExists | MemberError::LastOwner => 409,
+        })
+        .unwrap()
+    }
+}
+
+impl GetErrorSlug for MemberError {
+    fn slug(&self) -> &'static str {
+        match self {
+            MemberError::NameTooLong => "member_name_too_long",
+            MemberError::AlreadyExists => "member_already_exists",
+            MemberError::NotFound => "member_not_found",
+            MemberError::LastOwner => "member_last_owner",
+            MemberError::InviteInvalid => "member_invite_invalid",
+            MemberError::InviteExpired => "member_invite_expired",
+        }
+    }
+}
+
+/// Specific errors for the [Totp] model
+#[derive(Debug, PartialEq)]
+pub enum TotpError {
+    /// Two-factor is enabled but no one-time code was given
+    CodeRequired,
+    /// The one-time or recovery code given is incorrect or already used
+    CodeInvalid,
+    /// The org requires two-factor but it isn't enabled for this login
+    Required,
+    /// No two-factor enrollment exists for this login
+    NotFound,
+    /// Two-factor is already enabled for this login
+    AlreadyEnabled,
+}
+
+impl fmt::Display for TotpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                TotpError::CodeRequired => "One-time code is required in the X-OTP header",
+                TotpError::CodeInvalid => {
+                    "One-time code is incorrect or already used, start a session to reuse a login"
+                }
+                TotpError::Required => "Required by the org, enroll first",
+                TotpError::NotFound => "Could not be found",
+                TotpError::AlreadyEnabled => "Already enabled",
+            }
+        )
+    }
+}
+
+impl From<TotpError> for AuthErrorKind {
+    fn from(err: TotpError) -> Self {
+        AuthErrorKind::TotpError(err)
+    }
+}
+
+impl GetErrorCode for TotpError {
+    fn code(&self) -> StatusCode {
+        StatusCode::from_u16(match self {
+            TotpError::CodeRequired | TotpError::CodeInvalid => 401,
+            TotpError::Required => 403,
+            TotpError::NotFound => 404,
+            TotpError::AlreadyEnabled => 409,
+        })
+        .unwrap()
+    }
+}
+
+impl GetErrorSlug for TotpError {
+    fn slug(&self) -> &'static str {
+        match self {
+            TotpError::CodeRequired => "totp_code_required",
+            TotpError::CodeInvalid => "totp_code_invalid",
+            TotpError::Required => "totp_required",
+            TotpError::NotFound => "totp_not_found",
+            TotpError::AlreadyEnabled => "totp_already_enabled",
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -217,10 +655,36 @@ mod tests {
         assert_eq!(format!("{}", err), "Name is too long for user");
 
         let uuid = Uuid::new_v4();
-        err.id = Some(uuid.clone());
+        err.id = Some(uuid);
         assert_eq!(
             format!("{}", err),
             format!("Name is too long for user ({})", uuid)
         );
     }
+
+    #[test]
+    fn problem_body() {
+        let err = AuthError::new(ProviderError::NotFound, 42);
+        assert_eq!(
+            serde_json::to_value(Problem::from(&err)).unwrap(),
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Not Found",
+                "status": 404,
+                "code": "provider_not_found",
+                "detail": "Could not be found for provider",
+                "id": 42
+            })
+        );
+
+        let err: AuthError<i32> = AuthError::new(
+            AuthErrorKind::DatabaseError("relation \"org\" does not exist".to_string()),
+            None,
+        );
+        let problem = Problem::from(&err);
+        assert_eq!(problem.status, 500);
+        assert_eq!(problem.code, "database_error");
+        assert_eq!(problem.detail, REDACTED_DETAIL);
+        assert_eq!(problem.id, None);
+    }
 }
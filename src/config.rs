@@ -1,150 +1,1369 @@
-//! Small static configuration structure and helper methods
+//! Small static configuration structure and helper methods, read from
+//! environment variables layered over an optional config file
 
-use std::{env, fmt};
+use crate::crypto::{MasterKey, KEY_LENGTH};
+use crate::secret::{self, SecretSource};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{convert::TryInto, env, fmt, fs, str::FromStr};
+use zeroize::Zeroizing;
 
-/// Amount of parts for the [parse_host] function
-const HOST_PART_NUM: usize = 4;
+/// Environment variable for the config file path, if not given by `--config`
+const CONFIG_PATH_VAR: &str = "AUTHRIO_CONFIG";
+
+/// Maximum length of a hostname within [Config::bind]
+const MAX_HOSTNAME_LEN: usize = 253;
+
+/// Maximum length of each dot-separated label of a hostname within [Config::bind]
+const MAX_LABEL_LEN: usize = 63;
+
+/// Default for [MasterKey::id] if not given
+const DEFAULT_MASTER_KEY_ID: i32 = 1;
+
+/// Default for [RefreshConfig::window] in seconds
+const DEFAULT_REFRESH_WINDOW: u64 = 300;
+
+/// Default for [RefreshConfig::concurrency]
+const DEFAULT_REFRESH_CONCURRENCY: usize = 4;
+
+/// Default port for [TlsConfig::redirect] addresses without their own
+const DEFAULT_REDIRECT_PORT: u16 = 80;
+
+/// Minimum length of [Config::pepper] before it's warned about as being weak
+const MIN_PEPPER_LEN: usize = 16;
+
+/// Parameters of [Config::db_url] which enable encryption
+const DB_TLS_PARAMS: [&str; 4] = [
+    "ssl=true",
+    "sslmode=require",
+    "sslmode=verify-ca",
+    "sslmode=verify-full",
+];
+
+/// Default for [Config::db_max_connections]
+const DEFAULT_DB_MAX_CONNECTIONS: u32 = 5;
+
+/// Default for [HashConfig::memory] in KiB, following the OWASP minimum for argon2id
+const DEFAULT_HASH_MEMORY: u32 = 19456;
+
+/// Default for [HashConfig::iterations]
+const DEFAULT_HASH_ITERATIONS: u32 = 2;
+
+/// Default for [HashConfig::lanes]
+const DEFAULT_HASH_LANES: u32 = 1;
+
+/// Maximum for [HashConfig::lanes] allowed by argon2
+const MAX_HASH_LANES: u32 = 0xFFFFFF;
+
+/// Default for [HashConfig::queue_timeout] in seconds
+const DEFAULT_HASH_QUEUE_TIMEOUT: u64 = 5;
 
 /// Error whilst parsing a new [Config] structure
 #[derive(Debug, PartialEq)]
 pub enum ConfigError {
-    /// [Config::host] missing
+    /// [Config::bind] missing
     NoHost,
-    /// [Config::host] invalidly inputted and could not be parsed
+    /// [Config::bind] invalidly inputted and could not be parsed
     InvalidHost,
-    /// [Config::port] missing
+    /// [BindAddr::port] missing for an address without it's own port
     NoPort,
-    /// [Config::port] invalidly inputted and could not be parsed
+    /// [BindAddr::port] invalidly inputted and could not be parsed
     InvalidPort,
+    /// [Config::public_url] invalidly inputted, it should be a http or https url
+    InvalidPublicUrl,
+    /// [Config::tls] settings invalidly inputted, needing both a certificate and key
+    InvalidTls,
     /// [Config::pepper] missing
     NoPepper,
+    /// [Config::pepper] couldn't be decoded from the `PEPPER_ENCODING` given
+    InvalidPepper,
     /// [Config::db_url] missing
     NoDbUrl,
+    /// [Config::db_max_connections] invalidly inputted and could not be parsed
+    InvalidDbMaxConnections,
+    /// [Config::refresh] settings invalidly inputted and could not be parsed
+    InvalidRefresh,
+    /// [Config::hash] parameters invalidly inputted or unusable by argon2
+    InvalidHash,
+    /// [Config::notifier] unknown or missing it's path
+    InvalidNotifier,
+    /// [Config::master_key] missing
+    NoMasterKey,
+    /// [Config::master_key] invalidly inputted, it should be base64 encoded 32 bytes
+    InvalidMasterKey,
+    /// [Config::log_requests] invalidly inputted, it should be true or false
+    InvalidLog,
+    /// Config file couldn't be read or parsed, with the reason why
+    InvalidFile(String),
+    /// Secret couldn't be read from it's [SecretSource], with the reason why
+    InvalidSecret(String),
 }
 
 impl fmt::Display for ConfigError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidFile(reason) => {
+                return write!(f, "The config file given is invalid, {}", reason)
+            }
+            ConfigError::InvalidSecret(reason) => {
+                return write!(f, "A secret couldn't be read, {}", reason)
+            }
+            _ => (),
+        }
+
         write!(
             f,
             "{}",
             match self {
-                ConfigError::NoHost => "No host address found within environment variables",
+                ConfigError::NoHost => "No host address found within environment variables or config file",
                 ConfigError::InvalidHost => "The host address given is invalid",
-                ConfigError::NoPort => "No port number found within environment variables",
+                ConfigError::NoPort => "No port number found within environment variables or config file",
                 ConfigError::InvalidPort => "The port number given is invalid",
-                ConfigError::NoPepper => "No application pepper found within environment variables",
-                ConfigError::NoDbUrl => "No database url found within environment variables",
+                ConfigError::InvalidPublicUrl => "The public url given is invalid, it should start with http:// or https://",
+                ConfigError::InvalidTls => "The tls settings given are invalid, both a certificate and key path are needed",
+                ConfigError::NoPepper => "No application pepper found within environment variables or config file",
+                ConfigError::NoDbUrl => "No database url found within environment variables or config file",
+                ConfigError::InvalidDbMaxConnections => "The database pool size given is invalid",
+                ConfigError::InvalidRefresh => "The token refresh settings given are invalid",
+                ConfigError::InvalidNotifier => "The notifier given is invalid, it should be log or file with a path",
+                ConfigError::InvalidHash => "The password hashing settings given are invalid, the variant should be argon2i, argon2d or argon2id and memory at least 8 KiB per lane",
+                ConfigError::NoMasterKey => "No master encryption key found within environment variables or config file",
+                ConfigError::InvalidMasterKey => "The master encryption key given is invalid, it should be 32 base64 encoded bytes",
+                ConfigError::InvalidLog => "The logging settings given are invalid, requests should be true or false",
+                ConfigError::InvalidPepper => "The application pepper given is invalid for it's encoding, which should be raw, base64 or hex",
+                ConfigError::InvalidFile(_) | ConfigError::InvalidSecret(_) => unreachable!(),
             }
         )
     }
 }
 
+/// Weak but usable setting found whilst creating a [Config], see [Config::warnings]
+#[derive(Debug, PartialEq)]
+pub enum ConfigWarning {
+    /// [Config::pepper] is shorter than recommended, with it's length
+    ShortPepper(usize),
+    /// [Config::db_url] is a postgres url which doesn't enable encryption
+    UnencryptedDb,
+    /// A [Config::bind] address listens on all interfaces without [Config::tls]
+    PublicWithoutTls(BindAddr),
+}
+
+impl fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigWarning::ShortPepper(len) => write!(
+                f,
+                "The application pepper is only {} bytes, at least {} random bytes are recommended",
+                len, MIN_PEPPER_LEN
+            ),
+            ConfigWarning::UnencryptedDb => write!(
+                f,
+                "The database connection is unencrypted, add sslmode=require to the database url"
+            ),
+            ConfigWarning::PublicWithoutTls(addr) => write!(
+                f,
+                "Binding to {} listens on all interfaces over plain http, set a tls certificate and key or bind to a private address",
+                addr
+            ),
+        }
+    }
+}
+
 /// Contains basic configuration information for startup
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Config {
-    /// Host address to show server on
-    pub host: [u8; 4],
-    /// Port to show server on
-    pub port: u16,
-    /// Cryptographic pepper to embed
-    pub pepper: Vec<u8>,
-    /// Database url
+    /// Addresses to bind the server to, with at least one always being present
+    pub bind: Vec<BindAddr>,
+    /// Url the server is reachable at for clients, if different to [Config::bind]
+    pub public_url: Option<String>,
+    /// Serving over https instead of http, disabled if [None]
+    pub tls: Option<TlsConfig>,
+    /// Cryptographic pepper to embed, shared with hashing jobs and wiped from
+    /// memory once dropped
+    pub pepper: Arc<Zeroizing<Vec<u8>>>,
+    /// Argon2 parameters for new password hashes
+    pub hash: HashConfig,
+    /// Database url, being postgres unless prefixed with `sqlite:` or set to `memory`
     pub db_url: String,
+    /// Maximum amount of pooled database connections
+    pub db_max_connections: u32,
+    /// Background refreshing of expiring user tokens, disabled if [None]
+    pub refresh: Option<RefreshConfig>,
+    /// Delivery of org password reset tokens
+    pub notifier: NotifierConfig,
+    /// Master key for encrypting secrets at rest
+    pub master_key: MasterKey,
+    /// Retired master keys which are only used for decryption whilst rotating
+    pub old_master_keys: Vec<MasterKey>,
+    /// Printing a line for every request served
+    pub log_requests: bool,
+}
+
+/// Single address to bind the server to, resolved when binding if it's a
+/// hostname
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BindAddr {
+    /// Ip address or hostname, without brackets for ipv6 addresses
+    pub host: String,
+    /// Port to bind to
+    pub port: u16,
+}
+
+impl BindAddr {
+    /// Gets the host to show within urls, bracketing ipv6 addresses and using
+    /// `localhost` instead of wildcard addresses
+    fn url_host(&self) -> String {
+        match self.host.parse::<IpAddr>() {
+            Ok(ip) if ip.is_unspecified() => "localhost".to_string(),
+            Ok(IpAddr::V6(ip)) => format!("[{}]", ip),
+            _ => self.host.clone(),
+        }
+    }
+}
+
+impl fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.host.parse::<Ipv6Addr>() {
+            Ok(ip) => write!(f, "[{}]:{}", ip, self.port),
+            Err(_) => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// Settings for serving over https, with certificates being reloaded from disk
+/// whilst running
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TlsConfig {
+    /// Path to the pem encoded certificate chain, leaf first
+    pub cert: PathBuf,
+    /// Path to the pem encoded private key
+    pub key: PathBuf,
+    /// Plain http addresses which redirect to https, with none if empty
+    pub redirect: Vec<BindAddr>,
+}
+
+/// Argon2 parameters for password hashing, with hashes made using weaker ones
+/// being upgraded on the next successful login
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct HashConfig {
+    /// Argon2 variant to use
+    pub variant: argon2::Variant,
+    /// Memory cost in KiB
+    pub memory: u32,
+    /// Time cost as the amount of passes over memory
+    pub iterations: u32,
+    /// Degree of parallelism
+    pub lanes: u32,
+    /// Maximum amount of hashes to run at once, defaulting to the cpu count
+    pub concurrency: usize,
+    /// Seconds a hash may wait for a free slot before the request is refused
+    pub queue_timeout: u64,
+}
+
+impl HashConfig {
+    /// Checks if these parameters are weaker than `policy` or use a different
+    /// variant, meaning hashes made with them should be upgraded
+    pub fn is_weaker_than(&self, policy: &HashConfig) -> bool {
+        self.variant != policy.variant
+            || self.memory < policy.memory
+            || self.iterations < policy.iterations
+            || self.lanes < policy.lanes
+    }
+}
+
+impl Default for HashConfig {
+    fn default() -> Self {
+        Self {
+            variant: argon2::Variant::Argon2id,
+            memory: DEFAULT_HASH_MEMORY,
+            iterations: DEFAULT_HASH_ITERATIONS,
+            lanes: DEFAULT_HASH_LANES,
+            concurrency: std::thread::available_parallelism().map_or(1, |val| val.get()),
+            queue_timeout: DEFAULT_HASH_QUEUE_TIMEOUT,
+        }
+    }
+}
+
+/// Where messages about orgs such as password reset tokens are delivered,
+/// see [Notifier](crate::notifier::Notifier)
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub enum NotifierConfig {
+    /// Printed to the server's output, only suitable for local testing
+    #[default]
+    Log,
+    /// Appended as json lines to the file at this path
+    File(PathBuf),
+}
+
+/// Settings for proactively refreshing expiring user tokens in the background
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RefreshConfig {
+    /// Seconds between each sweep for expiring tokens
+    pub interval: u64,
+    /// Seconds before expiry that a token is considered to be expiring
+    pub window: u64,
+    /// Maximum amount of refreshes to run at once
+    pub concurrency: usize,
 }
 
 impl Config {
-    /// Creates a new [Config] from [mod@std::env] variables found
-    pub fn new() -> Result<Self, ConfigError> {
-        Ok(Self {
-            host: parse_host(env::var("HOST").map_err(|_| ConfigError::NoHost)?)?,
-            port: std::env::var("PORT")
-                .map_err(|_| ConfigError::NoPort)?
-                .parse()
-                .map_err(|_| ConfigError::InvalidPort)?,
-            pepper: std::env::var("PEPPER")
-                .map_err(|_| ConfigError::NoPepper)?
-                .as_bytes()
-                .into(),
-            db_url: std::env::var("DB_URL").map_err(|_| ConfigError::NoDbUrl)?,
+    /// Creates a new [Config] from [mod@std::env] variables found, layered over
+    /// the config file at `path` or otherwise the `AUTHRIO_CONFIG` variable,
+    /// giving every problem found if it's invalid
+    pub fn new(path: Option<&str>) -> Result<Self, Vec<ConfigError>> {
+        let path = path
+            .map(String::from)
+            .or_else(|| env::var(CONFIG_PATH_VAR).ok());
+        let file = match path {
+            Some(path) => FileConfig::load(path).map_err(|err| vec![err])?.into_vars(),
+            None => HashMap::new(),
+        };
+        let env = env::vars_os()
+            .filter_map(|(name, val)| Some((name.into_string().ok()?, val.into_string().ok()?)))
+            .collect();
+
+        Self::from_vars(&Vars::new(vec![env, file]))
+    }
+
+    /// Creates a new [Config] from already layered variables, collecting every
+    /// problem found instead of stopping at the first
+    fn from_vars(vars: &Vars) -> Result<Self, Vec<ConfigError>> {
+        let mut errors = Errors(vec![]);
+
+        let port = errors.take(match vars.get("PORT") {
+            Some(val) => val
+                .parse::<u16>()
+                .map(Some)
+                .map_err(|_| ConfigError::InvalidPort),
+            None => Ok(None),
+        });
+        let config = Self {
+            bind: errors.take(
+                vars.get("HOST")
+                    .ok_or(ConfigError::NoHost)
+                    .and_then(|host| parse_bind(host, port)),
+            ),
+            public_url: errors.take(match vars.get("PUBLIC_URL") {
+                Some(val) => parse_public_url(val).map(Some),
+                None => Ok(None),
+            }),
+            tls: errors.take(parse_tls(vars)),
+            pepper: errors.take(vars.secret("PEPPER").and_then(|pepper| {
+                parse_pepper(
+                    &pepper.ok_or(ConfigError::NoPepper)?,
+                    vars.get("PEPPER_ENCODING"),
+                )
+                .map(Arc::new)
+            })),
+            hash: errors.take(parse_hash(vars)),
+            db_url: errors.take(
+                vars.secret("DB_URL")
+                    .and_then(|url| url.map(|url| url.to_string()).ok_or(ConfigError::NoDbUrl)),
+            ),
+            db_max_connections: errors.take(match vars.get("DB_MAX_CONNECTIONS") {
+                Some(val) => parse_nonzero(val, ConfigError::InvalidDbMaxConnections),
+                None => Ok(DEFAULT_DB_MAX_CONNECTIONS),
+            }),
+            refresh: errors.take(parse_refresh(vars)),
+            notifier: errors.take(parse_notifier(vars)),
+            master_key: MasterKey {
+                id: errors.take(match vars.get("MASTER_KEY_ID") {
+                    Some(val) => val.parse().map_err(|_| ConfigError::InvalidMasterKey),
+                    None => Ok(DEFAULT_MASTER_KEY_ID),
+                }),
+                key: errors.take(
+                    vars.secret("MASTER_KEY")
+                        .and_then(|key| parse_key(key.ok_or(ConfigError::NoMasterKey)?)),
+                ),
+            },
+            old_master_keys: errors.take(vars.secret("OLD_MASTER_KEYS").and_then(
+                |keys| match keys {
+                    Some(val) => parse_old_keys(val),
+                    None => Ok(vec![]),
+                },
+            )),
+            log_requests: errors.take(match vars.get("LOG_REQUESTS") {
+                Some(val) => val.parse().map_err(|_| ConfigError::InvalidLog),
+                None => Ok(false),
+            }),
+        };
+        if errors.0.is_empty() {
+            errors.take(config.check_key_ids());
+        }
+
+        match errors.0.is_empty() {
+            true => Ok(config),
+            false => Err(errors.0),
+        }
+    }
+
+    /// Gets all weak settings which should be changed but don't stop startup
+    pub fn warnings(&self) -> Vec<ConfigWarning> {
+        let mut warnings = vec![];
+
+        if self.pepper.len() < MIN_PEPPER_LEN {
+            warnings.push(ConfigWarning::ShortPepper(self.pepper.len()));
+        }
+        if self.db_url.starts_with("postgres") && !self.db_is_encrypted() {
+            warnings.push(ConfigWarning::UnencryptedDb);
+        }
+        if self.tls.is_none() {
+            warnings.extend(
+                self.bind
+                    .iter()
+                    .filter(|addr| {
+                        addr.host
+                            .parse::<IpAddr>()
+                            .is_ok_and(|ip| ip.is_unspecified())
+                    })
+                    .map(|addr| ConfigWarning::PublicWithoutTls(addr.clone())),
+            );
+        }
+
+        warnings
+    }
+
+    /// Checks if [Config::db_url] enables encryption for it's connection
+    pub fn db_is_encrypted(&self) -> bool {
+        let params = match self.db_url.split_once('?') {
+            Some((_, params)) => params,
+            None => return false,
+        };
+
+        params
+            .split('&')
+            .any(|param| DB_TLS_PARAMS.contains(&param))
+    }
+
+    /// Gets the [MasterKey] with the given [MasterKey::id] for decryption
+    pub fn master_key_by_id(&self, id: i32) -> Option<&MasterKey> {
+        std::iter::once(&self.master_key)
+            .chain(self.old_master_keys.iter())
+            .find(|master| master.id == id)
+    }
+
+    /// Ensures every [MasterKey::id] is unique so rows can't be ambiguous
+    fn check_key_ids(&self) -> Result<(), ConfigError> {
+        let mut ids: Vec<i32> = self
+            .old_master_keys
+            .iter()
+            .map(|master| master.id)
+            .collect();
+        ids.push(self.master_key.id);
+        ids.sort_unstable();
+
+        if ids.windows(2).any(|pair| pair[0] == pair[1]) {
+            Err(ConfigError::InvalidMasterKey)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Gets the url clients should use to reach the server, being
+    /// [Config::public_url] or otherwise derived from the first [Config::bind]
+    pub fn advertised_url(&self) -> String {
+        if let Some(url) = &self.public_url {
+            return url.clone();
+        }
+
+        let bind = &self.bind[0];
+        match (self.tls.is_some(), bind.port) {
+            (false, 80) => format!("http://{}", bind.url_host()),
+            (false, port) => format!("http://{}:{}", bind.url_host(), port),
+            (true, 443) => format!("https://{}", bind.url_host()),
+            (true, port) => format!("https://{}:{}", bind.url_host(), port),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// Creates a [Config] with placeholder values for tests
+    pub fn test() -> Self {
+        Self {
+            bind: vec![BindAddr {
+                host: "127.0.0.1".to_string(),
+                port: 8080,
+            }],
+            public_url: None,
+            tls: None,
+            pepper: Arc::new(Zeroizing::new(b"pepper".to_vec())),
+            // cheapest parameters so tests stay fast
+            hash: HashConfig {
+                variant: argon2::Variant::Argon2id,
+                memory: 8,
+                iterations: 1,
+                lanes: 1,
+                concurrency: 2,
+                queue_timeout: DEFAULT_HASH_QUEUE_TIMEOUT,
+            },
+            db_url: String::new(),
+            db_max_connections: DEFAULT_DB_MAX_CONNECTIONS,
+            refresh: None,
+            notifier: NotifierConfig::Log,
+            master_key: MasterKey {
+                id: 1,
+                key: [0; KEY_LENGTH],
+            },
+            old_master_keys: vec![],
+            log_requests: false,
+        }
+    }
+}
+
+/// Problems found whilst creating a [Config]
+struct Errors(Vec<ConfigError>);
+
+impl Errors {
+    /// Takes the value of a result, recording it's error and using a placeholder
+    /// value instead if it failed
+    fn take<T: Default>(&mut self, result: Result<T, ConfigError>) -> T {
+        result.unwrap_or_else(|err| {
+            self.0.push(err);
+            T::default()
         })
     }
+}
+
+/// Configuration variables layered from most to least important, so that
+/// environment variables override the config file
+struct Vars {
+    layers: Vec<HashMap<String, String>>,
+    sources: Vec<Box<dyn SecretSource>>,
+}
+
+impl Vars {
+    /// Creates new [Vars] from layers, reading secrets from all available
+    /// [SecretSource]s
+    fn new(layers: Vec<HashMap<String, String>>) -> Self {
+        Self {
+            layers,
+            sources: secret::sources(),
+        }
+    }
 
-    /// Converts into hostname from [Config::host] and [Config::port] options
-    pub fn hostname(&self) -> String {
-        let host = self
-            .host
+    /// Gets a variable from the most important layer which has it
+    fn get(&self, name: &str) -> Option<String> {
+        self.layers
             .iter()
-            .map(|x| x.to_string())
-            .collect::<Vec<String>>()
-            .join(".");
+            .find_map(|layer| layer.get(name).cloned())
+    }
+
+    /// Gets a secret variable from the most important layer which has it, either
+    /// directly or as a reference read from a [SecretSource], e.g. `PEPPER_FILE`
+    fn secret(&self, name: &str) -> Result<Option<Zeroizing<String>>, ConfigError> {
+        for layer in self.layers.iter() {
+            if let Some(val) = layer.get(name) {
+                return Ok(Some(Zeroizing::new(val.clone())));
+            }
 
-        match self.port {
-            80 => host,
-            _ => format!("{}:{}", host, self.port),
+            for source in self.sources.iter() {
+                let ref_name = format!("{}{}", name, source.suffix());
+                if let Some(reference) = layer.get(&ref_name) {
+                    return source.read(reference).map(Some).map_err(|reason| {
+                        ConfigError::InvalidSecret(format!(
+                            "{} {}, {}",
+                            ref_name, reference, reason
+                        ))
+                    });
+                }
+            }
         }
+
+        Ok(None)
     }
 }
 
-/// Parses `i.i.i.i` into a valid [Config::host] element
-fn parse_host(input: impl AsRef<str>) -> Result<[u8; HOST_PART_NUM], ConfigError> {
-    let mut host = [0; HOST_PART_NUM];
-    let splitted: Vec<&str> = input.as_ref().split('.').collect();
+/// Optional TOML or YAML config file, with each value being overridable by the
+/// environment variable it maps to
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    host: Option<String>,
+    port: Option<u16>,
+    public_url: Option<String>,
+    pepper: Option<String>,
+    pepper_file: Option<String>,
+    pepper_encoding: Option<String>,
+    db: FileDb,
+    hash: FileHash,
+    refresh: FileRefresh,
+    notifier: FileNotifier,
+    master_key: FileMasterKey,
+    tls: FileTls,
+    log: FileLog,
+}
+
+/// The `db` section of a [FileConfig]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileDb {
+    url: Option<String>,
+    url_file: Option<String>,
+    max_connections: Option<u32>,
+}
+
+/// The `hash` section of a [FileConfig]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileHash {
+    variant: Option<String>,
+    memory: Option<u32>,
+    iterations: Option<u32>,
+    lanes: Option<u32>,
+    concurrency: Option<usize>,
+    queue_timeout: Option<u64>,
+}
+
+/// The `refresh` section of a [FileConfig], enabling refreshing if `interval`
+/// is given
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileRefresh {
+    interval: Option<u64>,
+    window: Option<u64>,
+    concurrency: Option<usize>,
+}
 
-    if splitted.len() != HOST_PART_NUM {
-        return Err(ConfigError::InvalidHost);
+/// The `notifier` section of a [FileConfig]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileNotifier {
+    kind: Option<String>,
+    path: Option<String>,
+}
+
+/// The `master_key` section of a [FileConfig], with `old` keys being `id:key`
+/// pairs
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileMasterKey {
+    id: Option<i32>,
+    key: Option<String>,
+    key_file: Option<String>,
+    old: Vec<String>,
+}
+
+/// The `tls` section of a [FileConfig], with `redirect` being comma-separated
+/// addresses like `host`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileTls {
+    cert: Option<String>,
+    key: Option<String>,
+    redirect: Option<String>,
+}
+
+/// The `log` section of a [FileConfig]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileLog {
+    requests: Option<bool>,
+}
+
+impl FileConfig {
+    /// Reads a [FileConfig], with the format chosen by it's extension
+    fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let err =
+            |reason: String| ConfigError::InvalidFile(format!("{}: {}", path.display(), reason));
+        let input = fs::read_to_string(path).map_err(|io_err| err(io_err.to_string()))?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&input).map_err(|de_err| err(de_err.to_string())),
+            Some("yaml") | Some("yml") => {
+                serde_yaml::from_str(&input).map_err(|de_err| err(de_err.to_string()))
+            }
+            _ => Err(err("expected a .toml, .yaml or .yml file".to_string())),
+        }
     }
 
-    for (ind, part) in splitted.into_iter().enumerate() {
-        host[ind] = part.parse().map_err(|_| ConfigError::InvalidHost)?;
+    /// Flattens into the environment variables each value maps to
+    fn into_vars(self) -> HashMap<String, String> {
+        let mut vars = HashMap::new();
+        let mut set = |name: &str, val: Option<String>| {
+            if let Some(val) = val {
+                vars.insert(name.to_string(), val);
+            }
+        };
+
+        set("HOST", self.host);
+        set("PORT", self.port.map(|val| val.to_string()));
+        set("PUBLIC_URL", self.public_url);
+        set("PEPPER", self.pepper);
+        set("PEPPER_FILE", self.pepper_file);
+        set("PEPPER_ENCODING", self.pepper_encoding);
+        set("DB_URL", self.db.url);
+        set("DB_URL_FILE", self.db.url_file);
+        set(
+            "DB_MAX_CONNECTIONS",
+            self.db.max_connections.map(|val| val.to_string()),
+        );
+        set("HASH_VARIANT", self.hash.variant);
+        set("HASH_MEMORY", self.hash.memory.map(|val| val.to_string()));
+        set(
+            "HASH_ITERATIONS",
+            self.hash.iterations.map(|val| val.to_string()),
+        );
+        set("HASH_LANES", self.hash.lanes.map(|val| val.to_string()));
+        set(
+            "HASH_CONCURRENCY",
+            self.hash.concurrency.map(|val| val.to_string()),
+        );
+        set(
+            "HASH_QUEUE_TIMEOUT",
+            self.hash.queue_timeout.map(|val| val.to_string()),
+        );
+        set(
+            "REFRESH_INTERVAL",
+            self.refresh.interval.map(|val| val.to_string()),
+        );
+        set(
+            "REFRESH_WINDOW",
+            self.refresh.window.map(|val| val.to_string()),
+        );
+        set(
+            "REFRESH_CONCURRENCY",
+            self.refresh.concurrency.map(|val| val.to_string()),
+        );
+        set("NOTIFIER", self.notifier.kind);
+        set("NOTIFIER_PATH", self.notifier.path);
+        set(
+            "MASTER_KEY_ID",
+            self.master_key.id.map(|val| val.to_string()),
+        );
+        set("MASTER_KEY", self.master_key.key);
+        set("MASTER_KEY_FILE", self.master_key.key_file);
+        set("TLS_CERT", self.tls.cert);
+        set("TLS_KEY", self.tls.key);
+        set("TLS_REDIRECT", self.tls.redirect);
+        set("LOG_REQUESTS", self.log.requests.map(|val| val.to_string()));
+        if !self.master_key.old.is_empty() {
+            set("OLD_MASTER_KEYS", Some(self.master_key.old.join(",")));
+        }
+
+        vars
     }
+}
+
+/// Parses comma-separated [Config::bind] addresses, each using the `port`
+/// given unless it has it's own
+fn parse_bind(input: impl AsRef<str>, port: Option<u16>) -> Result<Vec<BindAddr>, ConfigError> {
+    let bind = input
+        .as_ref()
+        .split(',')
+        .filter(|addr| !addr.trim().is_empty())
+        .map(|addr| parse_host(addr.trim(), port))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    Ok(host)
+    if bind.is_empty() {
+        Err(ConfigError::NoHost)
+    } else {
+        Ok(bind)
+    }
+}
+
+/// Parses a single [BindAddr] from an ipv4 address, an ipv6 address which must
+/// be bracketed if given a port, or a hostname, all optionally with a port
+fn parse_host(input: &str, port: Option<u16>) -> Result<BindAddr, ConfigError> {
+    let with_port = |host: String, own: Option<&str>| match own {
+        Some(val) => Ok(BindAddr {
+            host,
+            port: val.parse().map_err(|_| ConfigError::InvalidPort)?,
+        }),
+        None => Ok(BindAddr {
+            host,
+            port: port.ok_or(ConfigError::NoPort)?,
+        }),
+    };
+
+    if let Ok(addr) = input.parse::<SocketAddr>() {
+        return with_port(addr.ip().to_string(), Some(&addr.port().to_string()));
+    } else if let Ok(ip) = input.parse::<IpAddr>() {
+        return with_port(ip.to_string(), None);
+    } else if let Some(ip) = input
+        .strip_prefix('[')
+        .and_then(|val| val.strip_suffix(']'))
+    {
+        let ip: Ipv6Addr = ip.parse().map_err(|_| ConfigError::InvalidHost)?;
+        return with_port(ip.to_string(), None);
+    }
+
+    let (host, own) = match input.split_once(':') {
+        Some((host, own)) => (host, Some(own)),
+        None => (input, None),
+    };
+
+    if is_hostname(host) {
+        with_port(host.to_ascii_lowercase(), own)
+    } else {
+        Err(ConfigError::InvalidHost)
+    }
+}
+
+/// Checks if a hostname is valid, rejecting numeric ones which are malformed
+/// ipv4 addresses rather than names
+fn is_hostname(host: &str) -> bool {
+    let labels: Vec<&str> = host.trim_end_matches('.').split('.').collect();
+    let valid_label = |label: &&str| {
+        !label.is_empty()
+            && label.len() <= MAX_LABEL_LEN
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+
+    host.len() <= MAX_HOSTNAME_LEN
+        && labels.iter().all(valid_label)
+        && !labels
+            .last()
+            .is_some_and(|label| label.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Parses [Config::public_url], removing any trailing slash
+fn parse_public_url(input: impl AsRef<str>) -> Result<String, ConfigError> {
+    let url = input.as_ref().trim().trim_end_matches('/');
+
+    match url.split_once("://") {
+        Some(("http", rest)) | Some(("https", rest)) if !rest.is_empty() => Ok(url.to_string()),
+        _ => Err(ConfigError::InvalidPublicUrl),
+    }
+}
+
+/// Parses [Config::pepper] from the `encoding` given, defaulting to the raw
+/// bytes of the input
+fn parse_pepper(input: &str, encoding: Option<String>) -> Result<Zeroizing<Vec<u8>>, ConfigError> {
+    match encoding.as_deref() {
+        None | Some("raw") => Ok(Zeroizing::new(input.as_bytes().to_vec())),
+        Some("base64") => base64::decode(input.trim())
+            .map(Zeroizing::new)
+            .map_err(|_| ConfigError::InvalidPepper),
+        Some("hex") => decode_hex(input.trim())
+            .map(Zeroizing::new)
+            .ok_or(ConfigError::InvalidPepper),
+        Some(_) => Err(ConfigError::InvalidPepper),
+    }
+}
+
+/// Decodes a hex string, returning [None] if it isn't valid hex
+fn decode_hex(input: &str) -> Option<Vec<u8>> {
+    if !input.len().is_multiple_of(2) || !input.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..input.len())
+        .step_by(2)
+        .map(|ind| u8::from_str_radix(&input[ind..ind + 2], 16).ok())
+        .collect()
+}
+
+/// Parses a base64 encoded [MasterKey::key]
+fn parse_key(input: impl AsRef<str>) -> Result<[u8; KEY_LENGTH], ConfigError> {
+    base64::decode(input.as_ref())
+        .map_err(|_| ConfigError::InvalidMasterKey)?
+        .try_into()
+        .map_err(|_| ConfigError::InvalidMasterKey)
+}
+
+/// Parses [Config::old_master_keys] from comma-separated `id:key` pairs, with
+/// each key being base64 encoded
+fn parse_old_keys(input: impl AsRef<str>) -> Result<Vec<MasterKey>, ConfigError> {
+    input
+        .as_ref()
+        .split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| match pair.trim().split_once(':') {
+            Some((id, key)) => Ok(MasterKey {
+                id: id.parse().map_err(|_| ConfigError::InvalidMasterKey)?,
+                key: parse_key(key)?,
+            }),
+            None => Err(ConfigError::InvalidMasterKey),
+        })
+        .collect()
+}
+
+/// Parses [Config::tls] settings, enabled by the `TLS_CERT` and `TLS_KEY`
+/// variables which must be given together
+fn parse_tls(vars: &Vars) -> Result<Option<TlsConfig>, ConfigError> {
+    match (vars.get("TLS_CERT"), vars.get("TLS_KEY")) {
+        (Some(cert), Some(key)) => Ok(Some(TlsConfig {
+            cert: cert.into(),
+            key: key.into(),
+            redirect: match vars.get("TLS_REDIRECT") {
+                Some(val) => parse_bind(val, Some(DEFAULT_REDIRECT_PORT))?,
+                None => vec![],
+            },
+        })),
+        (None, None) if vars.get("TLS_REDIRECT").is_none() => Ok(None),
+        _ => Err(ConfigError::InvalidTls),
+    }
+}
+
+/// Parses [Config::hash] parameters, each defaulting to [HashConfig::default]
+fn parse_hash(vars: &Vars) -> Result<HashConfig, ConfigError> {
+    let default = HashConfig::default();
+    fn number<T: FromStr + Default + PartialEq>(
+        vars: &Vars,
+        name: &str,
+        default: T,
+    ) -> Result<T, ConfigError> {
+        match vars.get(name) {
+            Some(val) => parse_nonzero(val, ConfigError::InvalidHash),
+            None => Ok(default),
+        }
+    }
+
+    let hash = HashConfig {
+        variant: match vars.get("HASH_VARIANT") {
+            Some(val) => argon2::Variant::from_str(&val.to_ascii_lowercase())
+                .map_err(|_| ConfigError::InvalidHash)?,
+            None => default.variant,
+        },
+        memory: number(vars, "HASH_MEMORY", default.memory)?,
+        iterations: number(vars, "HASH_ITERATIONS", default.iterations)?,
+        lanes: number(vars, "HASH_LANES", default.lanes)?,
+        concurrency: number(vars, "HASH_CONCURRENCY", default.concurrency)?,
+        queue_timeout: number(vars, "HASH_QUEUE_TIMEOUT", default.queue_timeout)?,
+    };
+
+    if hash.lanes > MAX_HASH_LANES || hash.memory / 8 < hash.lanes {
+        Err(ConfigError::InvalidHash)
+    } else {
+        Ok(hash)
+    }
+}
+
+/// Parses [Config::refresh] settings, enabled by the `REFRESH_INTERVAL` variable
+fn parse_refresh(vars: &Vars) -> Result<Option<RefreshConfig>, ConfigError> {
+    let interval = match vars.get("REFRESH_INTERVAL") {
+        Some(val) => parse_nonzero(val, ConfigError::InvalidRefresh)?,
+        None => return Ok(None),
+    };
+
+    Ok(Some(RefreshConfig {
+        interval,
+        window: match vars.get("REFRESH_WINDOW") {
+            Some(val) => val.parse().map_err(|_| ConfigError::InvalidRefresh)?,
+            None => DEFAULT_REFRESH_WINDOW,
+        },
+        concurrency: match vars.get("REFRESH_CONCURRENCY") {
+            Some(val) => parse_nonzero(val, ConfigError::InvalidRefresh)?,
+            None => DEFAULT_REFRESH_CONCURRENCY,
+        },
+    }))
+}
+
+/// Parses [Config::notifier], defaulting to [NotifierConfig::Log]
+fn parse_notifier(vars: &Vars) -> Result<NotifierConfig, ConfigError> {
+    match vars.get("NOTIFIER").as_deref() {
+        None | Some("log") => Ok(NotifierConfig::Log),
+        Some("file") => vars
+            .get("NOTIFIER_PATH")
+            .map(|path| NotifierConfig::File(PathBuf::from(path)))
+            .ok_or(ConfigError::InvalidNotifier),
+        Some(_) => Err(ConfigError::InvalidNotifier),
+    }
+}
+
+/// Parses a number which must be above zero, giving `err` otherwise
+fn parse_nonzero<T: FromStr + Default + PartialEq>(
+    input: String,
+    err: ConfigError,
+) -> Result<T, ConfigError> {
+    match input.parse() {
+        Ok(val) if val != T::default() => Ok(val),
+        _ => Err(err),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Shorthand for creating a [BindAddr]
+    fn addr(host: &str, port: u16) -> BindAddr {
+        BindAddr {
+            host: host.to_string(),
+            port,
+        }
+    }
+
     #[test]
     fn host_parsing() {
-        assert_eq!(parse_host("127.0.0.1"), Ok([127, 0, 0, 1]));
-        assert_eq!(parse_host("0.0.0.0"), Ok([0, 0, 0, 0]));
-        assert_eq!(parse_host(""), Err(ConfigError::InvalidHost));
-        assert_eq!(parse_host("0.0.0"), Err(ConfigError::InvalidHost));
-        assert_eq!(parse_host("999.999.999.999"), Err(ConfigError::InvalidHost));
+        assert_eq!(parse_host("127.0.0.1", Some(80)), Ok(addr("127.0.0.1", 80)));
+        assert_eq!(parse_host("0.0.0.0", Some(80)), Ok(addr("0.0.0.0", 80)));
+        assert_eq!(parse_host("::", Some(80)), Ok(addr("::", 80)));
+        assert_eq!(parse_host("[::1]", Some(80)), Ok(addr("::1", 80)));
+        assert_eq!(parse_host("[::1]:8080", None), Ok(addr("::1", 8080)));
+        assert_eq!(
+            parse_host("10.0.0.1:8080", Some(80)),
+            Ok(addr("10.0.0.1", 8080))
+        );
+        assert_eq!(
+            parse_host("Auth.example.com", Some(80)),
+            Ok(addr("auth.example.com", 80))
+        );
+        assert_eq!(
+            parse_host("localhost:8080", None),
+            Ok(addr("localhost", 8080))
+        );
+        assert_eq!(parse_host("localhost", None), Err(ConfigError::NoPort));
+        assert_eq!(
+            parse_host("localhost:x", None),
+            Err(ConfigError::InvalidPort)
+        );
+        assert_eq!(parse_host("", Some(80)), Err(ConfigError::InvalidHost));
+        assert_eq!(parse_host("0.0.0", Some(80)), Err(ConfigError::InvalidHost));
+        assert_eq!(
+            parse_host("999.999.999.999", Some(80)),
+            Err(ConfigError::InvalidHost)
+        );
+        assert_eq!(
+            parse_host("[nope]", Some(80)),
+            Err(ConfigError::InvalidHost)
+        );
+        assert_eq!(
+            parse_host("-bad.com", Some(80)),
+            Err(ConfigError::InvalidHost)
+        );
+
+        assert_eq!(
+            parse_bind("0.0.0.0, [::]:9090", Some(80)),
+            Ok(vec![addr("0.0.0.0", 80), addr("::", 9090)])
+        );
+        assert_eq!(parse_bind(" , ", Some(80)), Err(ConfigError::NoHost));
+    }
+
+    #[test]
+    fn key_parsing() {
+        assert_eq!(
+            parse_key(base64::encode([1; KEY_LENGTH])),
+            Ok([1; KEY_LENGTH])
+        );
+        assert_eq!(
+            parse_key(base64::encode([1; KEY_LENGTH - 1])),
+            Err(ConfigError::InvalidMasterKey)
+        );
+        assert_eq!(parse_key("not base64!"), Err(ConfigError::InvalidMasterKey));
+    }
+
+    #[test]
+    fn old_keys_parsing() {
+        let key = base64::encode([2; KEY_LENGTH]);
+        assert_eq!(
+            parse_old_keys(format!("1:{}, 2:{}", key, key)),
+            Ok(vec![
+                MasterKey {
+                    id: 1,
+                    key: [2; KEY_LENGTH]
+                },
+                MasterKey {
+                    id: 2,
+                    key: [2; KEY_LENGTH]
+                }
+            ])
+        );
+        assert_eq!(parse_old_keys(""), Ok(vec![]));
+        assert_eq!(parse_old_keys(&key), Err(ConfigError::InvalidMasterKey));
+        assert_eq!(
+            parse_old_keys(format!("x:{}", key)),
+            Err(ConfigError::InvalidMasterKey)
+        );
+    }
+
+    #[test]
+    fn nonzero_parsing() {
+        assert_eq!(
+            parse_nonzero::<u64>("30".to_string(), ConfigError::InvalidRefresh),
+            Ok(30)
+        );
+        assert_eq!(
+            parse_nonzero::<u64>("0".to_string(), ConfigError::InvalidRefresh),
+            Err(ConfigError::InvalidRefresh)
+        );
+        assert_eq!(
+            parse_nonzero::<usize>("-1".to_string(), ConfigError::InvalidRefresh),
+            Err(ConfigError::InvalidRefresh)
+        );
+    }
+
+    #[test]
+    fn file_layering() {
+        let file: FileConfig = toml::from_str(&format!(
+            "host = \"0.0.0.0\"\nport = 80\npepper = \"pepper\"\n[db]\nurl = \"memory\"\nmax_connections = 20\n[master_key]\nkey = \"{}\"\n[log]\nrequests = true\n",
+            base64::encode([1; KEY_LENGTH])
+        ))
+        .unwrap();
+        let env = vec![("PORT".to_string(), "8080".to_string())];
+        let config = Config::from_vars(&Vars::new(vec![
+            env.into_iter().collect(),
+            file.into_vars(),
+        ]))
+        .unwrap();
+
+        assert_eq!(config.bind, vec![addr("0.0.0.0", 8080)]);
+        assert_eq!(config.db_max_connections, 20);
+        assert_eq!(config.master_key.key, [1; KEY_LENGTH]);
+        assert_eq!(config.refresh, None);
+        assert!(config.log_requests);
+    }
+
+    #[test]
+    fn tls_parsing() {
+        let vars = |pairs: &[(&str, &str)]| {
+            Vars::new(vec![pairs
+                .iter()
+                .map(|(name, val)| (name.to_string(), val.to_string()))
+                .collect()])
+        };
+
+        assert_eq!(parse_tls(&vars(&[])), Ok(None));
+        assert_eq!(
+            parse_tls(&vars(&[
+                ("TLS_CERT", "cert.pem"),
+                ("TLS_KEY", "key.pem"),
+                ("TLS_REDIRECT", "0.0.0.0"),
+            ])),
+            Ok(Some(TlsConfig {
+                cert: "cert.pem".into(),
+                key: "key.pem".into(),
+                redirect: vec![addr("0.0.0.0", 80)],
+            }))
+        );
+        assert_eq!(
+            parse_tls(&vars(&[("TLS_CERT", "cert.pem")])),
+            Err(ConfigError::InvalidTls)
+        );
+        assert_eq!(
+            parse_tls(&vars(&[("TLS_REDIRECT", "0.0.0.0")])),
+            Err(ConfigError::InvalidTls)
+        );
+    }
+
+    #[test]
+    fn hash_parsing() {
+        let vars = |pairs: &[(&str, &str)]| {
+            Vars::new(vec![pairs
+                .iter()
+                .map(|(name, val)| (name.to_string(), val.to_string()))
+                .collect()])
+        };
+
+        assert_eq!(parse_hash(&vars(&[])), Ok(HashConfig::default()));
+        assert_eq!(
+            parse_hash(&vars(&[
+                ("HASH_VARIANT", "Argon2i"),
+                ("HASH_MEMORY", "65536"),
+                ("HASH_LANES", "4"),
+                ("HASH_CONCURRENCY", "2"),
+            ])),
+            Ok(HashConfig {
+                variant: argon2::Variant::Argon2i,
+                memory: 65536,
+                lanes: 4,
+                concurrency: 2,
+                ..HashConfig::default()
+            })
+        );
+        assert_eq!(
+            parse_hash(&vars(&[("HASH_VARIANT", "bcrypt")])),
+            Err(ConfigError::InvalidHash)
+        );
+        assert_eq!(
+            parse_hash(&vars(&[("HASH_QUEUE_TIMEOUT", "0")])),
+            Err(ConfigError::InvalidHash)
+        );
+        assert_eq!(
+            parse_hash(&vars(&[("HASH_ITERATIONS", "0")])),
+            Err(ConfigError::InvalidHash)
+        );
+        assert_eq!(
+            parse_hash(&vars(&[("HASH_MEMORY", "16"), ("HASH_LANES", "4")])),
+            Err(ConfigError::InvalidHash)
+        );
+    }
+
+    #[test]
+    fn pepper_parsing() {
+        assert_eq!(
+            parse_pepper("pepper", None).as_deref(),
+            Ok(&b"pepper".to_vec())
+        );
+        assert_eq!(
+            parse_pepper("cGVwcGVy\n", Some("base64".to_string())).as_deref(),
+            Ok(&b"pepper".to_vec())
+        );
+        assert_eq!(
+            parse_pepper("70657070ff", Some("hex".to_string())).as_deref(),
+            Ok(&vec![0x70, 0x65, 0x70, 0x70, 0xff])
+        );
+        assert_eq!(
+            parse_pepper("7065f", Some("hex".to_string())),
+            Err(ConfigError::InvalidPepper)
+        );
+        assert_eq!(
+            parse_pepper("+f", Some("hex".to_string())),
+            Err(ConfigError::InvalidPepper)
+        );
+        assert_eq!(
+            parse_pepper("pepper", Some("rot13".to_string())),
+            Err(ConfigError::InvalidPepper)
+        );
+    }
+
+    #[test]
+    fn secret_layering() {
+        let path = env::temp_dir().join(format!("authrio-pepper-{}", std::process::id()));
+        fs::write(&path, "from file\n").unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let layer = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, val)| (name.to_string(), val.to_string()))
+                .collect()
+        };
+        let vars = Vars::new(vec![
+            layer(&[("PEPPER_FILE", &path)]),
+            layer(&[("PEPPER", "from config"), ("DB_URL_FILE", "missing")]),
+        ]);
+
+        assert_eq!(
+            vars.secret("PEPPER")
+                .unwrap()
+                .as_deref()
+                .map(String::as_str),
+            Some("from file")
+        );
+        assert!(matches!(
+            vars.secret("DB_URL"),
+            Err(ConfigError::InvalidSecret(_))
+        ));
+        assert_eq!(vars.secret("MASTER_KEY"), Ok(None));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn collects_errors() {
+        let vars = Vars::new(vec![vec![
+            ("PORT".to_string(), "x".to_string()),
+            ("DB_URL".to_string(), "memory".to_string()),
+            ("LOG_REQUESTS".to_string(), "yes".to_string()),
+        ]
+        .into_iter()
+        .collect()]);
+
+        assert_eq!(
+            Config::from_vars(&vars),
+            Err(vec![
+                ConfigError::InvalidPort,
+                ConfigError::NoHost,
+                ConfigError::NoPepper,
+                ConfigError::NoMasterKey,
+                ConfigError::InvalidLog
+            ])
+        );
+    }
+
+    #[test]
+    fn weak_warnings() {
+        assert_eq!(
+            Config::test().warnings(),
+            vec![ConfigWarning::ShortPepper(6)]
+        );
+
+        let config = Config {
+            bind: vec![addr("0.0.0.0", 80), addr("127.0.0.1", 80)],
+            pepper: Arc::new(Zeroizing::new(vec![0; MIN_PEPPER_LEN])),
+            db_url: "postgres://localhost/authrio?sslmode=disable".to_string(),
+            ..Config::test()
+        };
+        assert_eq!(
+            config.warnings(),
+            vec![
+                ConfigWarning::UnencryptedDb,
+                ConfigWarning::PublicWithoutTls(addr("0.0.0.0", 80))
+            ]
+        );
+
+        let config = Config {
+            pepper: Arc::new(Zeroizing::new(vec![0; MIN_PEPPER_LEN])),
+            db_url: "postgres://localhost/authrio?user=x&sslmode=verify-full".to_string(),
+            ..Config::test()
+        };
+        assert_eq!(config.warnings(), vec![]);
+    }
+
+    #[test]
+    fn file_parsing() {
+        let file: FileConfig = serde_yaml::from_str(
+            "refresh:\n  interval: 30\nmaster_key:\n  old: [\"2:a\", \"3:b\"]\nnotifier:\n  kind: file\n  path: resets.jsonl\n",
+        )
+        .unwrap();
+        let vars = Vars::new(vec![file.into_vars()]);
+        assert_eq!(vars.get("REFRESH_INTERVAL"), Some("30".to_string()));
+        assert_eq!(vars.get("OLD_MASTER_KEYS"), Some("2:a,3:b".to_string()));
+        assert_eq!(vars.get("HOST"), None);
+        assert_eq!(
+            parse_notifier(&vars),
+            Ok(NotifierConfig::File("resets.jsonl".into()))
+        );
+        let file: FileConfig = toml::from_str("[notifier]\nkind = \"file\"\n").unwrap();
+        assert_eq!(
+            parse_notifier(&Vars::new(vec![file.into_vars()])),
+            Err(ConfigError::InvalidNotifier)
+        );
+
+        assert!(toml::from_str::<FileConfig>("unknown = 1").is_err());
     }
 
     #[test]
     fn to_url() {
+        let config = |bind, public_url: Option<&str>| Config {
+            bind: vec![bind],
+            public_url: public_url.map(|url| parse_public_url(url).unwrap()),
+            ..Config::test()
+        };
+
         assert_eq!(
-            Config {
-                host: [127, 0, 0, 1],
-                port: 8080,
-                pepper: vec![],
-                db_url: String::new()
-            }
-            .hostname(),
-            "127.0.0.1:8080".to_string()
+            config(addr("127.0.0.1", 8080), None).advertised_url(),
+            "http://127.0.0.1:8080".to_string()
         );
         assert_eq!(
-            Config {
-                host: [0, 0, 0, 0],
-                port: 0,
-                pepper: vec![],
-                db_url: String::new()
-            }
-            .hostname(),
-            "0.0.0.0:0".to_string()
+            config(addr("0.0.0.0", 0), None).advertised_url(),
+            "http://localhost:0".to_string()
+        );
+        assert_eq!(
+            config(addr("255.255.255.255", 80), None).advertised_url(),
+            "http://255.255.255.255".to_string()
+        );
+        assert_eq!(
+            config(addr("::1", 8080), None).advertised_url(),
+            "http://[::1]:8080".to_string()
+        );
+        assert_eq!(
+            config(addr("::", 80), Some("https://auth.example.com/")).advertised_url(),
+            "https://auth.example.com".to_string()
         );
         assert_eq!(
             Config {
-                host: [255, 255, 255, 255],
-                port: 80,
-                pepper: vec![],
-                db_url: String::new()
+                tls: Some(TlsConfig {
+                    cert: "cert.pem".into(),
+                    key: "key.pem".into(),
+                    redirect: vec![],
+                }),
+                ..config(addr("auth.example.com", 443), None)
             }
-            .hostname(),
-            "255.255.255.255".to_string()
+            .advertised_url(),
+            "https://auth.example.com".to_string()
+        );
+        assert_eq!(addr("::1", 80).to_string(), "[::1]:80".to_string());
+        assert_eq!(
+            parse_public_url("auth.example.com"),
+            Err(ConfigError::InvalidPublicUrl)
         );
     }
 }
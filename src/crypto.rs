@@ -1,8 +1,16 @@
 //! Contains cryptography and random generators for use in password hashing and oauth
 
+use crate::config::HashConfig;
 use crate::Config;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
 use chrono::prelude::*;
+use hmac::{Hmac, Mac};
 use rand::prelude::*;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::{convert::TryInto, fmt};
+use zeroize::Zeroizing;
 
 /// Length of randomly generated salts
 const SALT_LENGTH: usize = 8;
@@ -13,9 +21,33 @@ const TOKEN_LENGTH: usize = 32;
 /// Maximum length for passwords
 const MAX_PASSWORD: usize = 72;
 
-/// Generates a random token
+/// Length of [MasterKey] and [DataKey] encryption keys
+pub const KEY_LENGTH: usize = 32;
+
+/// Length of nonces prepended to all encrypted data
+const NONCE_LENGTH: usize = 24;
+
+/// Prefix of the associated data used when wrapping a [DataKey] with a
+/// [MasterKey], followed by it's [row_aad]
+const WRAP_AAD: &[u8] = b"data_key";
+
+/// Generates a random url-safe token, also suitable as a PKCE `code_verifier`
 pub fn gen_token() -> String {
-    base64::encode(rand::thread_rng().gen::<[u8; TOKEN_LENGTH]>())
+    base64::encode_config(
+        rand::thread_rng().gen::<[u8; TOKEN_LENGTH]>(),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// Derives a PKCE `code_challenge` from a `code_verifier` using the `S256` method
+pub fn code_challenge(verifier: impl AsRef<[u8]>) -> String {
+    base64::encode_config(Sha256::digest(verifier.as_ref()), base64::URL_SAFE_NO_PAD)
+}
+
+/// Hashes a randomly generated token for storage and lookup, with a fast hash
+/// being enough as tokens are too long to brute force
+pub fn hash_token(token: impl AsRef<[u8]>) -> Vec<u8> {
+    Sha256::digest(token.as_ref()).to_vec()
 }
 
 /// Generates ids for i32 length
@@ -23,10 +55,29 @@ pub fn gen_id() -> i32 {
     rand::thread_rng().gen()
 }
 
+/// Computes an RFC 4226 one-time code of `digits` length for a `counter`,
+/// which for RFC 6238 TOTP is the current time step
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac =
+        <Hmac<Sha1> as Mac>::new_from_slice(secret).expect("hmac accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // dynamic truncation, taking 31 bits from an offset given by the last byte
+    let offset = (digest[digest.len() - 1] & 0xf) as usize;
+    let code = u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    code % 10u32.pow(digits)
+}
+
+/// Prefix of [Hash::inner] for hashes in PHC string format
+const PHC_PREFIX: &str = "$argon2";
+
 /// Hash container, allowing easy password hashing access
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Hash {
-    /// Actual hash
+    /// Actual hash as a PHC string containing it's argon2 parameters, or the raw
+    /// hash bytes for legacy hashes made with [argon2::Config::default] and the
+    /// pepper appended to the salt
     pub inner: Vec<u8>,
     /// Salt used to construct the hash
     pub salt: [u8; SALT_LENGTH],
@@ -35,9 +86,11 @@ pub struct Hash {
 }
 
 impl Hash {
-    /// Creates a new [struct@Hash] from salt, pepper and a given input
+    /// Creates a new [struct@Hash] from salt, pepper and a given input, using
+    /// the parameters of [Config::hash] and the pepper as the argon2 secret
     pub fn new(
-        config: &Config,
+        params: &HashConfig,
+        pepper: &[u8],
         input: impl AsRef<[u8]>,
         salt: impl Into<Option<[u8; SALT_LENGTH]>>,
     ) -> Result<Self, argon2::Error> {
@@ -46,12 +99,18 @@ impl Hash {
             None => gen_salt(),
         };
 
+        let argon = argon2::Config {
+            variant: params.variant,
+            version: argon2::Version::Version13,
+            mem_cost: params.memory,
+            time_cost: params.iterations,
+            lanes: params.lanes,
+            secret: pepper,
+            ..argon2::Config::default()
+        };
+
         Ok(Self {
-            inner: argon2::hash_raw(
-                input.as_ref(),
-                &concat_pepper(config, salt)[..],
-                &argon2::Config::default(),
-            )?,
+            inner: argon2::hash_encoded(input.as_ref(), &salt, &argon)?.into_bytes(),
             salt,
             created: Utc::now(),
         })
@@ -59,25 +118,74 @@ impl Hash {
 
     /// Creates a new [struct@Hash] from a plaintext password
     pub fn from_password(
-        config: &Config,
+        params: &HashConfig,
+        pepper: &[u8],
         password: impl AsRef<[u8]>,
     ) -> Result<Self, argon2::Error> {
         if password.as_ref().len() > MAX_PASSWORD {
             Err(argon2::Error::PwdTooLong)
         } else {
-            Self::new(config, password, gen_salt())
+            Self::new(params, pepper, password, gen_salt())
         }
     }
 
-    /// Compares a given input to existing hash on record
-    pub fn compare(&self, input: impl AsRef<[u8]>) -> Result<bool, argon2::Error> {
-        argon2::verify_raw(
-            self.inner.as_slice(),
-            &self.salt[..],
-            input.as_ref(),
-            &argon2::Config::default(),
-        )
+    /// Compares a given input to existing hash on record in constant time, using
+    /// the parameters stored within it or the legacy salt and pepper combination
+    pub fn compare(&self, pepper: &[u8], input: impl AsRef<[u8]>) -> Result<bool, argon2::Error> {
+        match self.phc() {
+            Some(phc) => argon2::verify_encoded_ext(phc, input.as_ref(), pepper, &[]),
+            None => argon2::verify_raw(
+                input.as_ref(),
+                &concat_pepper(pepper, self.salt)[..],
+                self.inner.as_slice(),
+                &argon2::Config::default(),
+            ),
+        }
+    }
+
+    /// Checks if this hash should be remade from the password once it's known,
+    /// being legacy or made with parameters weaker than [Config::hash]
+    pub fn needs_rehash(&self, policy: &HashConfig) -> bool {
+        match self.phc().and_then(phc_params) {
+            Some(params) => params.is_weaker_than(policy),
+            None => true,
+        }
+    }
+
+    /// Gets [Hash::inner] as a PHC string, or [None] if it's a legacy hash
+    fn phc(&self) -> Option<&str> {
+        std::str::from_utf8(&self.inner)
+            .ok()
+            .filter(|phc| phc.starts_with(PHC_PREFIX))
+    }
+}
+
+/// Gets the parameters of a PHC string, or [None] if it's malformed or from an
+/// older argon2 version
+fn phc_params(phc: &str) -> Option<HashConfig> {
+    let mut parts = phc.split('$').skip(1);
+    let variant = argon2::Variant::from_str(parts.next()?).ok()?;
+    if parts.next()? != "v=19" {
+        return None;
     }
+
+    let mut params = HashConfig {
+        variant,
+        memory: 0,
+        iterations: 0,
+        lanes: 0,
+        ..HashConfig::default()
+    };
+    for param in parts.next()?.split(',') {
+        match param.split_once('=')? {
+            ("m", val) => params.memory = val.parse().ok()?,
+            ("t", val) => params.iterations = val.parse().ok()?,
+            ("p", val) => params.lanes = val.parse().ok()?,
+            _ => return None,
+        }
+    }
+
+    Some(params)
 }
 
 impl From<Hash> for Vec<u8> {
@@ -92,12 +200,328 @@ impl From<Hash> for [u8; SALT_LENGTH] {
     }
 }
 
-/// Adds together a passed `salt` and a pepper from the [Config::pepper] element
-fn concat_pepper<'a>(config: &Config, salt: [u8; SALT_LENGTH]) -> Vec<u8> {
-    [&salt[..], config.pepper.as_slice()].concat()
+/// Key encryption key from the [Config], used to wrap the [DataKey] of each row
+/// and identified by [MasterKey::id] so it can be rotated
+#[derive(PartialEq, Eq, Clone)]
+pub struct MasterKey {
+    /// Identifier stored alongside every row wrapped by this key
+    pub id: i32,
+    /// Actual key material
+    pub key: [u8; KEY_LENGTH],
 }
 
-/// Generates a new random salt, used in conjunction with [concat_pepper] to add peppering
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MasterKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Identifies the row a [DataKey] belongs to by it's table, the org or
+/// provider owning it and it's id, which is bound into the wrapped key and
+/// every ciphertext so neither can be moved onto another row
+pub fn row_aad(table: &str, owner: impl fmt::Display, id: i32) -> String {
+    format!("{}:{}:{}", table, owner, id)
+}
+
+/// Randomly generated per-row key which encrypts secret fields, stored wrapped
+/// (encrypted) by a [MasterKey] as part of envelope encryption
+pub struct DataKey {
+    cipher: XChaCha20Poly1305,
+    /// The [row_aad] of the row this key belongs to
+    row: String,
+}
+
+impl DataKey {
+    /// Unwraps (decrypts) a [DataKey] previously wrapped for the same `row`
+    /// using [DataKey::generate_wrapped]
+    pub fn unwrap(
+        master: &MasterKey,
+        wrapped: &[u8],
+        row: &str,
+    ) -> Result<Self, chacha20poly1305::Error> {
+        let bytes = decrypt(
+            &XChaCha20Poly1305::new(&master.key.into()),
+            wrapped,
+            &wrap_aad(row),
+        )?;
+
+        if bytes.len() != KEY_LENGTH {
+            return Err(chacha20poly1305::Error);
+        }
+
+        Ok(Self::from_bytes(&bytes, row))
+    }
+
+    /// Unwraps a [DataKey] using whichever [MasterKey] from the [Config] has the
+    /// given `key_id`
+    pub fn unwrap_by_id(
+        config: &Config,
+        key_id: i32,
+        wrapped: &[u8],
+        row: &str,
+    ) -> Result<Self, chacha20poly1305::Error> {
+        match config.master_key_by_id(key_id) {
+            Some(master) => Self::unwrap(master, wrapped, row),
+            None => Err(chacha20poly1305::Error),
+        }
+    }
+
+    /// Generates a new [DataKey] for `row` and wraps it immediately, returning
+    /// both
+    pub fn generate_wrapped(
+        master: &MasterKey,
+        row: &str,
+    ) -> Result<(Self, Vec<u8>), chacha20poly1305::Error> {
+        let bytes = rand::thread_rng().gen::<[u8; KEY_LENGTH]>();
+        let wrapped = encrypt(
+            &XChaCha20Poly1305::new(&master.key.into()),
+            &bytes,
+            &wrap_aad(row),
+        )?;
+
+        Ok((Self::from_bytes(&bytes, row), wrapped))
+    }
+
+    /// Re-wraps a wrapped [DataKey] of `row` from whichever [MasterKey] has the
+    /// given `key_id` to the current [Config::master_key], leaving the data it
+    /// encrypts untouched
+    pub fn rewrap(
+        config: &Config,
+        key_id: i32,
+        wrapped: &[u8],
+        row: &str,
+    ) -> Result<Vec<u8>, chacha20poly1305::Error> {
+        let old = config
+            .master_key_by_id(key_id)
+            .ok_or(chacha20poly1305::Error)?;
+        let aad = wrap_aad(row);
+        let bytes = decrypt(&XChaCha20Poly1305::new(&old.key.into()), wrapped, &aad)?;
+
+        encrypt(
+            &XChaCha20Poly1305::new(&config.master_key.key.into()),
+            &bytes,
+            &aad,
+        )
+    }
+
+    /// Encrypts a secret field, bound to the given `field` name and the row of
+    /// this key so ciphertexts can't be swapped between fields or rows
+    pub fn encrypt(
+        &self,
+        plaintext: impl AsRef<[u8]>,
+        field: &str,
+    ) -> Result<Vec<u8>, chacha20poly1305::Error> {
+        encrypt(&self.cipher, plaintext.as_ref(), &self.field_aad(field))
+    }
+
+    /// Decrypts a secret field encrypted by [DataKey::encrypt] into a string
+    pub fn decrypt(
+        &self,
+        ciphertext: &[u8],
+        field: &str,
+    ) -> Result<String, chacha20poly1305::Error> {
+        String::from_utf8(decrypt(&self.cipher, ciphertext, &self.field_aad(field))?)
+            .map_err(|_| chacha20poly1305::Error)
+    }
+
+    /// Creates cipher for `row` from raw key bytes
+    fn from_bytes(bytes: &[u8], row: &str) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(bytes.into()),
+            row: row.to_string(),
+        }
+    }
+
+    /// Associated data of a secret field within the row of this key
+    fn field_aad(&self, field: &str) -> Vec<u8> {
+        format!("{}:{}", field, self.row).into_bytes()
+    }
+}
+
+/// Associated data of a wrapped [DataKey] for a given `row`
+fn wrap_aad(row: &str) -> Vec<u8> {
+    [WRAP_AAD, b":", row.as_bytes()].concat()
+}
+
+/// Encrypts with a random nonce, which is prepended to the returned ciphertext
+fn encrypt(
+    cipher: &XChaCha20Poly1305,
+    msg: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, chacha20poly1305::Error> {
+    let nonce = rand::thread_rng().gen::<[u8; NONCE_LENGTH]>();
+    let ciphertext = cipher.encrypt(XNonce::from_slice(&nonce), Payload { msg, aad })?;
+
+    Ok([&nonce[..], &ciphertext[..]].concat())
+}
+
+/// Decrypts ciphertext created by [encrypt], erroring if it was tampered with
+fn decrypt(
+    cipher: &XChaCha20Poly1305,
+    input: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, chacha20poly1305::Error> {
+    if input.len() < NONCE_LENGTH {
+        return Err(chacha20poly1305::Error);
+    }
+
+    let (nonce, msg) = input.split_at(NONCE_LENGTH);
+    cipher.decrypt(XNonce::from_slice(nonce), Payload { msg, aad })
+}
+
+/// Adds together a passed `salt` and a pepper from the [Config::pepper] element,
+/// only used by legacy hashes
+fn concat_pepper(pepper: &[u8], salt: [u8; SALT_LENGTH]) -> Zeroizing<Vec<u8>> {
+    Zeroizing::new([&salt[..], pepper].concat())
+}
+
+/// Generates a new random salt for [Hash::new]
 fn gen_salt() -> [u8; SALT_LENGTH] {
     rand::thread_rng().gen()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_compare() {
+        let hash = Hash::from_password(&Config::test().hash, b"pepper", "password").unwrap();
+
+        assert_eq!(hash.compare(b"pepper", "password"), Ok(true));
+        assert_eq!(hash.compare(b"pepper", "passwork"), Ok(false));
+        assert_eq!(hash.compare(b"pepper", ""), Ok(false));
+    }
+
+    #[test]
+    fn token_url_safe() {
+        let token = gen_token();
+        assert_eq!(token.len(), 43);
+        assert!(token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    }
+
+    #[test]
+    fn pkce_challenge() {
+        // RFC 7636 appendix B
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn envelope_roundtrip() {
+        let master = MasterKey {
+            id: 1,
+            key: [7; KEY_LENGTH],
+        };
+        let row = row_aad("table", "owner", 1);
+        let (data_key, wrapped) = DataKey::generate_wrapped(&master, &row).unwrap();
+        let ciphertext = data_key.encrypt("secret", "field").unwrap();
+
+        let data_key = DataKey::unwrap(&master, &wrapped, &row).unwrap();
+        assert_eq!(data_key.decrypt(&ciphertext, "field").unwrap(), "secret");
+        assert!(data_key.decrypt(&ciphertext, "other").is_err());
+        let (other_key, _) = DataKey::generate_wrapped(&master, &row).unwrap();
+        assert!(other_key.decrypt(&ciphertext, "field").is_err());
+
+        // neither the key nor it's ciphertexts can move onto another row
+        for other_row in [row_aad("table", "owner", 2), row_aad("table", "other", 1)] {
+            assert!(DataKey::unwrap(&master, &wrapped, &other_row).is_err());
+        }
+
+        let mut tampered = ciphertext;
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(data_key.decrypt(&tampered, "field").is_err());
+    }
+
+    #[test]
+    fn envelope_wrong_master() {
+        let master = MasterKey {
+            id: 1,
+            key: [7; KEY_LENGTH],
+        };
+        let other = MasterKey {
+            id: 2,
+            key: [8; KEY_LENGTH],
+        };
+        let (_, wrapped) = DataKey::generate_wrapped(&master, "row").unwrap();
+
+        assert!(DataKey::unwrap(&other, &wrapped, "row").is_err());
+        assert!(DataKey::unwrap(&master, &wrapped[..10], "row").is_err());
+    }
+
+    #[test]
+    fn envelope_rewrap() {
+        let old = MasterKey {
+            id: 1,
+            key: [7; KEY_LENGTH],
+        };
+        let mut config = Config::test();
+        config.master_key = MasterKey {
+            id: 2,
+            key: [8; KEY_LENGTH],
+        };
+        config.old_master_keys = vec![old.clone()];
+
+        let (data_key, wrapped) = DataKey::generate_wrapped(&old, "row").unwrap();
+        let ciphertext = data_key.encrypt("secret", "field").unwrap();
+        let rewrapped = DataKey::rewrap(&config, 1, &wrapped, "row").unwrap();
+
+        assert!(DataKey::unwrap(&old, &rewrapped, "row").is_err());
+        assert!(DataKey::unwrap_by_id(&config, 2, &rewrapped, "other").is_err());
+        let data_key = DataKey::unwrap_by_id(&config, 2, &rewrapped, "row").unwrap();
+        assert_eq!(data_key.decrypt(&ciphertext, "field").unwrap(), "secret");
+        assert!(DataKey::rewrap(&config, 3, &wrapped, "row").is_err());
+        assert!(DataKey::rewrap(&config, 1, &wrapped, "other").is_err());
+    }
+
+    #[test]
+    fn hash_compare_pepper() {
+        let hash = Hash::from_password(&Config::test().hash, b"pepper", "password").unwrap();
+        assert_eq!(hash.compare(b"peppex", "password"), Ok(false));
+    }
+
+    #[test]
+    fn hash_rehash() {
+        let mut params = Config::test().hash;
+        let salt = gen_salt();
+        let legacy = Hash {
+            inner: argon2::hash_raw(
+                b"password",
+                &concat_pepper(b"pepper", salt)[..],
+                &argon2::Config::default(),
+            )
+            .unwrap(),
+            salt,
+            created: Utc::now(),
+        };
+        assert_eq!(legacy.compare(b"pepper", "password"), Ok(true));
+        assert_eq!(legacy.compare(b"pepper", "passwork"), Ok(false));
+        assert!(legacy.needs_rehash(&params));
+
+        let hash = Hash::from_password(&params, b"pepper", "password").unwrap();
+        assert!(String::from_utf8(hash.inner.clone())
+            .unwrap()
+            .starts_with("$argon2id$v=19$m=8,t=1,p=1$"));
+        assert!(!hash.needs_rehash(&params));
+
+        params.iterations += 1;
+        assert!(hash.needs_rehash(&params));
+        assert_eq!(hash.compare(b"pepper", "password"), Ok(true));
+    }
+
+    #[test]
+    fn hotp_rfc6238() {
+        // sha1 test vectors from rfc 6238 appendix b, with 30 second steps
+        let secret = b"12345678901234567890";
+        assert_eq!(hotp(secret, 59 / 30, 8), 94287082);
+        assert_eq!(hotp(secret, 1111111109 / 30, 8), 7081804);
+        assert_eq!(hotp(secret, 1234567890 / 30, 8), 89005924);
+        assert_eq!(hotp(secret, 1234567890 / 30, 6), 5924);
+    }
+}
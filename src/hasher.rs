@@ -0,0 +1,184 @@
+//! Bounded pool for password hashing, see [Hasher]
+
+use crate::config::HashConfig;
+use crate::crypto::Hash;
+use crate::{AuthErrorKind, Config};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use zeroize::Zeroizing;
+
+/// Runs memory-hard argon2 work on the blocking thread pool instead of the
+/// async workers, with a limit on how many run at once so a burst of logins
+/// can't stall every other request
+#[derive(Debug)]
+pub struct Hasher {
+    /// Slots for running hashes, sized by [HashConfig::concurrency] and held by
+    /// the blocking job itself so cancelled requests don't free them early
+    slots: Arc<Semaphore>,
+    /// How long to wait for a slot, from [HashConfig::queue_timeout]
+    timeout: Duration,
+    /// Amount of hashes waiting for a slot
+    queued: AtomicUsize,
+    /// Amount of hashes currently running
+    running: Arc<AtomicUsize>,
+    /// Amount of hashes refused from waiting too long for a slot
+    timeouts: AtomicU64,
+}
+
+/// Point-in-time counters of a [Hasher], see [Hasher::stats]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct HasherStats {
+    /// Amount of hashes waiting for a slot
+    pub queued: usize,
+    /// Amount of hashes currently running
+    pub running: usize,
+    /// Amount of hashes refused since starting
+    pub timeouts: u64,
+}
+
+impl Hasher {
+    /// Creates a new [Hasher] from it's [HashConfig] limits
+    pub fn new(hash: &HashConfig) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(hash.concurrency)),
+            timeout: Duration::from_secs(hash.queue_timeout),
+            queued: AtomicUsize::new(0),
+            running: Arc::new(AtomicUsize::new(0)),
+            timeouts: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs `work` on the blocking thread pool once a slot is free, erroring
+    /// with [AuthErrorKind::Busy] if none frees up in time
+    pub async fn run<T: Send + 'static>(
+        &self,
+        work: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, AuthErrorKind> {
+        let slot = {
+            let _queued = Gauge::inc(&self.queued);
+            tokio::time::timeout(self.timeout, self.slots.clone().acquire_owned()).await
+        };
+        let slot = match slot {
+            Ok(Ok(slot)) => slot,
+            Ok(Err(_)) => unreachable!("hasher slots are never closed"),
+            Err(_) => {
+                self.timeouts.fetch_add(1, Ordering::Relaxed);
+                return Err(AuthErrorKind::Busy);
+            }
+        };
+
+        let running = self.running.clone();
+        tokio::task::spawn_blocking(move || {
+            let _slot = slot;
+            let _running = Gauge::inc(&running);
+            work()
+        })
+        .await
+        .map_err(|err| AuthErrorKind::UnknownError(Some(err.to_string())))
+    }
+
+    /// Hashes a new password, see [Hash::from_password]
+    pub async fn hash(&self, config: &Config, password: &str) -> Result<Hash, AuthErrorKind> {
+        let (params, pepper) = (config.hash, config.pepper.clone());
+        let password = Zeroizing::new(password.to_string());
+
+        self.run(move || Hash::from_password(&params, &pepper, password.as_str()))
+            .await?
+            .map_err(AuthErrorKind::from)
+    }
+
+    /// Compares a password to an existing hash, see [Hash::compare]
+    pub async fn compare(
+        &self,
+        config: &Config,
+        hash: &Hash,
+        password: &str,
+    ) -> Result<bool, AuthErrorKind> {
+        let (pepper, hash) = (config.pepper.clone(), hash.clone());
+        let password = Zeroizing::new(password.to_string());
+
+        self.run(move || hash.compare(&pepper, password.as_str()))
+            .await?
+            .map_err(AuthErrorKind::from)
+    }
+
+    /// Gets the current queue depth and other counters for metrics
+    pub fn stats(&self) -> HasherStats {
+        HasherStats {
+            queued: self.queued.load(Ordering::Relaxed),
+            running: self.running.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Increments a counter until dropped, so it stays accurate when a request is
+/// cancelled whilst waiting
+struct Gauge<'a>(&'a AtomicUsize);
+
+impl<'a> Gauge<'a> {
+    fn inc(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for Gauge<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn queue_timeout() {
+        let hasher = Hasher::new(&HashConfig {
+            concurrency: 1,
+            queue_timeout: 1,
+            ..Config::test().hash
+        });
+        let slot = hasher.slots.acquire().await.unwrap();
+
+        assert_eq!(hasher.run(|| ()).await, Err(AuthErrorKind::Busy));
+        assert_eq!(
+            hasher.stats(),
+            HasherStats {
+                queued: 0,
+                running: 0,
+                timeouts: 1
+            }
+        );
+
+        drop(slot);
+        let config = Config::test();
+        let hash = hasher.hash(&config, "password").await.unwrap();
+        assert_eq!(hasher.compare(&config, &hash, "password").await, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn slot_held_by_job() {
+        let hasher = Hasher::new(&HashConfig {
+            concurrency: 1,
+            ..Config::test().hash
+        });
+        let (release, wait) = std::sync::mpsc::channel::<()>();
+
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(50),
+            hasher.run(move || wait.recv().ok()),
+        )
+        .await;
+        assert!(cancelled.is_err());
+        assert_eq!(hasher.slots.available_permits(), 0);
+        assert_eq!(hasher.stats().running, 1);
+
+        release.send(()).unwrap();
+        let _ = hasher.slots.acquire().await.unwrap();
+        assert_eq!(hasher.stats().running, 0);
+    }
+}
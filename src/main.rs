@@ -2,17 +2,35 @@
 
 pub mod crypto;
 pub mod models;
+pub mod oauth;
 
 mod config;
 mod auth_result;
+mod hasher;
+mod migrate;
+mod notifier;
+mod refresher;
+mod rotate;
 mod routes;
+mod secret;
+mod store;
+mod tls;
 
 pub use auth_result::*;
 
-use actix_web::{App, HttpServer};
+use actix_web::{dev::Service, web, App, HttpServer};
 use config::Config;
-use sqlx::postgres::PgPoolOptions;
-use std::{fmt, process};
+use hasher::Hasher;
+use refresher::Refresher;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::{env, fmt, process, sync::Arc, time::Instant};
+use store::{MemoryStore, PgStore, Store};
+
+/// Value of [Config::db_url] which uses a [MemoryStore] instead of postgres
+const MEMORY_DB_URL: &str = "memory";
+
+/// Prefix of [Config::db_url] which uses a sqlite database instead of postgres
+const SQLITE_DB_PREFIX: &str = "sqlite:";
 
 /// Displays given error to `stderr` and exits
 fn err_exit(msg: impl fmt::Display) -> ! {
@@ -20,6 +38,13 @@ fn err_exit(msg: impl fmt::Display) -> ! {
     process::exit(1)
 }
 
+/// Displays given message to `stdout` and exits successfully, for modes which
+/// don't run the server
+fn done_exit(msg: impl fmt::Display) -> ! {
+    println!("✅ {}", msg);
+    process::exit(0)
+}
+
 /// Get local package version
 #[macro_export]
 macro_rules! crate_version {
@@ -30,23 +55,139 @@ macro_rules! crate_version {
 
 /// Shows message dependant upon the encryption type of the [Config::db_url] element
 fn db_is_encrypted(config: &Config) -> &str {
-    match config.db_url.contains("ssl=true") {
+    match config.db_is_encrypted() {
         true => "encrypted",
         false => "\x1b[31;1;4mUNENCRYPTED\x1b[0m",
     }
 }
 
-#[tokio::main]
-async fn main() {
-    // config setuo
-    println!("🔗 Pulling configurations..");
-    dotenv::dotenv().ok();
-    let config = Config::new().map_err(|err| err_exit(err)).unwrap();
+/// What to run, chosen by command line arguments
+#[derive(Clone, Copy)]
+enum Mode {
+    /// Migrate then run the server, the default
+    Serve,
+    /// Only apply pending migrations, from `--migrate-only`
+    MigrateOnly,
+    /// Revert migrations down to the given version or otherwise just the latest
+    /// migration, from `--rollback [version]`
+    Rollback(Option<i32>),
+    /// Re-wrap all rows onto the current master key, from `--rotate-keys`
+    RotateKeys,
+    /// Only validate the config, from `--check-config`
+    CheckConfig,
+}
+
+impl Mode {
+    /// Gets the [Mode] from command line arguments
+    fn from_args(args: &[String]) -> Result<Self, String> {
+        match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            [] => Ok(Self::Serve),
+            ["--migrate-only"] => Ok(Self::MigrateOnly),
+            ["--rollback"] => Ok(Self::Rollback(None)),
+            ["--rollback", version] => version
+                .parse()
+                .map(|val| Self::Rollback(Some(val)))
+                .map_err(|_| format!("Invalid version to rollback to '{}'", version)),
+            ["--rotate-keys"] => Ok(Self::RotateKeys),
+            ["--check-config"] => Ok(Self::CheckConfig),
+            _ => Err(format!(
+                "Unknown arguments '{}', expected one of --migrate-only, --rollback [version], --rotate-keys or --check-config alongside an optional --config <path>",
+                args.join(" ")
+            )),
+        }
+    }
+}
+
+/// Parsed command line arguments
+struct Args {
+    /// What to run
+    mode: Mode,
+    /// Config file path, from `--config <path>`
+    config: Option<String>,
+}
+
+impl Args {
+    /// Gets the [Args] from [mod@std::env] arguments
+    fn from_env() -> Result<Self, String> {
+        let mut args: Vec<String> = env::args().skip(1).collect();
+
+        let config = match args.iter().position(|arg| arg == "--config") {
+            Some(ind) if ind + 1 < args.len() => {
+                let path = args.remove(ind + 1);
+                args.remove(ind);
+                Some(path)
+            }
+            Some(_) => return Err("No path given for --config".to_string()),
+            None => None,
+        };
+
+        Ok(Self {
+            mode: Mode::from_args(&args)?,
+            config,
+        })
+    }
+}
+
+/// Runs migrations for a backend's pool, exiting once done if the [Mode] isn't
+/// to serve
+macro_rules! run_mode {
+    ($backend:ident, $pool:expr, $config:expr, $mode:expr) => {{
+        let (pool, config, mode) = ($pool, $config, $mode);
+
+        // schema migrations
+        if let Mode::Rollback(target) = mode {
+            let target = match target {
+                Some(val) => val,
+                None => match migrate::$backend::version(pool).await {
+                    Ok(val) => (val - 1).max(0),
+                    Err(err) => err_exit(format!("Couldn't get schema version, {:?}", err)),
+                },
+            };
+
+            match migrate::$backend::down(pool, target).await {
+                Ok(reverted) => done_exit(format!("Reverted {} migrations!", reverted)),
+                Err(err) => err_exit(format!("Rollback failed, {:?}", err)),
+            }
+        }
+
+        match migrate::$backend::up(pool).await {
+            Ok(applied) if applied > 0 => println!("📦 Applied {} migrations!", applied),
+            Ok(_) => (),
+            Err(err) => err_exit(format!("Migrations failed, {:?}", err)),
+        }
+
+        if let Mode::MigrateOnly = mode {
+            done_exit("Migrated, not starting server");
+        }
+
+        // master key rotation, ran instead of the server
+        if let Mode::RotateKeys = mode {
+            println!("🔑 Rotating to master key {}..", config.master_key.id);
+            match rotate::$backend::rotate(pool, config).await {
+                Ok(report) if report.failed == 0 && report.skipped == 0 => {
+                    done_exit(format!("Rotated {} rows!", report.rotated))
+                }
+                Ok(report) if report.failed == 0 => done_exit(format!(
+                    "Rotated {} rows, skipped {} changed whilst rotating, run again to catch any still on an old key",
+                    report.rotated, report.skipped
+                )),
+                Ok(report) => err_exit(format!(
+                    "Rotated {} rows but {} failed, run again once fixed to resume",
+                    report.rotated, report.failed
+                )),
+                Err(err) => err_exit(format!("Rotation stopped, run again to resume, {:?}", err)),
+            }
+        }
+    }};
+}
 
+/// Connects to postgres and runs migrations, exiting once done if the [Mode]
+/// isn't to serve
+async fn setup_postgres(config: &Config, mode: Mode) -> PgPool {
     // sqlx setup
-    println!("🔗 Connecting to {} database..", db_is_encrypted(&config));
+    println!("🔗 Connecting to {} database..", db_is_encrypted(config));
     let pool = match PgPoolOptions::new()
-        .max_connections(5)
+        .max_connections(config.db_max_connections)
         .connect(&config.db_url)
         .await
     {
@@ -54,18 +195,212 @@ async fn main() {
         Err(err) => err_exit(format!("Database could not be loaded, {:?}", err)),
     };
 
-    // run server
-    println!("🚀 Starting on http://{} address!", config.hostname());
+    run_mode!(postgres, &pool, config, mode);
+    pool
+}
+
+/// Opens the sqlite database, creating it if needed, and runs migrations,
+/// exiting once done if the [Mode] isn't to serve
+#[cfg(feature = "sqlite")]
+async fn setup_sqlite(config: &Config, mode: Mode) -> sqlx::SqlitePool {
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    // sqlx setup, with foreign keys needed for cascading deletes
+    println!("🔗 Opening local sqlite database..");
+    let options = match SqliteConnectOptions::from_str(&config.db_url) {
+        Ok(val) => val.create_if_missing(true).foreign_keys(true),
+        Err(err) => err_exit(format!("Database url is invalid, {:?}", err)),
+    };
+
+    // migrate on a connection of it's own first, as sqlite connections only see
+    // another's schema changes once a statement runs, after sqlx read it's columns
+    let migrate_pool = match SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options.clone())
+        .await
+    {
+        Ok(val) => val,
+        Err(err) => err_exit(format!("Database could not be loaded, {:?}", err)),
+    };
+    run_mode!(sqlite, &migrate_pool, config, mode);
+    migrate_pool.close().await;
+
+    // sqlx opens sqlite connections by blocking, which panics within the single
+    // threaded server workers, so all are opened here up front and never closed
+    match SqlitePoolOptions::new()
+        .min_connections(config.db_max_connections)
+        .max_connections(config.db_max_connections)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(options)
+        .await
+    {
+        Ok(val) => val,
+        Err(err) => err_exit(format!("Database could not be loaded, {:?}", err)),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::from_env().map_err(|err| err_exit(err)).unwrap();
+    let mode = args.mode;
+
+    // config setuo
+    println!("🔗 Pulling configurations..");
+    dotenv::dotenv().ok();
+    let config = match Config::new(args.config.as_deref()) {
+        Ok(val) => val,
+        Err(errors) => {
+            for err in errors.iter() {
+                eprintln!("❌ {}", err);
+            }
+            err_exit(format!("Found {} problems with the config", errors.len()))
+        }
+    };
+    let warnings = config.warnings();
+    for warning in warnings.iter() {
+        eprintln!("⚠️  {}", warning);
+    }
+
+    // validation only, including that the tls certificate is usable
+    if let Mode::CheckConfig = mode {
+        if let Some(tls) = &config.tls {
+            if let Err(err) = tls::CertResolver::new(tls) {
+                err_exit(format!("Tls certificate could not be loaded, {}", err))
+            }
+        }
+
+        done_exit(format!(
+            "Config is valid with {} warnings, not starting server",
+            warnings.len()
+        ))
+    }
+
+    // storage setup
+    let store: Arc<dyn Store> = if config.db_url == MEMORY_DB_URL {
+        if !matches!(mode, Mode::Serve) {
+            err_exit("An in-memory database can't be migrated or rotated")
+        }
+
+        println!("🔗 Using in-memory database, nothing will be persisted..");
+        Arc::new(MemoryStore::new())
+    } else if config.db_url.starts_with(SQLITE_DB_PREFIX) {
+        #[cfg(feature = "sqlite")]
+        {
+            let pool = setup_sqlite(&config, mode).await;
+            Arc::new(store::SqliteStore::new(pool, config.clone()))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        err_exit("Sqlite databases need building with the `sqlite` feature")
+    } else {
+        let pool = setup_postgres(&config, mode).await;
+        Arc::new(PgStore::new(pool, config.clone()))
+    };
+
+    // background token refreshing
+    let client = reqwest::Client::new();
+    if let Some(refresh) = config.refresh.clone() {
+        println!(
+            "🔄 Refreshing expiring tokens every {}s..",
+            refresh.interval
+        );
+        tokio::spawn(Refresher::new(store.clone(), client.clone(), refresh).run());
+    }
+
+    // tls setup, reloading the certificate whilst running
+    let tls_config = config.tls.as_ref().map(|tls| {
+        println!("🔒 Loading tls certificate from {}..", tls.cert.display());
+        let resolver = match tls::CertResolver::new(tls) {
+            Ok(val) => Arc::new(val),
+            Err(err) => err_exit(format!("Tls certificate could not be loaded, {}", err)),
+        };
+        tokio::spawn(resolver.clone().watch());
+
+        tls::server_config(resolver)
+            .map_err(|err| err_exit(err))
+            .unwrap()
+    });
+
+    // run server, with hashing and notifications shared between all workers
     let app_config = config.clone();
-    HttpServer::new(move || {
+    let hasher = web::Data::new(Hasher::new(&config.hash));
+    let notifier = notifier::from_config(&config.notifier);
+    let log_requests = config.log_requests;
+    let mut server = HttpServer::new(move || {
         App::new()
-            .data(pool.clone())
-            .data(app_config.clone())
+            .wrap_fn(move |req, srv| {
+                let started = Instant::now();
+                let line = log_requests.then(|| format!("{} {}", req.method(), req.path()));
+                let res = srv.call(req);
+
+                async move {
+                    let res = res.await?;
+                    if let Some(line) = line {
+                        println!(
+                            "📨 {} {} in {}ms",
+                            line,
+                            res.status().as_u16(),
+                            started.elapsed().as_millis()
+                        );
+                    }
+                    Ok(res)
+                }
+            })
+            .app_data(web::Data::from(store.clone()))
+            .app_data(web::Data::new(app_config.clone()))
+            .app_data(web::Data::new(client.clone()))
+            .app_data(hasher.clone())
+            .app_data(web::Data::from(notifier.clone()))
             .configure(routes::init)
-    })
-    .bind(config.hostname())
-    .expect("❌ Could not bind to url, is it in use?") // TODO: better error
-    .run()
-    .await
+    });
+    for addr in config.bind.iter() {
+        println!("🔗 Binding to {}..", addr);
+        let bound = match &tls_config {
+            Some(tls_config) => {
+                server.bind_rustls_0_23((addr.host.as_str(), addr.port), tls_config.clone())
+            }
+            None => server.bind((addr.host.as_str(), addr.port)),
+        };
+        server = match bound {
+            Ok(val) => val,
+            Err(err) => err_exit(format!(
+                "Could not bind to {}, is it in use or unresolvable? {}",
+                addr, err
+            )),
+        };
+    }
+
+    // plain http listeners which redirect to https
+    let redirect = config.tls.as_ref().and_then(|tls| {
+        if tls.redirect.is_empty() {
+            return None;
+        }
+
+        let url = config.advertised_url();
+        let mut redirect = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(url.clone()))
+                .default_service(web::to(tls::redirect))
+        })
+        .workers(1);
+        for addr in tls.redirect.iter() {
+            println!("🔗 Redirecting {} to https..", addr);
+            redirect = match redirect.bind((addr.host.as_str(), addr.port)) {
+                Ok(val) => val,
+                Err(err) => err_exit(format!(
+                    "Could not bind to {}, is it in use or unresolvable? {}",
+                    addr, err
+                )),
+            };
+        }
+        Some(redirect.run())
+    });
+
+    println!("🚀 Starting on {} address!", config.advertised_url());
+    match redirect {
+        Some(redirect) => tokio::try_join!(server.run(), redirect).map(|_| ()),
+        None => server.run().await,
+    }
     .expect("❌ Failed to launch app") // TODO: better error
 }
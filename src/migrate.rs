@@ -0,0 +1,187 @@
+//! Embedded schema migrations, applied in order and tracked by version within
+//! the `schema_version` table, with a separate set of migrations per backend
+
+/// Key for the transaction-level advisory lock held whilst migrating postgres,
+/// so that multiple instances starting at once don't race each other
+const LOCK_KEY: i64 = 0x0061_7574_6872_696f;
+
+/// Single embedded migration with it's forwards and backwards sql
+pub struct Migration {
+    /// Version number, migrations are applied in ascending order
+    pub version: i32,
+    /// Human-readable name, recorded alongside the version
+    pub name: &'static str,
+    /// Sql applying this migration
+    pub up: &'static str,
+    /// Sql reverting this migration
+    pub down: &'static str,
+}
+
+/// Embeds a migration from the `migrations/{backend}/{version}_{name}` directory
+macro_rules! migration {
+    ($backend:literal, $version:expr, $dir:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../migrations/", $backend, "/", $dir, "/up.sql")),
+            down: include_str!(concat!("../migrations/", $backend, "/", $dir, "/down.sql")),
+        }
+    };
+}
+
+/// Implements migrating a backend using the migrations within
+/// `migrations/{backend}`, optionally running `lock` at the start of every
+/// migration transaction
+macro_rules! migrator {
+    ($db:ty, $backend:literal $(, lock: $lock:expr)?) => {
+        use super::Migration;
+        use chrono::prelude::*;
+        use sqlx::{Executor, Pool, Transaction};
+
+        /// All migrations in the order they should be applied
+        pub const MIGRATIONS: [Migration; 10] = [
+            migration!($backend, 1, "0001_org", "org"),
+            migration!($backend, 2, "0002_provider", "provider"),
+            migration!($backend, 3, "0003_user_provider", "user_provider"),
+            migration!($backend, 4, "0004_oauth_state", "oauth_state"),
+            migration!($backend, 5, "0005_api_key", "api_key"),
+            migration!($backend, 6, "0006_api_key_scopes", "api_key_scopes"),
+            migration!($backend, 7, "0007_org_member", "org_member"),
+            migration!($backend, 8, "0008_password_reset", "password_reset"),
+            migration!($backend, 9, "0009_totp", "totp"),
+            migration!($backend, 10, "0010_oauth_state_binding", "oauth_state_binding"),
+        ];
+
+        /// Applies all pending [MIGRATIONS], each within it's own transaction,
+        /// returning the amount which were applied
+        pub async fn up(pool: &Pool<$db>) -> Result<usize, sqlx::Error> {
+            let mut applied = 0;
+
+            for migration in MIGRATIONS.iter() {
+                let mut tx = lock(pool).await?;
+                if current_version(&mut tx).await? >= migration.version {
+                    continue;
+                }
+
+                println!(
+                    "📦 Applying migration {} ({})..",
+                    migration.version, migration.name
+                );
+                tx.execute(migration.up).await?;
+                sqlx::query(
+                    "INSERT INTO schema_version (version, name, applied) VALUES ($1, $2, $3)",
+                )
+                .bind(migration.version)
+                .bind(migration.name)
+                .bind(Utc::now())
+                .execute(&mut tx)
+                .await?;
+                tx.commit().await?;
+
+                applied += 1;
+            }
+
+            Ok(applied)
+        }
+
+        /// Reverts applied [MIGRATIONS] newest first until the schema is at the
+        /// given version, returning the amount which were reverted
+        pub async fn down(pool: &Pool<$db>, target: i32) -> Result<usize, sqlx::Error> {
+            let mut reverted = 0;
+
+            for migration in MIGRATIONS.iter().rev() {
+                if migration.version <= target {
+                    break;
+                }
+
+                let mut tx = lock(pool).await?;
+                if current_version(&mut tx).await? != migration.version {
+                    continue;
+                }
+
+                println!(
+                    "📦 Reverting migration {} ({})..",
+                    migration.version, migration.name
+                );
+                tx.execute(migration.down).await?;
+                sqlx::query("DELETE FROM schema_version WHERE version = $1")
+                    .bind(migration.version)
+                    .execute(&mut tx)
+                    .await?;
+                tx.commit().await?;
+
+                reverted += 1;
+            }
+
+            Ok(reverted)
+        }
+
+        /// Gets the version the schema is currently at, `0` meaning nothing is
+        /// applied
+        pub async fn version(pool: &Pool<$db>) -> Result<i32, sqlx::Error> {
+            let mut tx = lock(pool).await?;
+            current_version(&mut tx).await
+        }
+
+        /// Starts a transaction holding the migration lock until it ends,
+        /// creating the `schema_version` table which tracks applied
+        /// [MIGRATIONS] if needed
+        async fn lock(pool: &Pool<$db>) -> Result<Transaction<'static, $db>, sqlx::Error> {
+            let mut tx = pool.begin().await?;
+            $($lock.execute(&mut tx).await?;)?
+            tx.execute(
+                "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER PRIMARY KEY, name TEXT NOT NULL, applied TIMESTAMP WITH TIME ZONE NOT NULL)",
+            )
+            .await?;
+
+            Ok(tx)
+        }
+
+        /// Gets the highest applied version within a transaction
+        async fn current_version(tx: &mut Transaction<'_, $db>) -> Result<i32, sqlx::Error> {
+            let (version,): (Option<i32>,) =
+                sqlx::query_as("SELECT MAX(version) FROM schema_version")
+                    .fetch_one(tx)
+                    .await?;
+
+            Ok(version.unwrap_or(0))
+        }
+    };
+}
+
+/// Migrations for [PgStore](crate::store::PgStore)
+pub mod postgres {
+    migrator!(
+        sqlx::Postgres,
+        "postgres",
+        lock: sqlx::query("SELECT pg_advisory_xact_lock($1)").bind(super::LOCK_KEY)
+    );
+}
+
+/// Migrations for [SqliteStore](crate::store::SqliteStore), which needs no
+/// extra lock as sqlite only ever allows a single writer
+#[cfg(feature = "sqlite")]
+pub mod sqlite {
+    migrator!(sqlx::Sqlite, "sqlite");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks a backend's migrations are in order and none are empty
+    fn check_ordered(migrations: &[Migration]) {
+        for (ind, migration) in migrations.iter().enumerate() {
+            assert_eq!(migration.version, ind as i32 + 1);
+            assert!(!migration.up.trim().is_empty());
+            assert!(!migration.down.trim().is_empty());
+        }
+    }
+
+    #[test]
+    fn ordered() {
+        check_ordered(&postgres::MIGRATIONS);
+        #[cfg(feature = "sqlite")]
+        check_ordered(&sqlite::MIGRATIONS);
+    }
+}
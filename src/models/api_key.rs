@@ -0,0 +1,170 @@
+//! See [ApiKey] for documentation
+
+use super::Scope;
+use crate::crypto::{gen_id, gen_token, hash_token};
+use crate::store::Store;
+use crate::{ApiKeyError, AuthError, AuthResult};
+use chrono::{prelude::*, Duration};
+use uuid::Uuid;
+
+/// Max length for [ApiKey::name] before erroring
+const MAX_NAME: usize = 32;
+
+/// Prefix of every generated key, so leaked keys are easy to scan for
+const KEY_PREFIX: &str = "authrio_";
+
+/// Seconds between updates of [ApiKey::last_used], so busy keys don't write to
+/// the [Store] on every request
+const LAST_USED_PRECISION: i64 = 60;
+
+/// Long-lived and revocable credential for an [Org](super::Org), used by
+/// services instead of sharing the org password
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ApiKey {
+    /// Randomly generated integer primary key
+    pub id: i32,
+    /// Name to tell keys apart, such as the service using it
+    pub name: String,
+    /// Hash of the key, which itself is only known when created
+    pub key_hash: Vec<u8>,
+    /// Permissions this key holds, which can't change once created
+    pub scopes: Vec<Scope>,
+    /// The [Org](super::Org) this authenticates as
+    pub org_id: Uuid,
+    /// Timestamp of the last request using this key, accurate to a minute
+    pub last_used: Option<DateTime<Utc>>,
+    /// Timestamp after which this key stops working, never expiring if [None]
+    pub expires: Option<DateTime<Utc>>,
+    /// Timestamp of creation
+    pub created: DateTime<Utc>,
+}
+
+impl ApiKey {
+    /// Creates a new [ApiKey] and validates contents, does not add to the
+    /// [Store], giving the key itself alongside as this is the only time it's
+    /// known
+    pub fn new(
+        name: impl Into<String>,
+        scopes: Vec<Scope>,
+        expires: Option<DateTime<Utc>>,
+        org_id: Uuid,
+    ) -> AuthResult<(Self, String), i32> {
+        let id = gen_id();
+        let name = name.into();
+        let key = format!("{}{}", KEY_PREFIX, gen_token());
+
+        if name.len() > MAX_NAME {
+            return Err(AuthError::new(ApiKeyError::NameTooLong, None));
+        } else if scopes.is_empty() {
+            return Err(AuthError::new(ApiKeyError::NoScopes, None));
+        } else if expires.is_some_and(|expires| expires <= Utc::now()) {
+            return Err(AuthError::new(ApiKeyError::AlreadyExpired, None));
+        }
+
+        Ok((
+            Self {
+                id,
+                name,
+                key_hash: hash_token(&key),
+                scopes,
+                org_id,
+                last_used: None,
+                expires,
+                created: Utc::now(),
+            },
+            key,
+        ))
+    }
+
+    /// Adds this [ApiKey] to the [Store]
+    pub async fn create(&self, store: &dyn Store) -> AuthResult<(), i32> {
+        store.create_api_key(self).await
+    }
+
+    /// Gets all [ApiKey]s of an org, oldest first
+    pub async fn list(store: &dyn Store, org_id: Uuid) -> AuthResult<Vec<Self>, i32> {
+        store.list_api_keys(org_id).await
+    }
+
+    /// Revokes an [ApiKey] of an org by it's [ApiKey::id]
+    pub async fn delete(store: &dyn Store, org_id: Uuid, id: i32) -> AuthResult<(), i32> {
+        store.delete_api_key(org_id, id).await
+    }
+
+    /// Gets the usable [ApiKey] for a given key, recording that it's been used,
+    /// or [None] if it's unknown or expired
+    pub async fn authenticate(store: &dyn Store, key: &str) -> AuthResult<Option<Self>, i32> {
+        let mut api_key = match store.get_api_key_by_hash(&hash_token(key)).await? {
+            Some(val) if !val.is_expired() => val,
+            _ => return Ok(None),
+        };
+
+        let now = Utc::now();
+        if api_key
+            .last_used
+            .is_none_or(|used| used + Duration::seconds(LAST_USED_PRECISION) < now)
+        {
+            store.touch_api_key(api_key.id, now).await?;
+            api_key.last_used = Some(now);
+        }
+
+        Ok(Some(api_key))
+    }
+
+    /// Checks if this key has passed it's [ApiKey::expires] time
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires <= Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture;
+    use crate::AuthErrorKind;
+
+    #[tokio::test]
+    async fn authenticate() {
+        let (store, _, org) = fixture().await;
+
+        let (api_key, key) = ApiKey::new("service", vec![Scope::UsersRead], None, org.id).unwrap();
+        assert!(key.starts_with(KEY_PREFIX));
+        api_key.create(&store).await.unwrap();
+
+        let got = ApiKey::authenticate(&store, &key).await.unwrap().unwrap();
+        assert_eq!(got.id, api_key.id);
+        assert!(got.last_used.is_some());
+        assert_eq!(
+            ApiKey::authenticate(&store, "authrio_wrong").await,
+            Ok(None)
+        );
+
+        ApiKey::delete(&store, org.id, api_key.id).await.unwrap();
+        assert_eq!(ApiKey::authenticate(&store, &key).await, Ok(None));
+    }
+
+    #[test]
+    fn expiry() {
+        let org_id = Uuid::new_v4();
+        let (mut api_key, _) =
+            ApiKey::new("service", vec![Scope::UsersRead], None, org_id).unwrap();
+        assert!(!api_key.is_expired());
+
+        api_key.expires = Some(Utc::now() - Duration::seconds(1));
+        assert!(api_key.is_expired());
+        assert!(matches!(
+            ApiKey::new("service", api_key.scopes, api_key.expires, org_id),
+            Err(AuthError {
+                kind: AuthErrorKind::ApiKeyError(ApiKeyError::AlreadyExpired),
+                ..
+            })
+        ));
+        assert!(matches!(
+            ApiKey::new("service", vec![], None, org_id),
+            Err(AuthError {
+                kind: AuthErrorKind::ApiKeyError(ApiKeyError::NoScopes),
+                ..
+            })
+        ));
+    }
+}
@@ -1,13 +1,33 @@
 //! Contains models for all database interactions
 
+mod api_key;
+mod oauth_state;
+mod one_time;
 mod org;
+mod org_invite;
+mod org_member;
+mod password_reset;
 mod provider;
+mod scope;
+mod totp;
 mod user_provider;
 
-pub use org::Org;
+pub use api_key::ApiKey;
+pub use oauth_state::OauthState;
+pub use org::{Org, OrgAuth, PasswordAuth};
+pub use org_invite::OrgInvite;
+pub use org_member::{OrgMember, Role};
+pub use password_reset::PasswordReset;
 pub use provider::Provider;
+pub use scope::Scope;
+pub use totp::Totp;
 pub use user_provider::UserProvider;
 
+#[cfg(all(test, feature = "sqlite"))]
+pub(crate) use org::tests::create_org;
+#[cfg(test)]
+pub(crate) use org::tests::{basic_auth, fixture};
+
 use crate::AuthResult;
 use std::fmt;
 
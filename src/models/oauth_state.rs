@@ -0,0 +1,151 @@
+//! See [OauthState] for documentation
+
+use crate::crypto::{code_challenge, gen_token, hash_token};
+use crate::store::Store;
+use crate::{AuthError, AuthResult, UserError};
+use chrono::{prelude::*, Duration};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Amount of minutes an [OauthState] may be used for before expiring
+const STATE_LIFETIME: i64 = 10;
+
+/// Short-lived server-side record of an in-progress authorization code flow,
+/// guarding against forged callbacks with `state`, against finishing a flow
+/// someone else started with a binding only they know and against code
+/// interception with PKCE
+#[derive(FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct OauthState {
+    /// Random `state` parameter sent to and returned by the provider
+    pub state: String,
+    /// PKCE `code_verifier` which is only revealed when exchanging the code
+    pub code_verifier: String,
+    /// Hash of the binding, which itself is only known by whoever started the
+    /// flow and must be given back alongside `state` to finish it
+    pub binding_hash: Vec<u8>,
+    /// Foreign key to the [Provider::key](super::Provider::key) field
+    pub provider_id: i32,
+    /// Timestamp of creation
+    pub created: DateTime<Utc>,
+}
+
+impl OauthState {
+    /// Creates a new [OauthState] with random values, does not add to db,
+    /// returning the binding for whoever starts the flow to keep
+    pub fn new(provider_id: i32) -> (Self, String) {
+        let binding = gen_token();
+
+        (
+            Self {
+                state: gen_token(),
+                code_verifier: gen_token(),
+                binding_hash: hash_token(&binding),
+                provider_id,
+                created: Utc::now(),
+            },
+            binding,
+        )
+    }
+
+    /// Gets the PKCE `code_challenge` for [OauthState::code_verifier]
+    pub fn code_challenge(&self) -> String {
+        code_challenge(&self.code_verifier)
+    }
+
+    /// Checks if this state is too old to be used
+    pub fn is_expired(&self) -> bool {
+        self.created + Duration::minutes(STATE_LIFETIME) < Utc::now()
+    }
+
+    /// Adds this [OauthState] to the [Store], clearing out any expired states
+    pub async fn create(&self, store: &dyn Store) -> AuthResult<(), String> {
+        store
+            .create_oauth_state(self, Utc::now() - Duration::minutes(STATE_LIFETIME))
+            .await
+    }
+
+    /// Removes and returns the [OauthState] for a given `state` so that it can
+    /// only ever be used once, erroring if it's unknown, replayed, expired, for
+    /// a provider of another org, which is left untouched, or if `binding`
+    /// isn't the one given when it was created
+    pub async fn consume(
+        store: &dyn Store,
+        org_id: Uuid,
+        state: &str,
+        binding: &str,
+    ) -> AuthResult<Self, String> {
+        let got = store
+            .take_oauth_state(org_id, state)
+            .await?
+            .ok_or_else(|| AuthError::new(UserError::StateInvalid, None))?;
+
+        if got.binding_hash != hash_token(binding) {
+            Err(AuthError::new(UserError::BindingInvalid, None))
+        } else if got.is_expired() {
+            Err(AuthError::new(UserError::StateExpired, None))
+        } else {
+            Ok(got)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{fixture, Provider};
+
+    #[test]
+    fn expiry() {
+        let (mut state, _) = OauthState::new(0);
+        assert!(!state.is_expired());
+
+        state.created = Utc::now() - Duration::minutes(STATE_LIFETIME + 1);
+        assert!(state.is_expired());
+    }
+
+    #[test]
+    fn unique() {
+        let (state, binding) = OauthState::new(0);
+        assert_ne!(state.state, state.code_verifier);
+        assert_ne!(state.state, binding);
+        assert_ne!(state.state, OauthState::new(0).0.state);
+    }
+
+    #[tokio::test]
+    async fn other_org_untouched() {
+        let (store, _, org) = fixture().await;
+        let provider = Provider::new("id", "secret", "domain", None, None, org.id).unwrap();
+        provider.create(&store).await.unwrap();
+
+        let (state, binding) = OauthState::new(provider.key);
+        state.create(&store).await.unwrap();
+        assert_eq!(
+            OauthState::consume(&store, Uuid::new_v4(), &state.state, &binding).await,
+            Err(AuthError::new(UserError::StateInvalid, None))
+        );
+        assert_eq!(
+            OauthState::consume(&store, org.id, &state.state, &binding).await,
+            Ok(state)
+        );
+    }
+
+    #[tokio::test]
+    async fn bound_to_starter() {
+        let (store, _, org) = fixture().await;
+        let provider = Provider::new("id", "secret", "domain", None, None, org.id).unwrap();
+        provider.create(&store).await.unwrap();
+
+        // someone else's binding uses the state up without finishing the flow
+        let (state, binding) = OauthState::new(provider.key);
+        state.create(&store).await.unwrap();
+        let (_, other) = OauthState::new(provider.key);
+        assert_eq!(
+            OauthState::consume(&store, org.id, &state.state, &other).await,
+            Err(AuthError::new(UserError::BindingInvalid, None))
+        );
+        assert_eq!(
+            OauthState::consume(&store, org.id, &state.state, &binding).await,
+            Err(AuthError::new(UserError::StateInvalid, None))
+        );
+    }
+}
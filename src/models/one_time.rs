@@ -0,0 +1,65 @@
+//! Shared parts of models consumed by a single-use token, see [OneTime]
+
+use crate::crypto::{gen_id, gen_token, hash_token};
+use crate::{AuthError, AuthErrorKind, AuthResult};
+use chrono::{prelude::*, Duration};
+use uuid::Uuid;
+
+/// Newly generated single-use token, which is only ever known when created as
+/// just it's hash gets stored
+pub(super) struct OneTimeToken {
+    /// Randomly generated integer primary key for the model holding it
+    pub(super) id: i32,
+    /// The token itself, starting with a prefix telling it's kind apart
+    pub(super) token: String,
+    /// Hash of the token
+    pub(super) hash: Vec<u8>,
+    /// Timestamp after which the token can't be used
+    pub(super) expires: DateTime<Utc>,
+    /// Timestamp of creation
+    pub(super) created: DateTime<Utc>,
+}
+
+impl OneTimeToken {
+    /// Generates a new token starting with `prefix` which lasts for `lifetime`
+    pub(super) fn generate(prefix: &str, lifetime: Duration) -> Self {
+        let token = format!("{}{}", prefix, gen_token());
+        let created = Utc::now();
+
+        Self {
+            id: gen_id(),
+            hash: hash_token(&token),
+            token,
+            expires: created + lifetime,
+            created,
+        }
+    }
+}
+
+/// Model such as a [PasswordReset](super::PasswordReset) or an
+/// [OrgInvite](super::OrgInvite) which is taken out of the store by the hash
+/// of it's token, so it can only ever be used once
+pub(super) trait OneTime: Sized {
+    /// Gets the timestamp after which this can't be used
+    fn expires(&self) -> DateTime<Utc>;
+
+    /// Checks if this has passed it's [OneTime::expires] time
+    fn is_expired(&self) -> bool {
+        self.expires() <= Utc::now()
+    }
+
+    /// Checks what was taken from the store for a token, erroring with
+    /// `expired` if it's too old or `invalid` if nothing was there
+    fn check_taken<K: Into<AuthErrorKind>>(
+        taken: AuthResult<Option<Self>, i32>,
+        expired: K,
+        invalid: K,
+    ) -> AuthResult<Self, Uuid> {
+        match taken {
+            Ok(Some(taken)) if !taken.is_expired() => Ok(taken),
+            Ok(Some(_)) => Err(AuthError::new(expired, None)),
+            Ok(None) => Err(AuthError::new(invalid, None)),
+            Err(err) => Err(AuthError::new(err.kind, None)),
+        }
+    }
+}
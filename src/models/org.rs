@@ -1,17 +1,105 @@
 //! See [Org] for documentation
 
-use super::IntoModel;
+use super::{ApiKey, OrgMember, Role, Scope, Totp};
 use crate::crypto::Hash;
-use crate::{AuthError, AuthErrorKind, AuthResult, Config, OrgError, UserError};
+use crate::hasher::Hasher;
+use crate::store::Store;
+use crate::{
+    AuthError, AuthErrorKind, AuthResult, Config, MemberError, OrgError, TotpError, UserError,
+};
+use actix_web::{dev::Payload, http::header, FromRequest, HttpRequest};
 use actix_web_httpauth::extractors::basic::BasicAuth;
-use chrono::prelude::*;
-use sqlx::{FromRow, PgPool};
-use std::convert::TryInto;
+use chrono::{prelude::*, Duration};
+use std::future::{ready, Ready};
 use uuid::Uuid;
+use zeroize::Zeroizing;
 
 /// Max length for [Org::name] before erroring
 const MAX_NAME: usize = 32;
 
+/// Scheme of an `Authorization` header using an [ApiKey], matched ignoring case
+const BEARER_SCHEME: &str = "Bearer ";
+
+/// Header holding a one-time or recovery code for logins with a [Totp]
+const OTP_HEADER: &str = "X-OTP";
+
+/// Name of the [ApiKey]s made by [Org::start_session]
+const SESSION_NAME: &str = "session";
+
+/// Minutes an [ApiKey] made by [Org::start_session] lasts for
+const SESSION_LIFETIME: i64 = 60;
+
+/// Credentials for an [Org] from the `Authorization` header, being either the
+/// org password with basic auth or an [ApiKey] as a bearer token
+pub enum OrgAuth {
+    /// Org or member id and password
+    Basic(PasswordAuth),
+    /// Key of an [ApiKey]
+    Bearer(String),
+}
+
+/// Id and password of an org or one of it's [OrgMember]s from basic auth,
+/// alongside a code from the [OTP_HEADER] header if they've enabled a [Totp]
+///
+/// As each code only works once, a login with a [Totp] can only make one
+/// request every 30 seconds, so services should instead use an [ApiKey] or
+/// one made by [Org::start_session]
+pub struct PasswordAuth {
+    basic: BasicAuth,
+    otp: Option<String>,
+}
+
+impl PasswordAuth {
+    /// Extracts basic auth and the optional code from a request
+    fn extract(req: &HttpRequest, payload: &mut Payload) -> Result<Self, actix_web::Error> {
+        Ok(Self {
+            basic: BasicAuth::from_request(req, payload).into_inner()?,
+            otp: req
+                .headers()
+                .get(OTP_HEADER)
+                .and_then(|val| val.to_str().ok())
+                .map(str::to_string),
+        })
+    }
+}
+
+impl FromRequest for PasswordAuth {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        ready(Self::extract(req, payload))
+    }
+}
+
+impl FromRequest for OrgAuth {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|val| val.to_str().ok())
+            .and_then(bearer_token);
+
+        ready(match token {
+            Some(token) => Ok(OrgAuth::Bearer(token.to_string())),
+            None => PasswordAuth::extract(req, payload).map(OrgAuth::Basic),
+        })
+    }
+}
+
+/// Gets the token from an `Authorization` header value using the
+/// [BEARER_SCHEME], which like all schemes is case-insensitive
+fn bearer_token(val: &str) -> Option<&str> {
+    let scheme = val.get(..BEARER_SCHEME.len())?;
+    match scheme.eq_ignore_ascii_case(BEARER_SCHEME) {
+        true => Some(val[BEARER_SCHEME.len()..].trim()),
+        false => None,
+    }
+}
+
 /// Top-level organisation which groups users into logical units
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Org {
@@ -21,115 +109,406 @@ pub struct Org {
     pub name: String,
     /// Hashed password and salt contained in the [struct@Hash] structure
     pub password: Hash,
+    /// If password logins must have a confirmed [Totp], with those who don't
+    /// only able to enroll
+    pub totp_required: bool,
     /// Timestamp of creation
     pub created: DateTime<Utc>,
 }
 
 impl Org {
     /// Creates a new [Org] and validates contents, does not add to db
-    pub fn new(
+    pub async fn new(
         config: &Config,
+        hasher: &Hasher,
         name: impl Into<String>,
-        password: impl AsRef<[u8]>,
+        password: &str,
     ) -> AuthResult<Self, Uuid> {
         let id = Uuid::new_v4();
+        let name = validate_name(name.into(), &id)?;
 
         Ok(Self {
-            id: id.clone(),
-            name: validate_name(name.into(), &id)?,
-            password: match Hash::from_password(config, password) {
+            id,
+            name,
+            password: match hasher.hash(config, password).await {
                 Ok(hash) => hash,
-                Err(err) => return Err(AuthError::new(AuthErrorKind::HashError(err), id)),
+                Err(err) => return Err(AuthError::new(err, id)),
             },
+            totp_required: false,
             created: Utc::now(),
         })
     }
 
-    /// Get an organisation from provided basic auth
-    pub fn from_auth(_auth: BasicAuth) -> AuthResult<Self, Uuid> {
-        todo!("get org from auth")
+    /// Adds this [Org] to the [Store]
+    pub async fn create(&self, store: &dyn Store) -> AuthResult<(), Uuid> {
+        store.create_org(self).await
+    }
+
+    /// Adds this [Org] to the [Store] alongside it's first owner, who gets a
+    /// hash of their own even if their password is the same as the org's
+    pub async fn create_with_owner(
+        &self,
+        store: &dyn Store,
+        config: &Config,
+        hasher: &Hasher,
+        owner_name: &str,
+        owner_password: &str,
+    ) -> AuthResult<OrgMember, Uuid> {
+        let password = hasher
+            .hash(config, owner_password)
+            .await
+            .map_err(|err| AuthError::new(err, self.id))?;
+        let owner = OrgMember::new(owner_name, password, Role::Owner, self.id)?;
+        self.create(store).await?;
+
+        match owner.create(store).await {
+            Ok(()) => Ok(owner),
+            Err(err) => {
+                // an org can't be left without an owner
+                self.delete(store).await.ok();
+                Err(err)
+            }
+        }
     }
 
-    /// Authorizes organisation and deletes all in one
-    pub fn auth_delete(_pool: &PgPool, _auth: BasicAuth) -> AuthResult<(), Uuid> {
-        todo!("get org from auth and delete at same time")
+    /// Gets an [Org] from the [Store] by it's [Org::id]
+    pub async fn get(store: &dyn Store, id: Uuid) -> AuthResult<Self, Uuid> {
+        store.get_org(id).await
     }
 
-    /// Authorizes organisation and patches with given values all in one
-    pub fn auth_patch(
-        _pool: &PgPool,
-        _auth: BasicAuth,
+    /// Get an organisation from provided [OrgAuth] credentials which must hold
+    /// `scope`, erroring with an identical [OrgError::Unauthorized] whether the
+    /// org, member or key doesn't exist or the password is wrong so that
+    /// existence isn't leaked
+    pub async fn from_auth(
+        store: &dyn Store,
+        config: &Config,
+        hasher: &Hasher,
+        auth: OrgAuth,
+        scope: Scope,
+    ) -> AuthResult<Self, Uuid> {
+        let (org, scopes) = match auth {
+            OrgAuth::Basic(auth) => Self::from_password(store, config, hasher, auth).await?,
+            OrgAuth::Bearer(token) => Self::from_key(store, &token).await?,
+        };
+
+        if scope.allowed_by(&scopes) {
+            Ok(org)
+        } else {
+            Err(AuthError::new(OrgError::MissingScope(scope), org.id))
+        }
+    }
+
+    /// Get an organisation and the scopes held from it's [ApiKey], erroring if
+    /// it's unknown or expired
+    async fn from_key(store: &dyn Store, token: &str) -> AuthResult<(Self, Vec<Scope>), Uuid> {
+        match ApiKey::authenticate(store, token).await {
+            Ok(Some(api_key)) => Ok((Self::get(store, api_key.org_id).await?, api_key.scopes)),
+            Ok(None) => Err(AuthError::new(OrgError::Unauthorized, None)),
+            Err(err) => Err(AuthError::new(err.kind, None)),
+        }
+    }
+
+    /// Get an organisation and the scopes held from the id and password of
+    /// either the org itself, which holds every scope, or one of it's
+    /// [OrgMember]s, which hold those of their [Role](super::Role), erroring
+    /// if the org requires a [Totp] which the login hasn't enabled
+    async fn from_password(
+        store: &dyn Store,
+        config: &Config,
+        hasher: &Hasher,
+        auth: PasswordAuth,
+    ) -> AuthResult<(Self, Vec<Scope>), Uuid> {
+        let (login, two_factor) = Login::authenticate(store, config, hasher, &auth).await?;
+        let (org, scopes) = match login {
+            Login::Org(org) => (org, vec![Scope::OrgAdmin]),
+            Login::Member(member) => (Self::get(store, member.org_id).await?, member.role.scopes()),
+        };
+
+        if org.totp_required && !two_factor {
+            Err(AuthError::new(TotpError::Required, org.id))
+        } else {
+            Ok((org, scopes))
+        }
+    }
+
+    /// Deletes this [Org] from the [Store], along with everything it owns
+    pub async fn delete(&self, store: &dyn Store) -> AuthResult<(), Uuid> {
+        store.delete_org(self.id).await
+    }
+
+    /// Patches this [Org] with given values and saves it, with passwords only
+    /// changed through [Org::change_password] or a password reset
+    pub async fn patch(
+        &mut self,
+        store: &dyn Store,
         new_name: Option<String>,
-        new_password: Option<String>,
+        new_totp_required: Option<bool>,
     ) -> AuthResult<(), Uuid> {
         let mut changed = false;
 
         if let Some(name) = new_name {
             changed = true;
-            todo!("name")
+            self.name = validate_name(name, &self.id)?;
         }
 
-        if let Some(password) = new_password {
+        if let Some(totp_required) = new_totp_required {
             changed = true;
-            todo!("password")
+            self.totp_required = totp_required;
         }
 
         if !changed {
-            Err(AuthError::new(OrgError::NothingToPatch, Uuid::new_v4())) // TODO: put auth in uuid slot
+            Err(AuthError::new(OrgError::NothingToPatch, self.id))
         } else {
-            Ok(())
+            store.update_org(self).await
+        }
+    }
+
+    /// Changes the password of whichever org or [OrgMember] the basic auth
+    /// credentials belong to, which requires knowing the current password
+    pub async fn change_password(
+        store: &dyn Store,
+        config: &Config,
+        hasher: &Hasher,
+        auth: PasswordAuth,
+        new_password: &str,
+    ) -> AuthResult<(), Uuid> {
+        let (mut login, _) = Login::authenticate(store, config, hasher, &auth).await?;
+        let password = hasher
+            .hash(config, new_password)
+            .await
+            .map_err(|err| AuthError::new(err, login.id()))?;
+        login.set_password(store, password).await
+    }
+
+    /// Starts enrolling whichever org or [OrgMember] logs in into [Totp],
+    /// replacing any earlier unconfirmed enrollment
+    pub async fn enroll_totp(
+        store: &dyn Store,
+        config: &Config,
+        hasher: &Hasher,
+        auth: PasswordAuth,
+    ) -> AuthResult<Totp, Uuid> {
+        let (login, two_factor) = Login::authenticate(store, config, hasher, &auth).await?;
+        if two_factor {
+            return Err(AuthError::new(TotpError::AlreadyEnabled, login.id()));
+        }
+
+        let totp = Totp::new(login.id(), login.org_id());
+        totp.create(store).await?;
+        Ok(totp)
+    }
+
+    /// Enables the [Totp] being enrolled by whichever org or [OrgMember] logs
+    /// in using a code from it, giving recovery codes
+    pub async fn confirm_totp(
+        store: &dyn Store,
+        config: &Config,
+        hasher: &Hasher,
+        auth: PasswordAuth,
+        code: &str,
+    ) -> AuthResult<Vec<String>, Uuid> {
+        let (login, _) = Login::authenticate(store, config, hasher, &auth).await?;
+
+        match Totp::get(store, login.id()).await? {
+            Some(mut totp) => totp.confirm(store, code).await,
+            None => Err(AuthError::new(TotpError::NotFound, login.id())),
         }
     }
+
+    /// Disables the [Totp] of whichever org or [OrgMember] logs in, which
+    /// needs a code from it as it's already enabled
+    pub async fn disable_totp(
+        store: &dyn Store,
+        config: &Config,
+        hasher: &Hasher,
+        auth: PasswordAuth,
+    ) -> AuthResult<(), Uuid> {
+        let (login, _) = Login::authenticate(store, config, hasher, &auth).await?;
+
+        match Totp::get(store, login.id()).await? {
+            Some(totp) => totp.delete(store).await,
+            None => Err(AuthError::new(TotpError::NotFound, login.id())),
+        }
+    }
+
+    /// Trades the password login of an org or [OrgMember], which uses up a
+    /// one-time code if they've enabled a [Totp], for an [ApiKey] with the
+    /// same scopes lasting [SESSION_LIFETIME] minutes, clearing out expired
+    /// ones made earlier
+    pub async fn start_session(
+        store: &dyn Store,
+        config: &Config,
+        hasher: &Hasher,
+        auth: PasswordAuth,
+    ) -> AuthResult<(ApiKey, String), Uuid> {
+        let (org, scopes) = Self::from_password(store, config, hasher, auth).await?;
+        let to_org = |err: AuthError<i32>| AuthError::new(err.kind, org.id);
+
+        for api_key in ApiKey::list(store, org.id).await.map_err(to_org)? {
+            if api_key.name == SESSION_NAME && api_key.is_expired() {
+                ApiKey::delete(store, org.id, api_key.id)
+                    .await
+                    .map_err(to_org)?;
+            }
+        }
+
+        let expires = Utc::now() + Duration::minutes(SESSION_LIFETIME);
+        let (api_key, key) =
+            ApiKey::new(SESSION_NAME, scopes, Some(expires), org.id).map_err(to_org)?;
+        api_key.create(store).await.map_err(to_org)?;
+        Ok((api_key, key))
+    }
+
+    /// Replaces [Org::password] with an already made hash and saves it
+    pub(crate) async fn set_password(
+        &mut self,
+        store: &dyn Store,
+        password: Hash,
+    ) -> AuthResult<(), Uuid> {
+        store.set_org_password(self.id, &password).await?;
+        self.password = password;
+        Ok(())
+    }
+
+    /// Validates all contents before storing
+    pub(crate) fn validate(&self) -> AuthResult<(), Uuid> {
+        validate_name(self.name.clone(), &self.id).map(|_| ())
+    }
 }
 
-/// Internal sqlx mapping for the [Org] model
-#[derive(FromRow)]
-struct OrgInternal {
-    id: Uuid,
-    name: String,
-    pw_hash: Vec<u8>,
-    pw_salt: Vec<u8>,
-    pw_created: DateTime<Utc>,
-    created: DateTime<Utc>,
+/// Holder of a password which can log in as an [Org] with basic auth
+enum Login {
+    /// The org itself using the org password
+    Org(Org),
+    /// One of the org's members using their own password
+    Member(OrgMember),
 }
 
-impl IntoModel<Org, Uuid> for OrgInternal {
-    fn into_model(self) -> AuthResult<Org, Uuid> {
-        Ok(Org {
-            id: self.id,
-            name: self.name,
-            password: Hash {
-                inner: self.pw_hash,
-                salt: match self.pw_salt.try_into() {
-                    Ok(salt) => salt,
-                    Err(_) => {
-                        return Err(AuthError::new(
-                            AuthErrorKind::DatabaseError("salt length invalid for org".to_string()),
-                            self.id,
-                        ))
-                    }
-                },
-                created: self.pw_created,
-            },
-            created: self.created,
-        })
+impl Login {
+    /// Checks the id and password of basic auth against whichever org or
+    /// member has that id, upgrading it's hash if the parameters changed, then
+    /// checks the code of it's [Totp] if enabled, giving if one was checked
+    async fn authenticate(
+        store: &dyn Store,
+        config: &Config,
+        hasher: &Hasher,
+        auth: &PasswordAuth,
+    ) -> AuthResult<(Self, bool), Uuid> {
+        let password = auth.basic.password().unwrap_or_default();
+        let login = match Uuid::parse_str(auth.basic.user_id()) {
+            Ok(id) => Login::find(store, id).await?,
+            Err(_) => None,
+        };
+
+        let mut login = match login {
+            Some(login) => login,
+            None => {
+                // hash anyway so timing matches that of an existing org
+                let (params, pepper) = (config.hash, config.pepper.clone());
+                let password = Zeroizing::new(password.to_string());
+                hasher
+                    .run(move || Hash::new(&params, &pepper, password.as_str(), None).ok())
+                    .await
+                    .map_err(|err| AuthError::new(err, None))?;
+                return Err(AuthError::new(OrgError::Unauthorized, None));
+            }
+        };
+
+        match hasher.compare(config, login.password(), password).await {
+            Ok(true) => (),
+            Ok(false) => return Err(AuthError::new(OrgError::Unauthorized, None)),
+            Err(err) => return Err(AuthError::new(err, None)),
+        }
+
+        if login.password().needs_rehash(&config.hash) {
+            // login is still valid if upgrading fails, retried next login
+            login.rehash(store, config, hasher, password).await.ok();
+        }
+
+        match Totp::get(store, login.id()).await? {
+            Some(totp) if totp.confirmed => {
+                totp.verify(store, auth.otp.as_deref()).await?;
+                Ok((login, true))
+            }
+            _ => Ok((login, false)),
+        }
     }
-}
 
-impl IntoModel<OrgInternal, Uuid> for Org {
-    fn into_model(self) -> AuthResult<OrgInternal, Uuid> {
-        Ok(OrgInternal {
-            id: self.id,
-            name: validate_name(self.name, &self.id)?,
-            pw_hash: self.password.inner,
-            pw_salt: self.password.salt.to_vec(),
-            pw_created: self.password.created,
-            created: self.created,
-        })
+    /// Finds whichever org or member has the given id, if any
+    async fn find(store: &dyn Store, id: Uuid) -> AuthResult<Option<Self>, Uuid> {
+        match Org::get(store, id).await {
+            Ok(org) => return Ok(Some(Login::Org(org))),
+            Err(AuthError {
+                kind: AuthErrorKind::OrgError(OrgError::NotFound),
+                ..
+            }) => (),
+            Err(err) => return Err(err),
+        }
+
+        match store.get_member(id).await {
+            Ok(member) => Ok(Some(Login::Member(member))),
+            Err(AuthError {
+                kind: AuthErrorKind::MemberError(MemberError::NotFound),
+                ..
+            }) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Gets the id of the org or member
+    fn id(&self) -> Uuid {
+        match self {
+            Login::Org(org) => org.id,
+            Login::Member(member) => member.id,
+        }
+    }
+
+    /// Gets the id of the org which is logged into
+    fn org_id(&self) -> Uuid {
+        match self {
+            Login::Org(org) => org.id,
+            Login::Member(member) => member.org_id,
+        }
+    }
+
+    /// Gets the password hash to compare against
+    fn password(&self) -> &Hash {
+        match self {
+            Login::Org(org) => &org.password,
+            Login::Member(member) => &member.password,
+        }
+    }
+
+    /// Remakes the password hash with the current [Config::hash] parameters,
+    /// keeping it's creation time
+    async fn rehash(
+        &mut self,
+        store: &dyn Store,
+        config: &Config,
+        hasher: &Hasher,
+        password: &str,
+    ) -> AuthResult<(), Uuid> {
+        let hash = Hash {
+            created: self.password().created,
+            ..hasher
+                .hash(config, password)
+                .await
+                .map_err(|err| AuthError::new(err, self.id()))?
+        };
+        self.set_password(store, hash).await
+    }
+
+    /// Replaces the password hash and saves it
+    async fn set_password(&mut self, store: &dyn Store, password: Hash) -> AuthResult<(), Uuid> {
+        match self {
+            Login::Org(org) => org.set_password(store, password).await,
+            Login::Member(member) => member.set_password(store, password).await,
+        }
     }
 }
 
-/// Validates [Org::name]/[OrgInternal::name] element
+/// Validates [Org::name] element
 fn validate_name(name: String, id: &Uuid) -> AuthResult<String, Uuid> {
     if name.len() > MAX_NAME {
         Err(AuthError::new(UserError::NameTooLong, *id))
@@ -137,3 +516,64 @@ fn validate_name(name: String, id: &Uuid) -> AuthResult<String, Uuid> {
         Ok(name)
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use actix_web_httpauth::headers::authorization::Basic;
+
+    /// Creates an org with the password `password` within `store`
+    pub(crate) async fn create_org(store: &dyn Store, config: &Config) -> Org {
+        let org = Org::new(config, &Hasher::new(&config.hash), "org", "password")
+            .await
+            .unwrap();
+        org.create(store).await.unwrap();
+        org
+    }
+
+    /// Basic auth credentials of an org or member without a one-time code
+    pub(crate) fn basic_auth(id: Uuid, password: &str) -> OrgAuth {
+        OrgAuth::Basic(PasswordAuth {
+            basic: Basic::new(id.to_string(), Some(password.to_string())).into(),
+            otp: None,
+        })
+    }
+
+    /// Memory store alongside the test config and an org created within it
+    pub(crate) async fn fixture() -> (MemoryStore, Config, Org) {
+        let (store, config) = (MemoryStore::new(), Config::test());
+        let org = create_org(&store, &config).await;
+        (store, config, org)
+    }
+
+    #[tokio::test]
+    async fn crud() {
+        let (store, _, mut org) = fixture().await;
+        assert_eq!(Org::get(&store, org.id).await, Ok(org.clone()));
+
+        assert!(org.patch(&store, None, None).await.is_err());
+        org.patch(&store, Some("renamed".into()), None)
+            .await
+            .unwrap();
+        assert_eq!(Org::get(&store, org.id).await.unwrap().name, "renamed");
+
+        org.delete(&store).await.unwrap();
+        assert!(matches!(
+            Org::get(&store, org.id).await,
+            Err(AuthError {
+                kind: AuthErrorKind::OrgError(OrgError::NotFound),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn bearer_scheme() {
+        assert_eq!(bearer_token("Bearer key"), Some("key"));
+        assert_eq!(bearer_token("bearer key"), Some("key"));
+        assert_eq!(bearer_token("BEARER  key"), Some("key"));
+        assert_eq!(bearer_token("Basic a2V5Og=="), None);
+        assert_eq!(bearer_token("Bearer"), None);
+    }
+}
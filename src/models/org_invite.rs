@@ -0,0 +1,123 @@
+//! See [OrgInvite] for documentation
+
+use super::one_time::{OneTime, OneTimeToken};
+use super::org_member::{validate_name, Role};
+use super::OrgMember;
+use crate::crypto::hash_token;
+use crate::hasher::Hasher;
+use crate::store::Store;
+use crate::{AuthError, AuthResult, Config, MemberError};
+use chrono::{prelude::*, Duration};
+use uuid::Uuid;
+
+/// Amount of days an [OrgInvite] may be accepted for before expiring
+const INVITE_LIFETIME: i64 = 7;
+
+/// Prefix of every generated invite token, telling them apart from api keys
+const TOKEN_PREFIX: &str = "authrio_invite_";
+
+/// One-time invitation to join an [Org](super::Org) as a new [OrgMember] with
+/// a given [Role], accepted by whoever holds the token
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OrgInvite {
+    /// Randomly generated integer primary key
+    pub id: i32,
+    /// Hash of the token, which itself is only known when created
+    pub token_hash: Vec<u8>,
+    /// Role the new member will have
+    pub role: Role,
+    /// The [Org](super::Org) this invites to
+    pub org_id: Uuid,
+    /// Timestamp after which this invite can't be accepted
+    pub expires: DateTime<Utc>,
+    /// Timestamp of creation
+    pub created: DateTime<Utc>,
+}
+
+impl OrgInvite {
+    /// Creates a new [OrgInvite], does not add to the [Store], returning the
+    /// token to hand to whoever is invited
+    pub fn new(role: Role, org_id: Uuid) -> (Self, String) {
+        let token = OneTimeToken::generate(TOKEN_PREFIX, Duration::days(INVITE_LIFETIME));
+
+        (
+            Self {
+                id: token.id,
+                token_hash: token.hash,
+                role,
+                org_id,
+                expires: token.expires,
+                created: token.created,
+            },
+            token.token,
+        )
+    }
+
+    /// Adds this [OrgInvite] to the [Store], clearing out any expired invites
+    pub async fn create(&self, store: &dyn Store) -> AuthResult<(), i32> {
+        store.create_invite(self, Utc::now()).await
+    }
+
+    /// Consumes the invite for a given token so it can only ever be used once,
+    /// adding a new [OrgMember] with it's [Role] to the [Store]
+    pub async fn accept(
+        store: &dyn Store,
+        config: &Config,
+        hasher: &Hasher,
+        token: &str,
+        name: String,
+        password: &str,
+    ) -> AuthResult<OrgMember, Uuid> {
+        // validate and hash first so a bad request doesn't use up the invite
+        let name =
+            validate_name(name, &Uuid::nil()).map_err(|err| AuthError::new(err.kind, None))?;
+        let password = hasher
+            .hash(config, password)
+            .await
+            .map_err(|err| AuthError::new(err, None))?;
+
+        let invite = Self::check_taken(
+            store.take_invite(&hash_token(token)).await,
+            MemberError::InviteExpired,
+            MemberError::InviteInvalid,
+        )?;
+
+        let member = OrgMember::new(name, password, invite.role, invite.org_id)?;
+        member.create(store).await?;
+        Ok(member)
+    }
+}
+
+impl OneTime for OrgInvite {
+    fn expires(&self) -> DateTime<Utc> {
+        self.expires
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture;
+    use crate::AuthErrorKind;
+
+    #[tokio::test]
+    async fn single_use() {
+        let (store, config, org) = fixture().await;
+        let hasher = Hasher::new(&config.hash);
+
+        let (invite, token) = OrgInvite::new(Role::Admin, org.id);
+        invite.create(&store).await.unwrap();
+
+        let member = OrgInvite::accept(&store, &config, &hasher, &token, "bob".into(), "pw")
+            .await
+            .unwrap();
+        assert_eq!((member.role, member.org_id), (Role::Admin, org.id));
+        assert!(matches!(
+            OrgInvite::accept(&store, &config, &hasher, &token, "eve".into(), "pw").await,
+            Err(AuthError {
+                kind: AuthErrorKind::MemberError(MemberError::InviteInvalid),
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+//! See [OrgMember] for documentation
+
+use super::Scope;
+use crate::crypto::Hash;
+use crate::store::Store;
+use crate::{AuthError, AuthResult, MemberError};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+use uuid::Uuid;
+
+/// Max length for [OrgMember::name] before erroring
+const MAX_NAME: usize = 32;
+
+/// Role of an [OrgMember], deciding which [Scope]s their credentials hold
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Everything, including managing members and deleting the org
+    Owner,
+    /// Managing providers and users, but not the org itself
+    Admin,
+    /// Viewing providers and users
+    Viewer,
+}
+
+impl Role {
+    /// Gets the name of this role, such as `owner`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Admin => "admin",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    /// Gets the [Scope]s held by members with this role
+    pub fn scopes(&self) -> Vec<Scope> {
+        match self {
+            Role::Owner => vec![Scope::OrgAdmin],
+            Role::Admin => vec![
+                Scope::ProvidersRead,
+                Scope::ProvidersWrite,
+                Scope::UsersRead,
+                Scope::UsersWrite,
+                Scope::UsersDelete,
+            ],
+            Role::Viewer => vec![Scope::ProvidersRead, Scope::UsersRead],
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        [Role::Owner, Role::Admin, Role::Viewer]
+            .iter()
+            .find(|role| role.as_str() == input)
+            .copied()
+            .ok_or_else(|| format!("unknown role '{}'", input))
+    }
+}
+
+/// Person within an [Org](super::Org) with their own credentials, logging in
+/// with basic auth using their [OrgMember::id] and password
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OrgMember {
+    /// Unique member primary key uuid
+    pub id: Uuid,
+    /// Name to tell members apart, which doesn't need to be unique
+    pub name: String,
+    /// Hashed password and salt contained in the [struct@Hash] structure
+    pub password: Hash,
+    /// Role deciding what this member can do, see [Role::scopes]
+    pub role: Role,
+    /// The [Org](super::Org) this member belongs to
+    pub org_id: Uuid,
+    /// Timestamp of creation
+    pub created: DateTime<Utc>,
+}
+
+impl OrgMember {
+    /// Creates a new [OrgMember] from an already hashed password and validates
+    /// contents, does not add to db
+    pub fn new(
+        name: impl Into<String>,
+        password: Hash,
+        role: Role,
+        org_id: Uuid,
+    ) -> AuthResult<Self, Uuid> {
+        let id = Uuid::new_v4();
+
+        Ok(Self {
+            id,
+            name: validate_name(name.into(), &id)?,
+            password,
+            role,
+            org_id,
+            created: Utc::now(),
+        })
+    }
+
+    /// Adds this [OrgMember] to the [Store]
+    pub async fn create(&self, store: &dyn Store) -> AuthResult<(), Uuid> {
+        store.create_member(self).await
+    }
+
+    /// Gets an [OrgMember] of an org by it's [OrgMember::id]
+    pub async fn get(store: &dyn Store, org_id: Uuid, id: Uuid) -> AuthResult<Self, Uuid> {
+        match store.get_member(id).await? {
+            member if member.org_id == org_id => Ok(member),
+            _ => Err(AuthError::new(MemberError::NotFound, id)),
+        }
+    }
+
+    /// Gets all [OrgMember]s of an org, oldest first
+    pub async fn list(store: &dyn Store, org_id: Uuid) -> AuthResult<Vec<Self>, Uuid> {
+        store.list_members(org_id).await
+    }
+
+    /// Changes the [Role] of this member, erroring if they're the last owner
+    pub async fn set_role(&mut self, store: &dyn Store, role: Role) -> AuthResult<(), Uuid> {
+        store.set_member_role(self.org_id, self.id, role).await?;
+        self.role = role;
+        Ok(())
+    }
+
+    /// Removes this member from their org, erroring if they're the last owner
+    pub async fn delete(&self, store: &dyn Store) -> AuthResult<(), Uuid> {
+        store.delete_member(self.org_id, self.id).await
+    }
+
+    /// Replaces [OrgMember::password] with an already made hash and saves it
+    pub(crate) async fn set_password(
+        &mut self,
+        store: &dyn Store,
+        password: Hash,
+    ) -> AuthResult<(), Uuid> {
+        store.set_member_password(self.id, &password).await?;
+        self.password = password;
+        Ok(())
+    }
+
+    /// Validates all contents before storing
+    pub(crate) fn validate(&self) -> AuthResult<(), Uuid> {
+        validate_name(self.name.clone(), &self.id).map(|_| ())
+    }
+}
+
+/// Validates [OrgMember::name] element
+pub(super) fn validate_name(name: String, id: &Uuid) -> AuthResult<String, Uuid> {
+    if name.len() > MAX_NAME {
+        Err(AuthError::new(MemberError::NameTooLong, *id))
+    } else {
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture;
+    use crate::AuthErrorKind;
+
+    #[tokio::test]
+    async fn last_owner() {
+        let (store, _, org) = fixture().await;
+
+        let mut owner = OrgMember::new("alice", org.password.clone(), Role::Owner, org.id).unwrap();
+        owner.create(&store).await.unwrap();
+        let is_last_owner = |result: AuthResult<(), Uuid>| {
+            matches!(
+                result,
+                Err(AuthError {
+                    kind: AuthErrorKind::MemberError(MemberError::LastOwner),
+                    ..
+                })
+            )
+        };
+        assert!(is_last_owner(owner.set_role(&store, Role::Admin).await));
+        assert!(is_last_owner(owner.delete(&store).await));
+
+        let mut other = OrgMember::new("bob", org.password.clone(), Role::Viewer, org.id).unwrap();
+        other.create(&store).await.unwrap();
+        other.set_role(&store, Role::Owner).await.unwrap();
+        owner.set_role(&store, Role::Viewer).await.unwrap();
+        assert!(is_last_owner(other.delete(&store).await));
+        owner.delete(&store).await.unwrap();
+
+        assert_eq!(OrgMember::list(&store, org.id).await, Ok(vec![other]));
+    }
+}
@@ -0,0 +1,207 @@
+//! See [PasswordReset] for documentation
+
+use super::one_time::{OneTime, OneTimeToken};
+use super::Org;
+use crate::crypto::hash_token;
+use crate::hasher::Hasher;
+use crate::notifier::Notifier;
+use crate::store::Store;
+use crate::{AuthError, AuthErrorKind, AuthResult, Config, OrgError};
+use chrono::{prelude::*, Duration};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Amount of minutes a [PasswordReset] may be used for before expiring
+const RESET_LIFETIME: i64 = 30;
+
+/// Prefix of every generated reset token, telling them apart from api keys
+const TOKEN_PREFIX: &str = "authrio_reset_";
+
+/// Single-use and short-lived permission to set a new shared password for an
+/// [Org] which has lost it, delivered through a [Notifier]. This never touches
+/// the passwords of it's [OrgMember](super::OrgMember)s, who recover by
+/// being removed and invited again by an owner
+#[derive(FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct PasswordReset {
+    /// Randomly generated integer primary key
+    pub id: i32,
+    /// Hash of the token, which itself is only known when created
+    pub token_hash: Vec<u8>,
+    /// The [Org] whose password this resets
+    pub org_id: Uuid,
+    /// Timestamp after which this reset can't be used
+    pub expires: DateTime<Utc>,
+    /// Timestamp of creation
+    pub created: DateTime<Utc>,
+}
+
+impl PasswordReset {
+    /// Creates a new [PasswordReset], does not add to the [Store], returning
+    /// the token for the [Notifier] to deliver
+    pub fn new(org_id: Uuid) -> (Self, String) {
+        let token = OneTimeToken::generate(TOKEN_PREFIX, Duration::minutes(RESET_LIFETIME));
+
+        (
+            Self {
+                id: token.id,
+                token_hash: token.hash,
+                org_id,
+                expires: token.expires,
+                created: token.created,
+            },
+            token.token,
+        )
+    }
+
+    /// Starts a reset for an org, delivering the token through the [Notifier],
+    /// doing nothing if the org doesn't exist or already has an unexpired reset
+    /// so requests can't spam the notifier or invalidate a token. Failures
+    /// past finding the org are only logged, so they can't tell if it exists
+    pub async fn request(
+        store: &dyn Store,
+        notifier: &dyn Notifier,
+        org_id: Uuid,
+    ) -> AuthResult<(), Uuid> {
+        let org = match Org::get(store, org_id).await {
+            Ok(org) => org,
+            Err(AuthError {
+                kind: AuthErrorKind::OrgError(OrgError::NotFound),
+                ..
+            }) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        let (reset, token) = Self::new(org.id);
+        match store.create_password_reset(&reset, Utc::now()).await {
+            Ok(true) => (),
+            Ok(false) => return Ok(()),
+            Err(err) => {
+                eprintln!("❌ Couldn't store password reset, {}", err.kind);
+                return Ok(());
+            }
+        }
+
+        if let Err(err) = notifier.password_reset(&org, &token, reset.expires).await {
+            eprintln!("❌ Couldn't deliver password reset, {}", err);
+            // undelivered resets would otherwise block new requests until expiring
+            store.take_password_reset(&reset.token_hash).await.ok();
+        }
+        Ok(())
+    }
+
+    /// Consumes the reset for a given token so it can only ever be used once,
+    /// setting a new password for it's org and optionally revoking all of the
+    /// org's [ApiKey](super::ApiKey)s in case they were leaked alongside it
+    pub async fn confirm(
+        store: &dyn Store,
+        config: &Config,
+        hasher: &Hasher,
+        token: &str,
+        password: &str,
+        revoke_keys: bool,
+    ) -> AuthResult<Org, Uuid> {
+        // hash first so a busy server doesn't use up the token
+        let password = hasher
+            .hash(config, password)
+            .await
+            .map_err(|err| AuthError::new(err, None))?;
+
+        let reset = Self::check_taken(
+            store.take_password_reset(&hash_token(token)).await,
+            OrgError::ResetExpired,
+            OrgError::ResetInvalid,
+        )?;
+
+        let mut org = Org::get(store, reset.org_id).await?;
+        org.set_password(store, password).await?;
+
+        if revoke_keys {
+            store
+                .delete_api_keys(org.id)
+                .await
+                .map_err(|err| AuthError::new(err.kind, org.id))?;
+        }
+
+        Ok(org)
+    }
+}
+
+impl OneTime for PasswordReset {
+    fn expires(&self) -> DateTime<Utc> {
+        self.expires
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{basic_auth, ApiKey, Scope};
+    use crate::notifier::FileNotifier;
+    use crate::store::MemoryStore;
+
+    #[tokio::test]
+    async fn reset() {
+        let (store, config) = (MemoryStore::new(), Config::test());
+        let hasher = Hasher::new(&config.hash);
+        // an owner made without a password of their own starts out with the org's
+        let org = Org::new(&config, &hasher, "acme", "password")
+            .await
+            .unwrap();
+        let owner = org
+            .create_with_owner(&store, &config, &hasher, "owner", "password")
+            .await
+            .unwrap();
+        let (api_key, _) = ApiKey::new("service", vec![Scope::UsersRead], None, org.id).unwrap();
+        api_key.create(&store).await.unwrap();
+
+        let path = std::env::temp_dir().join(format!("authrio-reset-{}", std::process::id()));
+        let notifier = FileNotifier::new(&path);
+        PasswordReset::request(&store, &notifier, Uuid::new_v4())
+            .await
+            .unwrap();
+        let broken = FileNotifier::new(path.join("missing"));
+        PasswordReset::request(&store, &broken, org.id)
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            PasswordReset::request(&store, &notifier, org.id)
+                .await
+                .unwrap();
+        }
+        let line = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(line.lines().count(), 1);
+        std::fs::remove_file(&path).unwrap();
+        let token = serde_json::from_str::<serde_json::Value>(&line).unwrap()["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let org = PasswordReset::confirm(&store, &config, &hasher, &token, "new", true)
+            .await
+            .unwrap();
+        assert_eq!(
+            hasher.compare(&config, &org.password, "new").await,
+            Ok(true)
+        );
+        assert_eq!(ApiKey::list(&store, org.id).await, Ok(vec![]));
+        let login = |password| {
+            Org::from_auth(
+                &store,
+                &config,
+                &hasher,
+                basic_auth(owner.id, password),
+                Scope::OrgAdmin,
+            )
+        };
+        // resetting the org password leaves member credentials alone
+        assert_eq!(login("password").await, Ok(org.clone()));
+        assert_eq!(
+            login("new").await,
+            Err(AuthError::new(OrgError::Unauthorized, None))
+        );
+        assert_eq!(
+            PasswordReset::confirm(&store, &config, &hasher, &token, "again", false).await,
+            Err(AuthError::new(OrgError::ResetInvalid, None))
+        );
+    }
+}
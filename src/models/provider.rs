@@ -1,8 +1,9 @@
 //! See [Provider] for documentation
 
+use crate::crypto::gen_id;
+use crate::store::Store;
 use crate::{AuthError, AuthResult, ProviderError};
 use chrono::prelude::*;
-use sqlx::FromRow;
 use uuid::Uuid;
 
 /// Maximum allowed size for general medium strings
@@ -11,14 +12,23 @@ const MAX_MED: usize = 64;
 /// Maximum allowed uri size
 const MAX_URI: usize = 2000;
 
+/// Path appended to [Provider::domain] for the authorization endpoint
+const AUTHORIZE_PATH: &str = "/authorize";
+
+/// Path appended to [Provider::domain] for the token endpoint
+const TOKEN_PATH: &str = "/token";
+
 /// Provider explaining the relationship to a service from an org
-#[derive(FromRow, Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Provider {
-    /// The `client_id` unique primary key id
+    /// Randomly generated integer primary key, referenced by
+    /// [UserProvider::provider_id](super::UserProvider::provider_id)
+    pub key: i32,
+    /// The `client_id` identifier, unique within an org
     pub id: String,
     /// Backchannel `client_secret` identifier
     pub secret: String,
-    /// Domain of provider to connect to
+    /// Domain of provider to connect to, used as the base of it's oauth endpoints
     pub domain: String,
     /// Optional but recommended uri to redirect to
     pub redirect_uri: Option<String>,
@@ -31,7 +41,7 @@ pub struct Provider {
 }
 
 impl Provider {
-    /// Creates a new [Provider] and validates contents, does not add to db
+    /// Creates a new [Provider] and validates contents, does not add to the [Store]
     pub fn new<S: Into<String>, Os: Into<Option<String>>>(
         id: S,
         secret: S,
@@ -42,30 +52,155 @@ impl Provider {
     ) -> AuthResult<Self, String> {
         // create
         let got = Self {
+            key: gen_id(),
             id: id.into(),
             secret: secret.into(),
             domain: domain.into(),
             redirect_uri: redirect_uri.into(),
             scope: scope.into(),
-            org_id: org_id.into(),
+            org_id,
             created: Utc::now(),
         };
 
-        // validate
-        validate(&got.id, MAX_MED, ProviderError::IdTooLong, &got.id)?;
-        validate(&got.secret, MAX_MED, ProviderError::SecretTooLong, &got.id)?;
-        validate(&got.domain, MAX_URI, ProviderError::DomainTooLong, &got.id)?;
+        // validate and return
+        got.validate()?;
+        Ok(got)
+    }
+
+    /// Adds this [Provider] to the [Store]
+    pub async fn create(&self, store: &dyn Store) -> AuthResult<(), String> {
+        store.create_provider(self).await
+    }
+
+    /// Gets a [Provider] from the [Store] by it's [Provider::id], scoped to the
+    /// given [Org](super::Org)
+    pub async fn get(
+        store: &dyn Store,
+        org_id: Uuid,
+        id: impl Into<String>,
+    ) -> AuthResult<Self, String> {
+        store.get_provider(org_id, &id.into()).await
+    }
+
+    /// Gets a [Provider] from the [Store] by it's [Provider::key], used when no
+    /// org authentication is present such as redirecting end users
+    pub async fn get_by_key(store: &dyn Store, key: i32) -> AuthResult<Self, String> {
+        store.get_provider_by_key(key).await
+    }
+
+    /// Gets all [Provider]s belonging to the given [Org](super::Org)
+    pub async fn list(store: &dyn Store, org_id: Uuid) -> AuthResult<Vec<Self>, String> {
+        store.list_providers(org_id).await
+    }
+
+    /// Patches this [Provider] with given values and updates the [Store]
+    pub async fn patch(
+        mut self,
+        store: &dyn Store,
+        new_secret: Option<String>,
+        new_domain: Option<String>,
+        new_redirect_uri: Option<String>,
+        new_scope: Option<String>,
+    ) -> AuthResult<Self, String> {
+        let mut changed = false;
 
-        if let Some(val) = &got.redirect_uri {
-            validate(val, MAX_URI, ProviderError::RedirectUriTooLong, &got.id)?;
+        if let Some(secret) = new_secret {
+            changed = true;
+            self.secret = secret;
         }
 
-        if let Some(val) = &got.scope {
-            validate(val, MAX_MED, ProviderError::ScopeTooLong, &got.id)?;
+        if let Some(domain) = new_domain {
+            changed = true;
+            self.domain = domain;
         }
 
-        // return
-        Ok(got)
+        if let Some(redirect_uri) = new_redirect_uri {
+            changed = true;
+            self.redirect_uri = Some(redirect_uri);
+        }
+
+        if let Some(scope) = new_scope {
+            changed = true;
+            self.scope = Some(scope);
+        }
+
+        if !changed {
+            return Err(AuthError::new(ProviderError::NothingToPatch, self.id));
+        }
+
+        self.validate()?;
+        store.update_provider(&self).await?;
+        Ok(self)
+    }
+
+    /// Deletes a [Provider] from the [Store] by it's [Provider::id], scoped to the
+    /// given [Org](super::Org)
+    pub async fn delete(
+        store: &dyn Store,
+        org_id: Uuid,
+        id: impl Into<String>,
+    ) -> AuthResult<(), String> {
+        store.delete_provider(org_id, &id.into()).await
+    }
+
+    /// Builds the url to redirect end users to in order to start an oauth
+    /// authorization code flow, using the `state` and PKCE `code_challenge` from
+    /// an [OauthState](super::OauthState)
+    pub fn authorize_url(&self, state: &str, code_challenge: &str) -> String {
+        let mut query = vec![
+            ("response_type", "code"),
+            ("client_id", self.id.as_str()),
+            ("state", state),
+            ("code_challenge", code_challenge),
+            ("code_challenge_method", "S256"),
+        ];
+
+        if let Some(redirect_uri) = &self.redirect_uri {
+            query.push(("redirect_uri", redirect_uri));
+        }
+
+        if let Some(scope) = &self.scope {
+            query.push(("scope", scope));
+        }
+
+        format!(
+            "{}{}?{}",
+            self.domain.trim_end_matches('/'),
+            AUTHORIZE_PATH,
+            serde_urlencoded::to_string(query).unwrap()
+        )
+    }
+
+    /// Url of the token endpoint for exchanging codes and refreshing tokens
+    pub fn token_url(&self) -> String {
+        format!("{}{}", self.domain.trim_end_matches('/'), TOKEN_PATH)
+    }
+
+    /// Validates lengths of all contents
+    pub(crate) fn validate(&self) -> AuthResult<(), String> {
+        validate(&self.id, MAX_MED, ProviderError::IdTooLong, &self.id)?;
+        validate(
+            &self.secret,
+            MAX_MED,
+            ProviderError::SecretTooLong,
+            &self.id,
+        )?;
+        validate(
+            &self.domain,
+            MAX_URI,
+            ProviderError::DomainTooLong,
+            &self.id,
+        )?;
+
+        if let Some(val) = &self.redirect_uri {
+            validate(val, MAX_URI, ProviderError::RedirectUriTooLong, &self.id)?;
+        }
+
+        if let Some(val) = &self.scope {
+            validate(val, MAX_MED, ProviderError::ScopeTooLong, &self.id)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -77,3 +212,60 @@ fn validate(part: &str, max: usize, err: ProviderError, id: &str) -> AuthResult<
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_validation() {
+        let org_id = Uuid::new_v4();
+        let long = "a".repeat(MAX_MED + 1);
+
+        assert!(Provider::new("id", "secret", "domain", None, None, org_id).is_ok());
+        assert_eq!(
+            Provider::new(
+                long.clone(),
+                "secret".into(),
+                "domain".into(),
+                None,
+                None,
+                org_id
+            ),
+            Err(AuthError::new(ProviderError::IdTooLong, long.clone()))
+        );
+        assert_eq!(
+            Provider::new("id", "secret", "domain", None, Some(long), org_id),
+            Err(AuthError::new(
+                ProviderError::ScopeTooLong,
+                "id".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn urls() {
+        let mut provider = Provider::new(
+            "client id",
+            "secret",
+            "https://example.com/oauth/",
+            None,
+            None,
+            Uuid::new_v4(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            provider.authorize_url("st", "ch"),
+            "https://example.com/oauth/authorize?response_type=code&client_id=client+id&state=st&code_challenge=ch&code_challenge_method=S256"
+        );
+        assert_eq!(provider.token_url(), "https://example.com/oauth/token");
+
+        provider.redirect_uri = Some("https://app.example.com/callback".to_string());
+        provider.scope = Some("read write".to_string());
+        assert_eq!(
+            provider.authorize_url("st", "ch"),
+            "https://example.com/oauth/authorize?response_type=code&client_id=client+id&state=st&code_challenge=ch&code_challenge_method=S256&redirect_uri=https%3A%2F%2Fapp.example.com%2Fcallback&scope=read+write"
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! See [Scope] for documentation
+
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Permission held by an org credential, with the org password holding all of
+/// them and each [ApiKey](super::ApiKey) holding the ones it was created with
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Scope {
+    /// Viewing providers
+    #[serde(rename = "providers:read")]
+    ProvidersRead,
+    /// Creating, changing and deleting providers
+    #[serde(rename = "providers:write")]
+    ProvidersWrite,
+    /// Viewing users and their tokens
+    #[serde(rename = "users:read")]
+    UsersRead,
+    /// Adding users and refreshing their tokens
+    #[serde(rename = "users:write")]
+    UsersWrite,
+    /// Deleting users
+    #[serde(rename = "users:delete")]
+    UsersDelete,
+    /// Changing or deleting the org and managing it's api keys, implying every
+    /// other scope
+    #[serde(rename = "org:admin")]
+    OrgAdmin,
+}
+
+impl Scope {
+    /// Every scope, as held by the org password
+    pub const ALL: [Scope; 6] = [
+        Scope::ProvidersRead,
+        Scope::ProvidersWrite,
+        Scope::UsersRead,
+        Scope::UsersWrite,
+        Scope::UsersDelete,
+        Scope::OrgAdmin,
+    ];
+
+    /// Gets the name of this scope, such as `providers:read`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ProvidersRead => "providers:read",
+            Scope::ProvidersWrite => "providers:write",
+            Scope::UsersRead => "users:read",
+            Scope::UsersWrite => "users:write",
+            Scope::UsersDelete => "users:delete",
+            Scope::OrgAdmin => "org:admin",
+        }
+    }
+
+    /// Checks if holding `scopes` allows this scope
+    pub fn allowed_by(&self, scopes: &[Scope]) -> bool {
+        scopes.contains(self) || scopes.contains(&Scope::OrgAdmin)
+    }
+
+    /// Joins scopes into a space-separated list, like oauth scopes
+    pub fn join(scopes: &[Scope]) -> String {
+        scopes
+            .iter()
+            .map(Scope::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Splits a space-separated list made by [Scope::join]
+    pub fn split(input: &str) -> Result<Vec<Scope>, String> {
+        input.split_whitespace().map(str::parse).collect()
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .iter()
+            .find(|scope| scope.as_str() == input)
+            .copied()
+            .ok_or_else(|| format!("unknown scope '{}'", input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_split() {
+        let scopes = [Scope::ProvidersRead, Scope::UsersDelete];
+        assert_eq!(Scope::join(&scopes), "providers:read users:delete");
+        assert_eq!(Scope::split(&Scope::join(&scopes)), Ok(scopes.to_vec()));
+        assert_eq!(Scope::split(""), Ok(vec![]));
+        assert!(Scope::split("providers:read users:everything").is_err());
+    }
+
+    #[test]
+    fn admin_implies_all() {
+        assert!(Scope::UsersRead.allowed_by(&[Scope::UsersRead]));
+        assert!(!Scope::UsersDelete.allowed_by(&[Scope::UsersRead]));
+        assert!(Scope::ALL
+            .iter()
+            .all(|scope| scope.allowed_by(&[Scope::OrgAdmin])));
+    }
+}
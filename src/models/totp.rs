@@ -0,0 +1,215 @@
+//! See [Totp] for documentation
+
+use crate::crypto::{gen_id, hash_token, hotp};
+use crate::store::Store;
+use crate::{AuthError, AuthResult, TotpError};
+use chrono::prelude::*;
+use data_encoding::BASE32_NOPAD;
+use rand::prelude::*;
+use uuid::Uuid;
+
+/// Length of generated secrets in bytes, the size of a sha1 digest as
+/// recommended by RFC 4226
+const SECRET_LENGTH: usize = 20;
+
+/// Seconds each one-time code is valid for
+const STEP: i64 = 30;
+
+/// Amount of digits in each one-time code
+const DIGITS: u32 = 6;
+
+/// Amount of steps either side of the current one which are also accepted,
+/// allowing for clock drift and slow typing
+const SKEW: i64 = 1;
+
+/// Amount of recovery codes given when two-factor is confirmed
+const RECOVERY_CODES: usize = 10;
+
+/// Length of each recovery code in characters
+const RECOVERY_LENGTH: usize = 10;
+
+/// Issuer shown by authenticator apps alongside the login id
+const ISSUER: &str = "authrio";
+
+/// RFC 6238 time-based one-time password enrollment for whichever org or
+/// [OrgMember](super::OrgMember) logs in with [Totp::login_id], which once
+/// confirmed requires a code alongside it's password
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Totp {
+    /// Randomly generated integer primary key
+    pub id: i32,
+    /// Id of the org or member this protects
+    pub login_id: Uuid,
+    /// The [Org](super::Org) the login belongs to
+    pub org_id: Uuid,
+    /// Base32 encoded shared secret, encrypted at rest
+    pub secret: String,
+    /// If a code has been given since enrolling, only then is it enforced
+    pub confirmed: bool,
+    /// Last time step a code was accepted for, so codes can't be replayed
+    pub last_step: i64,
+    /// Timestamp of creation
+    pub created: DateTime<Utc>,
+}
+
+impl Totp {
+    /// Creates a new unconfirmed [Totp] with a random secret, does not add to
+    /// the [Store]
+    pub fn new(login_id: Uuid, org_id: Uuid) -> Self {
+        Self {
+            id: gen_id(),
+            login_id,
+            org_id,
+            secret: BASE32_NOPAD.encode(&rand::thread_rng().gen::<[u8; SECRET_LENGTH]>()),
+            confirmed: false,
+            last_step: 0,
+            created: Utc::now(),
+        }
+    }
+
+    /// Gets the `otpauth://` uri to show as a qr code for authenticator apps
+    pub fn uri(&self) -> String {
+        format!(
+            "otpauth://totp/{issuer}:{}?secret={}&issuer={issuer}&algorithm=SHA1&digits={}&period={}",
+            self.login_id,
+            self.secret,
+            DIGITS,
+            STEP,
+            issuer = ISSUER
+        )
+    }
+
+    /// Adds this [Totp] to the [Store], replacing any other for it's login
+    pub async fn create(&self, store: &dyn Store) -> AuthResult<(), Uuid> {
+        store.create_totp(self).await
+    }
+
+    /// Gets the [Totp] of an org or member if they've enrolled
+    pub async fn get(store: &dyn Store, login_id: Uuid) -> AuthResult<Option<Self>, Uuid> {
+        store.get_totp(login_id).await
+    }
+
+    /// Enables this enrollment once a correct code proves the secret was saved,
+    /// giving single-use recovery codes which are only stored hashed
+    pub async fn confirm(
+        &mut self,
+        store: &dyn Store,
+        code: &str,
+    ) -> AuthResult<Vec<String>, Uuid> {
+        if self.confirmed {
+            return Err(AuthError::new(TotpError::AlreadyEnabled, self.login_id));
+        }
+        self.use_code(store, code, Utc::now()).await?;
+
+        let codes: Vec<String> = (0..RECOVERY_CODES).map(|_| gen_recovery_code()).collect();
+        let hashes: Vec<Vec<u8>> = codes.iter().map(hash_token).collect();
+        store
+            .confirm_totp(self.id, &hashes)
+            .await
+            .map_err(|err| AuthError::new(err.kind, self.login_id))?;
+
+        self.confirmed = true;
+        Ok(codes)
+    }
+
+    /// Checks a one-time code or, failing that, uses up a recovery code
+    pub async fn verify(&self, store: &dyn Store, code: Option<&str>) -> AuthResult<(), Uuid> {
+        let code = match code {
+            Some(code) => code.trim(),
+            None => return Err(AuthError::new(TotpError::CodeRequired, self.login_id)),
+        };
+
+        if code.len() == DIGITS as usize && code.bytes().all(|byte| byte.is_ascii_digit()) {
+            return self.use_code(store, code, Utc::now()).await;
+        }
+
+        match store
+            .take_recovery_code(self.id, &hash_token(code.to_ascii_lowercase()))
+            .await
+        {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(AuthError::new(TotpError::CodeInvalid, self.login_id)),
+            Err(err) => Err(AuthError::new(err.kind, self.login_id)),
+        }
+    }
+
+    /// Removes this enrollment and it's recovery codes
+    pub async fn delete(&self, store: &dyn Store) -> AuthResult<(), Uuid> {
+        store.delete_totp(self.login_id).await
+    }
+
+    /// Accepts a one-time code for any step within [SKEW] of `now`, recording
+    /// it's step so neither it nor any earlier code can be used again
+    async fn use_code(
+        &self,
+        store: &dyn Store,
+        code: &str,
+        now: DateTime<Utc>,
+    ) -> AuthResult<(), Uuid> {
+        let invalid = || AuthError::new(TotpError::CodeInvalid, self.login_id);
+        let secret = BASE32_NOPAD
+            .decode(self.secret.as_bytes())
+            .map_err(|_| invalid())?;
+        let code: u32 = code.parse().map_err(|_| invalid())?;
+
+        let current = now.timestamp() / STEP;
+        let step = (current - SKEW..=current + SKEW)
+            .find(|&step| step > self.last_step && hotp(&secret, step as u64, DIGITS) == code)
+            .ok_or_else(invalid)?;
+
+        // the store only advances forwards, so two requests can't both use it
+        match store.advance_totp(self.id, step).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(invalid()),
+            Err(err) => Err(AuthError::new(err.kind, self.login_id)),
+        }
+    }
+}
+
+/// Generates a random lowercase base32 recovery code
+fn gen_recovery_code() -> String {
+    let bytes = rand::thread_rng().gen::<[u8; RECOVERY_LENGTH]>();
+    BASE32_NOPAD.encode(&bytes)[..RECOVERY_LENGTH].to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture;
+
+    #[tokio::test]
+    async fn codes_single_use() {
+        let (store, _, org) = fixture().await;
+
+        // secret and codes from the rfc 6238 test vectors
+        let totp = Totp {
+            secret: BASE32_NOPAD.encode(b"12345678901234567890"),
+            ..Totp::new(org.id, org.id)
+        };
+        totp.create(&store).await.unwrap();
+        let at = |secs| Utc.timestamp_opt(secs, 0).unwrap();
+        totp.use_code(&store, "287082", at(59)).await.unwrap();
+        assert!(totp.use_code(&store, "287082", at(59)).await.is_err());
+        assert!(totp
+            .use_code(&store, "081804", at(1111111109 + 2 * STEP))
+            .await
+            .is_err());
+        totp.use_code(&store, "005924", at(1234567890 + STEP))
+            .await
+            .unwrap();
+
+        let mut totp = Totp::new(org.id, org.id);
+        totp.create(&store).await.unwrap();
+        let secret = BASE32_NOPAD.decode(totp.secret.as_bytes()).unwrap();
+        let code = hotp(&secret, (Utc::now().timestamp() / STEP) as u64, DIGITS);
+        let recovery = totp.confirm(&store, &format!("{:06}", code)).await.unwrap();
+        assert_eq!(recovery.len(), RECOVERY_CODES);
+        assert!(Totp::get(&store, org.id).await.unwrap().unwrap().confirmed);
+
+        assert!(totp.verify(&store, None).await.is_err());
+        totp.verify(&store, Some(&recovery[0].to_uppercase()))
+            .await
+            .unwrap();
+        assert!(totp.verify(&store, Some(&recovery[0])).await.is_err());
+    }
+}
@@ -1,11 +1,15 @@
 //! See [UserProvider] for documentation
 
+use super::Provider;
 use crate::crypto::gen_id;
+use crate::oauth::{self, TokenResponse};
+use crate::store::Store;
+use crate::{AuthError, AuthErrorKind, AuthResult, ProviderError, UserError};
 use chrono::prelude::*;
-use sqlx::FromRow;
+use uuid::Uuid;
 
 /// Model for users in the scope of a provider, for external logins
-#[derive(FromRow, Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct UserProvider {
     /// Randomly generated integer primary key
     pub id: i32,
@@ -14,8 +18,11 @@ pub struct UserProvider {
     /// Optional refresh token for easy refreshing
     pub token_refresh: Option<String>,
     /// Optional expiry date of [UserProvider::token_access] if provided
-    pub token_expires: Option<DateTime<Utc>>, 
-    /// Foreign key to the [Provider::id](super::Provider::id) field
+    pub token_expires: Option<DateTime<Utc>>,
+    /// If the provider has rejected [UserProvider::token_refresh], meaning it
+    /// won't be retried in the background
+    pub token_refresh_dead: bool,
+    /// Foreign key to the [Provider::key](super::Provider::key) field
     pub provider_id: i32,
     /// Timestamp of creation
     pub created: DateTime<Utc>,
@@ -23,14 +30,208 @@ pub struct UserProvider {
 
 impl UserProvider {
     /// Creates new [UserProvider] from given info
-    pub fn new(token_access: impl Into<String>, token_refresh: impl Into<Option<String>>, token_expires: impl Into<Option<DateTime<Utc>>>, provider_id: i32) -> Self {
+    pub fn new(
+        token_access: impl Into<String>,
+        token_refresh: impl Into<Option<String>>,
+        token_expires: impl Into<Option<DateTime<Utc>>>,
+        provider_id: i32,
+    ) -> Self {
         Self {
             id: gen_id(),
             token_access: token_access.into(),
             token_refresh: token_refresh.into(),
             token_expires: token_expires.into(),
+            token_refresh_dead: false,
             provider_id,
             created: Utc::now(),
         }
     }
+
+    /// Adds this [UserProvider] to the [Store]
+    pub async fn create(&self, store: &dyn Store) -> AuthResult<(), i32> {
+        store.create_user_provider(self).await
+    }
+
+    /// Gets a [UserProvider] from the [Store] by it's [UserProvider::id], scoped
+    /// to providers of the given [Org](super::Org)
+    pub async fn get(store: &dyn Store, org_id: Uuid, id: i32) -> AuthResult<Self, i32> {
+        store.get_user_provider(org_id, id).await
+    }
+
+    /// Gets all [UserProvider]s with a usable refresh token whose access token
+    /// expires before the given time
+    pub async fn expiring(store: &dyn Store, before: DateTime<Utc>) -> AuthResult<Vec<Self>, i32> {
+        store.expiring_user_providers(before).await
+    }
+
+    /// Refreshes [UserProvider::token_access] using the stored refresh token with
+    /// it's owning [Provider], updating the [Store] with the new tokens. If the
+    /// provider rejects the refresh token it's marked as dead, but not if it
+    /// rejects the provider's own client configuration. Both only apply if the
+    /// stored refresh token hasn't changed meanwhile, otherwise this takes on
+    /// the tokens of whichever refresh got there first
+    pub async fn refresh(
+        &mut self,
+        store: &dyn Store,
+        client: &reqwest::Client,
+        provider: &Provider,
+    ) -> AuthResult<(), i32> {
+        let token_refresh = self
+            .token_refresh
+            .clone()
+            .ok_or_else(|| AuthError::new(UserError::NoRefreshToken, self.id))?;
+
+        match oauth::refresh_token(client, provider, &token_refresh).await {
+            Ok(token) => {
+                let mut refreshed = self.clone();
+                refreshed.set_tokens(token);
+
+                if store
+                    .update_user_provider(&refreshed, &token_refresh)
+                    .await?
+                {
+                    *self = refreshed;
+                    return Ok(());
+                }
+            }
+            Err(err) => {
+                let rejected = matches!(
+                    err.kind,
+                    AuthErrorKind::ProviderError(ProviderError::Rejected(_))
+                );
+
+                if !rejected || store.kill_user_provider(self.id, &token_refresh).await? {
+                    self.token_refresh_dead |= rejected;
+                    return Err(AuthError::new(err.kind, self.id));
+                }
+            }
+        }
+
+        // another refresh rotated the token first, so it's tokens are the ones to keep
+        *self = Self::get(store, provider.org_id, self.id).await?;
+        Ok(())
+    }
+
+    /// Replaces tokens with newly issued ones, keeping the existing refresh token
+    /// if the provider didn't rotate it
+    fn set_tokens(&mut self, token: TokenResponse) {
+        self.token_refresh_dead = false;
+        self.token_expires = token.expires();
+        self.token_access = token.access_token;
+
+        if let Some(token_refresh) = token.refresh_token {
+            self.token_refresh = Some(token_refresh);
+        }
+    }
+
+    /// Deletes a [UserProvider] from the [Store] by it's [UserProvider::id],
+    /// scoped to providers of the given [Org](super::Org)
+    pub async fn delete(store: &dyn Store, org_id: Uuid, id: i32) -> AuthResult<(), i32> {
+        store.delete_user_provider(org_id, id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture;
+    use crate::oauth::tests::{mock_provider, provider, GOOD_REFRESH, ROTATED_REFRESH};
+
+    #[test]
+    fn set_tokens_rotation() {
+        let mut user = UserProvider::new("access", Some("refresh".to_string()), None, 0);
+
+        user.set_tokens(TokenResponse {
+            access_token: "access-2".to_string(),
+            refresh_token: None,
+            expires_in: Some(60),
+        });
+        assert_eq!(user.token_access, "access-2");
+        assert_eq!(user.token_refresh, Some("refresh".to_string()));
+        assert!(user.token_expires.is_some());
+
+        user.set_tokens(TokenResponse {
+            access_token: "access-3".to_string(),
+            refresh_token: Some("refresh-2".to_string()),
+            expires_in: None,
+        });
+        assert_eq!(user.token_access, "access-3");
+        assert_eq!(user.token_refresh, Some("refresh-2".to_string()));
+        assert_eq!(user.token_expires, None);
+    }
+
+    #[actix_web::test]
+    async fn refresh_dead_only_on_invalid_grant() {
+        let (store, _, org) = fixture().await;
+        let (domain, client) = (mock_provider(), reqwest::Client::new());
+
+        let misconfigured = Provider {
+            org_id: org.id,
+            ..provider(&domain, "wrong")
+        };
+        misconfigured.create(&store).await.unwrap();
+        let mut user =
+            UserProvider::new("access", Some(GOOD_REFRESH.into()), None, misconfigured.key);
+        user.create(&store).await.unwrap();
+        assert_eq!(
+            user.refresh(&store, &client, &misconfigured).await,
+            Err(AuthError::new(
+                ProviderError::Misconfigured("invalid_client".to_string()),
+                user.id
+            ))
+        );
+        assert!(!user.token_refresh_dead);
+        assert!(
+            !UserProvider::get(&store, org.id, user.id)
+                .await
+                .unwrap()
+                .token_refresh_dead
+        );
+
+        let working = Provider {
+            secret: "secret".into(),
+            ..misconfigured
+        };
+        let mut user = UserProvider::new("access", Some("dead-refresh".into()), None, working.key);
+        user.create(&store).await.unwrap();
+        assert!(user.refresh(&store, &client, &working).await.is_err());
+        assert!(
+            UserProvider::get(&store, org.id, user.id)
+                .await
+                .unwrap()
+                .token_refresh_dead
+        );
+    }
+
+    #[actix_web::test]
+    async fn concurrent_refreshes() {
+        let (store, _, org) = fixture().await;
+        let (domain, client) = (mock_provider(), reqwest::Client::new());
+        let provider = Provider {
+            org_id: org.id,
+            ..provider(&domain, "secret")
+        };
+        provider.create(&store).await.unwrap();
+        let user = UserProvider::new("access", Some(GOOD_REFRESH.into()), None, provider.key);
+        user.create(&store).await.unwrap();
+
+        // the provider only accepts the refresh token once, so one of these loses
+        let (mut first, mut second) = (user.clone(), user.clone());
+        let (first_result, second_result) = tokio::join!(
+            first.refresh(&store, &client, &provider),
+            second.refresh(&store, &client, &provider)
+        );
+        assert!(first_result.is_ok() || second_result.is_ok());
+
+        let stored = UserProvider::get(&store, org.id, user.id).await.unwrap();
+        assert_eq!(stored.token_refresh.as_deref(), Some(ROTATED_REFRESH));
+        assert_eq!(stored.token_access, "refreshed");
+        assert!(!stored.token_refresh_dead);
+
+        // a stale copy refreshing afterwards takes on the stored tokens
+        let mut stale = user.clone();
+        assert_eq!(stale.refresh(&store, &client, &provider).await, Ok(()));
+        assert_eq!(stale, stored);
+        assert_eq!(UserProvider::get(&store, org.id, user.id).await, Ok(stored));
+    }
 }
@@ -0,0 +1,129 @@
+//! Delivery of messages about an org outside of api responses, see [Notifier]
+
+use crate::config::NotifierConfig;
+use crate::models::Org;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+/// Delivers messages about an org, such as password reset tokens, wherever
+/// the operator set it up to send them as orgs hold no contact details, with
+/// implementations for email or chat living alongside
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Delivers a password reset `token` for an org, which is usable until
+    /// `expires`, erroring with the reason why it couldn't be delivered
+    async fn password_reset(
+        &self,
+        org: &Org,
+        token: &str,
+        expires: DateTime<Utc>,
+    ) -> Result<(), String>;
+}
+
+/// Prints messages to the server's output, only suitable for local testing as
+/// anyone reading the logs can use them
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn password_reset(
+        &self,
+        org: &Org,
+        token: &str,
+        expires: DateTime<Utc>,
+    ) -> Result<(), String> {
+        println!(
+            "🔑 Password reset for org {} requested, token {} is valid until {}",
+            org.id, token, expires
+        );
+        Ok(())
+    }
+}
+
+/// Appends messages as json lines to a file, for local testing or for another
+/// process to pick up and deliver
+pub struct FileNotifier {
+    path: PathBuf,
+}
+
+impl FileNotifier {
+    /// Creates a new [FileNotifier] appending to the file at `path`, which is
+    /// created if it doesn't exist
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl Notifier for FileNotifier {
+    async fn password_reset(
+        &self,
+        org: &Org,
+        token: &str,
+        expires: DateTime<Utc>,
+    ) -> Result<(), String> {
+        let mut line = serde_json::json!({
+            "kind": "password_reset",
+            "org_id": org.id,
+            "token": token,
+            "expires": expires,
+        })
+        .to_string();
+        line.push('\n');
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|err| err.to_string())?;
+        file.write_all(line.as_bytes())
+            .await
+            .map_err(|err| err.to_string())?;
+        // tokio writes in the background, so wait for it before returning
+        file.flush().await.map_err(|err| err.to_string())
+    }
+}
+
+/// Creates the [Notifier] chosen by a [NotifierConfig]
+pub fn from_config(config: &NotifierConfig) -> Arc<dyn Notifier> {
+    match config {
+        NotifierConfig::Log => Arc::new(LogNotifier),
+        NotifierConfig::File(path) => Arc::new(FileNotifier::new(path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture;
+
+    #[tokio::test]
+    async fn file_notifier() {
+        let (_, _, org) = fixture().await;
+        let path = std::env::temp_dir().join(format!("authrio-notify-{}", std::process::id()));
+        let notifier = FileNotifier::new(&path);
+
+        notifier
+            .password_reset(&org, "first", Utc::now())
+            .await
+            .unwrap();
+        notifier
+            .password_reset(&org, "second", Utc::now())
+            .await
+            .unwrap();
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let tokens: Vec<String> = lines
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<serde_json::Value>(line).unwrap()["token"].to_string()
+            })
+            .collect();
+        assert_eq!(tokens, vec!["\"first\"", "\"second\""]);
+    }
+}
@@ -0,0 +1,318 @@
+//! Outbound oauth requests made to a [Provider]'s token endpoint
+
+use crate::models::{Provider, UserProvider};
+use crate::{AuthError, AuthResult, ProviderError};
+use chrono::{prelude::*, Duration};
+use reqwest::header::ACCEPT;
+use serde::Deserialize;
+
+/// Successful response from a token endpoint, see RFC 6749 section 5.1
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct TokenResponse {
+    /// Newly issued access token
+    pub access_token: String,
+    /// Optional refresh token, may be rotated on every refresh
+    pub refresh_token: Option<String>,
+    /// Optional amount of seconds until [TokenResponse::access_token] expires
+    pub expires_in: Option<i64>,
+}
+
+impl TokenResponse {
+    /// Gets the absolute expiry of [TokenResponse::access_token] if known
+    pub fn expires(&self) -> Option<DateTime<Utc>> {
+        self.expires_in
+            .map(|secs| Utc::now() + Duration::seconds(secs))
+    }
+
+    /// Converts into a new [UserProvider] for the given [Provider::key]
+    pub fn into_user_provider(self, provider_id: i32) -> UserProvider {
+        let expires = self.expires();
+        UserProvider::new(self.access_token, self.refresh_token, expires, provider_id)
+    }
+}
+
+/// Oauth error code for a rejected code or refresh token, with all others
+/// coming from problems with the [Provider]'s client configuration
+const INVALID_GRANT: &str = "invalid_grant";
+
+/// Error response from a token endpoint, see RFC 6749 section 5.2
+#[derive(Deserialize)]
+struct TokenError {
+    error: String,
+}
+
+/// Either kind of body a token endpoint may respond with, as some providers
+/// respond to errors with a successful status code
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TokenResult {
+    Ok(TokenResponse),
+    Err(TokenError),
+}
+
+/// Exchanges an authorization `code` received from the [Provider] for tokens,
+/// proving possession of the PKCE `code_verifier` used to start the flow
+pub async fn exchange_code(
+    client: &reqwest::Client,
+    provider: &Provider,
+    code: &str,
+    code_verifier: &str,
+) -> AuthResult<TokenResponse, String> {
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("code_verifier", code_verifier),
+        ("client_id", provider.id.as_str()),
+        ("client_secret", provider.secret.as_str()),
+    ];
+
+    if let Some(redirect_uri) = &provider.redirect_uri {
+        form.push(("redirect_uri", redirect_uri));
+    }
+
+    token_request(client, provider, &form).await
+}
+
+/// Exchanges a `refresh_token` for a fresh access token, which may also come with
+/// a rotated refresh token
+pub async fn refresh_token(
+    client: &reqwest::Client,
+    provider: &Provider,
+    refresh_token: &str,
+) -> AuthResult<TokenResponse, String> {
+    let form = [
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token),
+        ("client_id", provider.id.as_str()),
+        ("client_secret", provider.secret.as_str()),
+    ];
+
+    token_request(client, provider, &form).await
+}
+
+/// Posts a form to the [Provider::token_url] and parses the response
+async fn token_request(
+    client: &reqwest::Client,
+    provider: &Provider,
+    form: &[(&str, &str)],
+) -> AuthResult<TokenResponse, String> {
+    let err = |kind: ProviderError| AuthError::new(kind, provider.id.clone());
+
+    let resp = client
+        .post(provider.token_url())
+        .header(ACCEPT, "application/json")
+        .form(form)
+        .send()
+        .await
+        .map_err(|_| err(ProviderError::Unreachable))?;
+
+    if resp.status().is_server_error() {
+        return Err(err(ProviderError::Unreachable));
+    }
+
+    match resp.json::<TokenResult>().await {
+        Ok(TokenResult::Ok(token)) => Ok(token),
+        Ok(TokenResult::Err(token_err)) if token_err.error == INVALID_GRANT => {
+            Err(err(ProviderError::Rejected(token_err.error)))
+        }
+        Ok(TokenResult::Err(token_err)) => Err(err(ProviderError::Misconfigured(token_err.error))),
+        Err(_) => Err(err(ProviderError::InvalidResponse)),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::crypto::code_challenge;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use uuid::Uuid;
+
+    /// Code accepted by the [mock_provider] alongside [GOOD_VERIFIER], as is the
+    /// code challenge of any verifier alongside it
+    pub const GOOD_CODE: &str = "good-code";
+
+    /// PKCE verifier accepted by the [mock_provider] alongside [GOOD_CODE]
+    pub const GOOD_VERIFIER: &str = "good-verifier";
+
+    /// Refresh token accepted once by each [mock_provider], which rotates it
+    /// into [ROTATED_REFRESH] and rejects it afterwards
+    pub const GOOD_REFRESH: &str = "good-refresh";
+
+    /// Refresh token given by the [mock_provider] in place of [GOOD_REFRESH]
+    pub const ROTATED_REFRESH: &str = "rotated-refresh";
+
+    /// Spins up a mock oauth provider on a random local port within a thread of
+    /// it's own, so it runs alongside any runtime, returning it's domain
+    pub fn mock_provider() -> String {
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                let refreshed = web::Data::new(AtomicBool::new(false));
+                let server = HttpServer::new(move || {
+                    App::new()
+                        .app_data(refreshed.clone())
+                        .route("/token", web::post().to(mock_token))
+                })
+                .workers(1)
+                .disable_signals()
+                .bind(("127.0.0.1", 0))
+                .unwrap();
+                tx.send(format!("http://{}", server.addrs()[0])).unwrap();
+                server.run().await
+            })
+        });
+        rx.recv().unwrap()
+    }
+
+    /// Token endpoint of the [mock_provider]
+    async fn mock_token(
+        form: web::Form<HashMap<String, String>>,
+        refreshed: web::Data<AtomicBool>,
+    ) -> HttpResponse {
+        let get = |key: &str| form.get(key).map(String::as_str);
+
+        if get("client_id") != Some("client") || get("client_secret") != Some("secret") {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({"error": "invalid_client"}));
+        }
+
+        if get("grant_type") == Some("refresh_token") {
+            return match get("refresh_token") {
+                Some(GOOD_REFRESH) if !refreshed.swap(true, Ordering::SeqCst) => HttpResponse::Ok()
+                    .json(serde_json::json!({
+                        "access_token": "refreshed",
+                        "refresh_token": ROTATED_REFRESH,
+                        "expires_in": 3600,
+                    })),
+                _ => HttpResponse::BadRequest().json(serde_json::json!({"error": "invalid_grant"})),
+            };
+        }
+
+        // a code challenge stands in for a code issued to it, needing it's verifier
+        match (get("grant_type"), get("code"), get("code_verifier")) {
+            (Some("authorization_code"), Some(code), Some(verifier))
+                if (code, verifier) == (GOOD_CODE, GOOD_VERIFIER)
+                    || code == code_challenge(verifier) =>
+            {
+                HttpResponse::Ok().json(serde_json::json!({
+                    "access_token": "access",
+                    "refresh_token": GOOD_REFRESH,
+                    "expires_in": 3600,
+                    "token_type": "bearer",
+                }))
+            }
+            _ => HttpResponse::BadRequest().json(serde_json::json!({"error": "invalid_grant"})),
+        }
+    }
+
+    /// Creates a [Provider] pointing towards a [mock_provider] domain
+    pub fn provider(domain: &str, secret: &str) -> Provider {
+        Provider::new("client", secret, domain, None, None, Uuid::new_v4()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn exchange() {
+        let domain = mock_provider();
+        let client = reqwest::Client::new();
+
+        let token = exchange_code(
+            &client,
+            &provider(&domain, "secret"),
+            GOOD_CODE,
+            GOOD_VERIFIER,
+        )
+        .await
+        .unwrap();
+        assert_eq!(token.access_token, "access");
+        assert_eq!(token.refresh_token, Some(GOOD_REFRESH.to_string()));
+        assert!(token.expires().unwrap() > Utc::now());
+    }
+
+    #[actix_web::test]
+    async fn exchange_rejected() {
+        let domain = mock_provider();
+        let client = reqwest::Client::new();
+
+        assert_eq!(
+            exchange_code(
+                &client,
+                &provider(&domain, "secret"),
+                "bad-code",
+                GOOD_VERIFIER
+            )
+            .await,
+            Err(AuthError::new(
+                ProviderError::Rejected("invalid_grant".to_string()),
+                "client".to_string()
+            ))
+        );
+        assert_eq!(
+            exchange_code(
+                &client,
+                &provider(&domain, "secret"),
+                GOOD_CODE,
+                "bad-verifier"
+            )
+            .await,
+            Err(AuthError::new(
+                ProviderError::Rejected("invalid_grant".to_string()),
+                "client".to_string()
+            ))
+        );
+        assert_eq!(
+            exchange_code(
+                &client,
+                &provider(&domain, "wrong"),
+                GOOD_CODE,
+                GOOD_VERIFIER
+            )
+            .await,
+            Err(AuthError::new(
+                ProviderError::Misconfigured("invalid_client".to_string()),
+                "client".to_string()
+            ))
+        );
+    }
+
+    #[actix_web::test]
+    async fn refresh() {
+        let domain = mock_provider();
+        let client = reqwest::Client::new();
+        let provider = provider(&domain, "secret");
+
+        let token = refresh_token(&client, &provider, GOOD_REFRESH)
+            .await
+            .unwrap();
+        assert_eq!(token.access_token, "refreshed");
+        assert_eq!(token.refresh_token, Some(ROTATED_REFRESH.to_string()));
+
+        assert_eq!(
+            refresh_token(&client, &provider, "dead-refresh").await,
+            Err(AuthError::new(
+                ProviderError::Rejected("invalid_grant".to_string()),
+                "client".to_string()
+            ))
+        );
+    }
+
+    #[actix_web::test]
+    async fn exchange_unreachable() {
+        let client = reqwest::Client::new();
+
+        assert_eq!(
+            exchange_code(
+                &client,
+                &provider("http://127.0.0.1:1", "secret"),
+                GOOD_CODE,
+                GOOD_VERIFIER
+            )
+            .await,
+            Err(AuthError::new(
+                ProviderError::Unreachable,
+                "client".to_string()
+            ))
+        );
+    }
+}
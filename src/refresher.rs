@@ -0,0 +1,186 @@
+//! Background task which proactively refreshes expiring [UserProvider] tokens
+
+use crate::config::RefreshConfig;
+use crate::models::{Provider, UserProvider};
+use crate::store::Store;
+use crate::{AuthErrorKind, ProviderError};
+use chrono::{prelude::*, Duration};
+use rand::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::{sync::Semaphore, task::JoinSet, time};
+
+/// Base delay in seconds before retrying a failed refresh, doubled each failure
+const BACKOFF_BASE: i64 = 30;
+
+/// Maximum delay in seconds before retrying a failed refresh
+const BACKOFF_MAX: i64 = 3600;
+
+/// Periodically sweeps for [UserProvider]s expiring within the configured window
+/// and refreshes them with bounded concurrency
+pub struct Refresher {
+    store: Arc<dyn Store>,
+    client: reqwest::Client,
+    config: RefreshConfig,
+    backoff: Backoff,
+}
+
+impl Refresher {
+    /// Creates a new [Refresher], which does nothing until [Refresher::run]
+    pub fn new(store: Arc<dyn Store>, client: reqwest::Client, config: RefreshConfig) -> Self {
+        Self {
+            store,
+            client,
+            config,
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Runs sweeps forever at the configured interval
+    pub async fn run(mut self) {
+        let mut interval = time::interval(std::time::Duration::from_secs(self.config.interval));
+
+        loop {
+            interval.tick().await;
+            self.sweep().await;
+        }
+    }
+
+    /// Refreshes all currently expiring tokens which aren't backing off
+    async fn sweep(&mut self) {
+        let now = Utc::now();
+        let before = now + Duration::seconds(self.config.window as i64);
+        let users = match UserProvider::expiring(self.store.as_ref(), before).await {
+            Ok(val) => val,
+            Err(err) => return eprintln!("❌ Couldn't find expiring tokens, {}", err),
+        };
+
+        let semaphore = Arc::new(Semaphore::new(self.config.concurrency));
+        let mut providers: HashMap<i32, Provider> = HashMap::new();
+        let mut tasks = JoinSet::new();
+
+        for mut user in users {
+            if !self.backoff.ready(user.id, now) {
+                continue;
+            }
+
+            let provider = match providers.get(&user.provider_id) {
+                Some(val) => val.clone(),
+                None => match Provider::get_by_key(self.store.as_ref(), user.provider_id).await {
+                    Ok(val) => providers.entry(val.key).or_insert(val).clone(),
+                    Err(err) => {
+                        eprintln!("❌ Couldn't refresh token, {}", err);
+                        self.backoff.fail(user.id, now);
+                        continue;
+                    }
+                },
+            };
+
+            let permit = semaphore.clone().acquire_owned().await.unwrap();
+            let (store, client) = (self.store.clone(), self.client.clone());
+            tasks.spawn(async move {
+                let result = user.refresh(store.as_ref(), &client, &provider).await;
+                drop(permit);
+                (user.id, result)
+            });
+        }
+
+        while let Some(joined) = tasks.join_next().await {
+            let (id, result) = match joined {
+                Ok(val) => val,
+                Err(err) => {
+                    eprintln!("❌ Token refresh task failed, {}", err);
+                    continue;
+                }
+            };
+
+            match result {
+                Ok(()) => self.backoff.succeed(id),
+                Err(err) => {
+                    eprintln!("❌ Couldn't refresh token, {}", err);
+
+                    match err.kind {
+                        // refresh token was marked as dead so it won't be seen again
+                        AuthErrorKind::ProviderError(ProviderError::Rejected(_)) => {
+                            self.backoff.succeed(id)
+                        }
+                        _ => self.backoff.fail(id, Utc::now()),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Tracks consecutive refresh failures so retries can be spaced out
+#[derive(Default)]
+struct Backoff {
+    /// Amount of failures and time of next allowed attempt, per [UserProvider::id]
+    failures: HashMap<i32, (u32, DateTime<Utc>)>,
+}
+
+impl Backoff {
+    /// Checks if a refresh for the given user may be attempted
+    fn ready(&self, id: i32, now: DateTime<Utc>) -> bool {
+        match self.failures.get(&id) {
+            Some((_, next)) => *next <= now,
+            None => true,
+        }
+    }
+
+    /// Records a failure, pushing the next attempt back
+    fn fail(&mut self, id: i32, now: DateTime<Utc>) {
+        let entry = self.failures.entry(id).or_insert((0, now));
+        entry.0 += 1;
+        entry.1 = now + backoff_delay(entry.0);
+    }
+
+    /// Forgets all failures once successful
+    fn succeed(&mut self, id: i32) {
+        self.failures.remove(&id);
+    }
+}
+
+/// Gets a randomly jittered delay before the next retry from the amount of
+/// consecutive failures, between half and all of the exponential delay
+fn backoff_delay(failures: u32) -> Duration {
+    let exp = failures.saturating_sub(1).min(16);
+    let max = BACKOFF_BASE.saturating_mul(1 << exp).min(BACKOFF_MAX);
+
+    Duration::seconds(rand::thread_rng().gen_range(max / 2..=max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_bounds() {
+        for failures in 1..=64 {
+            let delay = backoff_delay(failures).num_seconds();
+            assert!(delay >= BACKOFF_BASE / 2);
+            assert!(delay <= BACKOFF_MAX);
+        }
+
+        assert!(backoff_delay(1).num_seconds() <= BACKOFF_BASE);
+        assert!(backoff_delay(64).num_seconds() >= BACKOFF_MAX / 2);
+    }
+
+    #[test]
+    fn backoff_tracking() {
+        let now = Utc::now();
+        let mut backoff = Backoff::default();
+        assert!(backoff.ready(1, now));
+
+        backoff.fail(1, now);
+        assert!(!backoff.ready(1, now));
+        assert!(backoff.ready(2, now));
+        assert!(backoff.ready(1, now + Duration::seconds(BACKOFF_BASE)));
+
+        backoff.fail(1, now);
+        assert_eq!(backoff.failures[&1].0, 2);
+
+        backoff.succeed(1);
+        assert!(backoff.ready(1, now));
+    }
+}
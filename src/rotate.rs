@@ -0,0 +1,207 @@
+//! Online rotation of [MasterKey](crate::crypto::MasterKey)s, re-wrapping the
+//! [DataKey](crate::crypto::DataKey) of every row onto the current
+//! [Config::master_key](crate::Config::master_key), with a
+//! separate implementation per database backend
+
+/// Amount of rows to re-wrap within each transaction
+const BATCH_SIZE: i64 = 100;
+
+/// Outcome of a rotation run
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RotateReport {
+    /// Rows moved onto the current master key
+    pub rotated: u64,
+    /// Rows left alone as they were rewritten after being read, which are
+    /// picked up by running again if still on an old key
+    pub skipped: u64,
+    /// Rows which couldn't be re-wrapped, typically from a missing old key
+    pub failed: u64,
+}
+
+/// Implements rotating for a sqlx backend
+macro_rules! rotator {
+    ($db:ty) => {
+        use super::{RotateReport, BATCH_SIZE};
+        use crate::crypto::{row_aad, DataKey};
+        use crate::store::sql::{PROVIDER_TABLE, TOTP_TABLE, USER_PROVIDER_TABLE};
+        use crate::Config;
+        use sqlx::{Decode, Pool, Type};
+        use std::fmt::Display;
+        use uuid::Uuid;
+
+        /// Re-wraps all rows not yet using the current [Config::master_key] in batches,
+        /// whilst the server keeps running. Progress lives in each row's `key_id` so an
+        /// interrupted run is resumed by simply running it again
+        pub async fn rotate(pool: &Pool<$db>, config: &Config) -> Result<RotateReport, sqlx::Error> {
+            let mut report = RotateReport::default();
+
+            rotate_table::<Uuid>(pool, config, PROVIDER_TABLE, "org_id", &mut report).await?;
+            rotate_table::<i32>(pool, config, USER_PROVIDER_TABLE, "provider_id", &mut report).await?;
+            rotate_table::<Uuid>(pool, config, TOTP_TABLE, "org_id", &mut report).await?;
+
+            Ok(report)
+        }
+
+        /// Re-wraps the rows of a single table, whose `owner` column is bound into
+        /// the [row_aad] of each wrapped key alongside it's id
+        async fn rotate_table<O>(
+            pool: &Pool<$db>,
+            config: &Config,
+            table: &str,
+            owner: &str,
+            report: &mut RotateReport,
+        ) -> Result<(), sqlx::Error>
+        where
+            O: for<'r> Decode<'r, $db> + Type<$db> + Display + Send + Unpin,
+        {
+            let (total,): (i64,) = sqlx::query_as(&format!(
+                "SELECT COUNT(*) FROM {} WHERE key_id <> $1",
+                table
+            ))
+            .bind(config.master_key.id)
+            .fetch_one(pool)
+            .await?;
+
+            let mut last_id = i32::MIN;
+            let mut done = 0;
+
+            loop {
+                let rows = sqlx::query_as::<_, (i32, i32, Vec<u8>, O)>(&format!(
+                    "SELECT id, key_id, data_key, {} FROM {} \
+                    WHERE key_id <> $1 AND id > $2 ORDER BY id LIMIT $3",
+                    owner, table
+                ))
+                .bind(config.master_key.id)
+                .bind(last_id)
+                .bind(BATCH_SIZE)
+                .fetch_all(pool)
+                .await?;
+
+                let last = match rows.last() {
+                    Some(row) => row.0,
+                    None => break,
+                };
+                done += rows.len();
+
+                let mut tx = pool.begin().await?;
+                for (id, key_id, data_key, owner) in rows {
+                    let row = row_aad(table, owner, id);
+                    let wrapped = match DataKey::rewrap(config, key_id, &data_key, &row) {
+                        Ok(val) => val,
+                        Err(_) => {
+                            eprintln!(
+                                "❌ Couldn't re-wrap {} {}, is master key {} configured?",
+                                table, id, key_id
+                            );
+                            report.failed += 1;
+                            continue;
+                        }
+                    };
+
+                    // only touch rows which weren't rewritten since being read, as
+                    // another instance may have re-encrypted under a new data key
+                    let result = sqlx::query(&format!(
+                        "UPDATE {} SET key_id = $1, data_key = $2 \
+                        WHERE id = $3 AND key_id = $4 AND data_key = $5",
+                        table
+                    ))
+                    .bind(config.master_key.id)
+                    .bind(wrapped)
+                    .bind(id)
+                    .bind(key_id)
+                    .bind(&data_key)
+                    .execute(&mut tx)
+                    .await?;
+
+                    if result.rows_affected() > 0 {
+                        report.rotated += 1;
+                    } else {
+                        report.skipped += 1;
+                    }
+                }
+                tx.commit().await?;
+
+                last_id = last;
+                println!("🔑 Rotating {}, {}/{} rows..", table, done, total);
+            }
+
+            Ok(())
+        }
+    };
+}
+
+/// Rotation for [PgStore](crate::store::PgStore)
+pub mod postgres {
+    rotator!(sqlx::Postgres);
+}
+
+/// Rotation for [SqliteStore](crate::store::SqliteStore)
+#[cfg(feature = "sqlite")]
+pub mod sqlite {
+    rotator!(sqlx::Sqlite);
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use crate::crypto::MasterKey;
+    use crate::models::{create_org, Provider, Totp, UserProvider};
+    use crate::store::{sqlite::tests::migrated_pool, SqliteStore, Store};
+    use crate::Config;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resumable() {
+        let (pool, old) = (migrated_pool().await, Config::test());
+        let store = SqliteStore::new(pool.clone(), old.clone());
+        let org = create_org(&store, &old).await;
+        let provider = Provider::new("id", "secret", "domain", None, None, org.id).unwrap();
+        store.create_provider(&provider).await.unwrap();
+        let user = UserProvider::new("access", None, None, provider.key);
+        store.create_user_provider(&user).await.unwrap();
+        let totp = Totp::new(org.id, org.id);
+        store.create_totp(&totp).await.unwrap();
+
+        let new = Config {
+            master_key: MasterKey {
+                id: 2,
+                key: [1; 32],
+            },
+            old_master_keys: vec![old.master_key.clone()],
+            ..old
+        };
+        let report = super::sqlite::rotate(&pool, &new).await.unwrap();
+        assert_eq!((report.rotated, report.skipped, report.failed), (3, 0, 0));
+        assert_eq!(
+            super::sqlite::rotate(&pool, &new).await.unwrap(),
+            Default::default()
+        );
+
+        // readable without the old key once rotated
+        let store = SqliteStore::new(
+            pool,
+            Config {
+                old_master_keys: vec![],
+                ..new
+            },
+        );
+        assert_eq!(
+            store
+                .get_provider_by_key(provider.key)
+                .await
+                .unwrap()
+                .secret,
+            "secret"
+        );
+        assert_eq!(
+            store
+                .get_user_provider(org.id, user.id)
+                .await
+                .unwrap()
+                .token_access,
+            "access"
+        );
+        assert_eq!(
+            store.get_totp(org.id).await.unwrap().unwrap().secret,
+            totp.secret
+        );
+    }
+}
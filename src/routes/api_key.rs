@@ -0,0 +1,93 @@
+use super::auth::{Authorized, OrgAdmin};
+use crate::{
+    models::{ApiKey, Scope},
+    store::Store,
+};
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Viewable information of an [ApiKey], only including the key on creation
+#[derive(Serialize)]
+pub(super) struct ApiKeyResponse {
+    id: i32,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    scopes: Vec<Scope>,
+    last_used: Option<DateTime<Utc>>,
+    expires: Option<DateTime<Utc>>,
+    created: DateTime<Utc>,
+}
+
+impl ApiKeyResponse {
+    /// Creates response from an [ApiKey], optionally including the key itself
+    pub(super) fn new(api_key: ApiKey, key: Option<String>) -> Self {
+        Self {
+            id: api_key.id,
+            name: api_key.name,
+            key,
+            scopes: api_key.scopes,
+            last_used: api_key.last_used,
+            expires: api_key.expires,
+            created: api_key.created,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiKeyPost {
+    name: String,
+    scopes: Vec<Scope>,
+    expires: Option<DateTime<Utc>>,
+}
+
+/// Creates an api key, which a key can only do if it holds [Scope::OrgAdmin]
+#[post("/keys")]
+async fn post(
+    store: web::Data<dyn Store>,
+    auth: Authorized<OrgAdmin>,
+    data: web::Json<ApiKeyPost>,
+) -> impl Responder {
+    let org = auth.org;
+
+    let data = data.into_inner();
+    let (api_key, key) = match ApiKey::new(data.name, data.scopes, data.expires, org.id) {
+        Ok(val) => val,
+        Err(err) => return err.into(),
+    };
+
+    match api_key.create(store.get_ref()).await {
+        Ok(()) => HttpResponse::Created().json(ApiKeyResponse::new(api_key, Some(key))),
+        Err(err) => err.into(),
+    }
+}
+
+#[get("/keys")]
+async fn get_all(store: web::Data<dyn Store>, auth: Authorized<OrgAdmin>) -> impl Responder {
+    let org = auth.org;
+
+    match ApiKey::list(store.get_ref(), org.id).await {
+        Ok(api_keys) => HttpResponse::Ok().json(
+            api_keys
+                .into_iter()
+                .map(|api_key| ApiKeyResponse::new(api_key, None))
+                .collect::<Vec<_>>(),
+        ),
+        Err(err) => err.into(),
+    }
+}
+
+#[delete("/keys/{id}")]
+async fn delete(
+    store: web::Data<dyn Store>,
+    auth: Authorized<OrgAdmin>,
+    path_id: web::Path<i32>,
+) -> impl Responder {
+    let org = auth.org;
+
+    match ApiKey::delete(store.get_ref(), org.id, path_id.into_inner()).await {
+        Ok(()) => HttpResponse::Ok().body("api key revoked successfully"),
+        Err(err) => err.into(),
+    }
+}
@@ -0,0 +1,84 @@
+//! Extractor for authorizing org credentials by scope, see [Authorized]
+
+use crate::{
+    hasher::Hasher,
+    models::{Org, OrgAuth, Scope},
+    store::Store,
+    Config,
+};
+use actix_web::{
+    dev::Payload,
+    error::{ErrorInternalServerError, InternalError},
+    web, Error, FromRequest, HttpRequest, HttpResponse,
+};
+use std::{future::Future, marker::PhantomData, pin::Pin};
+
+/// Scope a route needs its [Authorized] credentials to hold
+pub trait RequiredScope {
+    const SCOPE: Scope;
+}
+
+/// Generates marker types for use as `Authorized<marker>`, one per [Scope]
+macro_rules! required_scopes {
+    ($($name:ident),*) => {
+        $(
+            #[doc = concat!("Requires [Scope::", stringify!($name), "]")]
+            pub struct $name;
+
+            impl RequiredScope for $name {
+                const SCOPE: Scope = Scope::$name;
+            }
+        )*
+    };
+}
+
+required_scopes!(
+    ProvidersRead,
+    ProvidersWrite,
+    UsersRead,
+    UsersWrite,
+    UsersDelete,
+    OrgAdmin
+);
+
+/// [Org] authorized from the request's [OrgAuth] credentials, rejecting the
+/// request before the handler runs if they don't hold the scope `S` needs
+pub struct Authorized<S: RequiredScope> {
+    pub org: Org,
+    scope: PhantomData<S>,
+}
+
+impl<S: RequiredScope> FromRequest for Authorized<S> {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let auth = OrgAuth::from_request(req, payload).into_inner();
+        let store = req.app_data::<web::Data<dyn Store>>().cloned();
+        let config = req.app_data::<web::Data<Config>>().cloned();
+        let hasher = req.app_data::<web::Data<Hasher>>().cloned();
+
+        Box::pin(async move {
+            let (store, config, hasher) = match (store, config, hasher) {
+                (Some(store), Some(config), Some(hasher)) => (store, config, hasher),
+                _ => return Err(ErrorInternalServerError("authorization app data missing")),
+            };
+
+            Org::from_auth(
+                store.get_ref(),
+                config.get_ref(),
+                hasher.get_ref(),
+                auth?,
+                S::SCOPE,
+            )
+            .await
+            .map(|org| Self {
+                org,
+                scope: PhantomData,
+            })
+            .map_err(|err| {
+                InternalError::from_response(err.to_string(), HttpResponse::from(err)).into()
+            })
+        })
+    }
+}
@@ -1,7 +1,29 @@
 use crate::crate_version;
-use actix_web::{get, HttpResponse, Responder};
+use crate::hasher::Hasher;
+use actix_web::{get, web, HttpResponse, Responder};
 
 #[get("/")]
 async fn index() -> impl Responder {
     HttpResponse::Ok().body(format!("Authrio v{}", crate_version!()))
 }
+
+/// Prometheus text format metrics, currently for the password hashing queue
+#[get("/metrics")]
+async fn metrics(hasher: web::Data<Hasher>) -> impl Responder {
+    let stats = hasher.stats();
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(format!(
+            "# HELP authrio_hash_queue_depth Password hashes waiting for a free slot\n\
+             # TYPE authrio_hash_queue_depth gauge\n\
+             authrio_hash_queue_depth {}\n\
+             # HELP authrio_hash_running Password hashes currently running\n\
+             # TYPE authrio_hash_running gauge\n\
+             authrio_hash_running {}\n\
+             # HELP authrio_hash_timeouts_total Password hashes refused from waiting too long\n\
+             # TYPE authrio_hash_timeouts_total counter\n\
+             authrio_hash_timeouts_total {}\n",
+            stats.queued, stats.running, stats.timeouts
+        ))
+}
@@ -0,0 +1,151 @@
+use super::auth::{Authorized, OrgAdmin};
+use crate::{
+    hasher::Hasher,
+    models::{OrgInvite, OrgMember, Role},
+    store::Store,
+    Config,
+};
+use actix_web::{delete, get, patch, post, web, HttpResponse, Responder};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Viewable information of an [OrgMember], excluding credentials
+#[derive(Serialize)]
+pub(super) struct MemberResponse {
+    id: Uuid,
+    name: String,
+    role: Role,
+    org_id: Uuid,
+    created: DateTime<Utc>,
+}
+
+impl From<OrgMember> for MemberResponse {
+    fn from(member: OrgMember) -> Self {
+        Self {
+            id: member.id,
+            name: member.name,
+            role: member.role,
+            org_id: member.org_id,
+            created: member.created,
+        }
+    }
+}
+
+#[get("/members")]
+async fn get_all(store: web::Data<dyn Store>, auth: Authorized<OrgAdmin>) -> impl Responder {
+    match OrgMember::list(store.get_ref(), auth.org.id).await {
+        Ok(members) => HttpResponse::Ok().json(
+            members
+                .into_iter()
+                .map(MemberResponse::from)
+                .collect::<Vec<_>>(),
+        ),
+        Err(err) => err.into(),
+    }
+}
+
+#[derive(Deserialize)]
+struct InvitePost {
+    role: Role,
+}
+
+/// Viewable information of a newly created [OrgInvite], including it's token
+#[derive(Serialize)]
+struct InviteResponse {
+    token: String,
+    role: Role,
+    expires: DateTime<Utc>,
+}
+
+/// Creates a one-time invite for a new member, to be given to them privately
+#[post("/members/invite")]
+async fn invite(
+    store: web::Data<dyn Store>,
+    auth: Authorized<OrgAdmin>,
+    data: web::Json<InvitePost>,
+) -> impl Responder {
+    let (invite, token) = OrgInvite::new(data.role, auth.org.id);
+
+    match invite.create(store.get_ref()).await {
+        Ok(()) => HttpResponse::Created().json(InviteResponse {
+            token,
+            role: invite.role,
+            expires: invite.expires,
+        }),
+        Err(err) => err.into(),
+    }
+}
+
+#[derive(Deserialize)]
+struct AcceptPost {
+    token: String,
+    name: String,
+    password: String,
+}
+
+/// Joins an org using an invite, needing no credentials as the token is one
+#[post("/members/accept")]
+async fn accept(
+    store: web::Data<dyn Store>,
+    config: web::Data<Config>,
+    hasher: web::Data<Hasher>,
+    data: web::Json<AcceptPost>,
+) -> impl Responder {
+    let data = data.into_inner();
+
+    match OrgInvite::accept(
+        store.get_ref(),
+        config.get_ref(),
+        hasher.get_ref(),
+        &data.token,
+        data.name,
+        &data.password,
+    )
+    .await
+    {
+        Ok(member) => HttpResponse::Created().json(MemberResponse::from(member)),
+        Err(err) => err.into(),
+    }
+}
+
+#[derive(Deserialize)]
+struct MemberPatch {
+    role: Role,
+}
+
+#[patch("/members/{id}")]
+async fn patch(
+    store: web::Data<dyn Store>,
+    auth: Authorized<OrgAdmin>,
+    path_id: web::Path<Uuid>,
+    data: web::Json<MemberPatch>,
+) -> impl Responder {
+    let mut member = match OrgMember::get(store.get_ref(), auth.org.id, path_id.into_inner()).await
+    {
+        Ok(val) => val,
+        Err(err) => return err.into(),
+    };
+
+    match member.set_role(store.get_ref(), data.role).await {
+        Ok(()) => HttpResponse::Ok().json(MemberResponse::from(member)),
+        Err(err) => err.into(),
+    }
+}
+
+#[delete("/members/{id}")]
+async fn delete(
+    store: web::Data<dyn Store>,
+    auth: Authorized<OrgAdmin>,
+    path_id: web::Path<Uuid>,
+) -> impl Responder {
+    let member = match OrgMember::get(store.get_ref(), auth.org.id, path_id.into_inner()).await {
+        Ok(val) => val,
+        Err(err) => return err.into(),
+    };
+
+    match member.delete(store.get_ref()).await {
+        Ok(()) => HttpResponse::Ok().body("member removed successfully"),
+        Err(err) => err.into(),
+    }
+}
@@ -1,16 +1,597 @@
+mod api_key;
+mod auth;
 mod base;
+mod member;
 mod org;
 mod provider;
+mod totp;
 mod user_provider;
 
+use actix_web::web;
+
 /// Initializes all routes
-pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
+pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(base::index);
-    cfg.service(user_provider::post);
-    cfg.service(user_provider::get);
-    cfg.service(user_provider::patch);
-    cfg.service(user_provider::delete);
-    cfg.service(user_provider::authorise);
-    cfg.service(user_provider::refresh);
-    cfg.service(org::delete);
+    cfg.service(base::metrics);
+    cfg.service(
+        web::scope("/org")
+            .service(org::post)
+            .service(api_key::post)
+            .service(api_key::get_all)
+            .service(api_key::delete)
+            .service(member::get_all)
+            .service(member::invite)
+            .service(member::accept)
+            .service(member::patch)
+            .service(member::delete)
+            .service(org::change_password)
+            .service(org::session)
+            .service(org::request_reset)
+            .service(org::confirm_reset)
+            .service(totp::enroll)
+            .service(totp::confirm)
+            .service(totp::delete)
+            .service(org::get)
+            .service(org::patch)
+            .service(org::delete),
+    );
+    cfg.service(
+        web::scope("/provider")
+            .service(provider::post)
+            .service(provider::get_all)
+            .service(provider::get)
+            .service(provider::patch)
+            .service(provider::delete),
+    );
+    cfg.service(
+        web::scope("/user")
+            .service(user_provider::authorise)
+            .service(user_provider::refresh)
+            .service(user_provider::post)
+            .service(user_provider::get)
+            .service(user_provider::patch)
+            .service(user_provider::delete),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hotp;
+    use crate::hasher::Hasher;
+    use crate::notifier::{LogNotifier, Notifier};
+    use crate::store::{MemoryStore, Store};
+    use crate::Config;
+    use actix_http::Request;
+    use actix_web::dev::{Service, ServiceResponse};
+    use actix_web::{http::header, test, App};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    /// Initializes every route with the test config, a log notifier, an http
+    /// client and the given store
+    async fn test_app(
+        store: Arc<dyn Store>,
+    ) -> impl Service<Request, Response = ServiceResponse, Error = actix_web::Error> {
+        test::init_service(
+            App::new()
+                .app_data(web::Data::from(store))
+                .app_data(web::Data::new(Config::test()))
+                .app_data(web::Data::new(Hasher::new(&Config::test().hash)))
+                .app_data(web::Data::from(Arc::new(LogNotifier) as Arc<dyn Notifier>))
+                .app_data(web::Data::new(reqwest::Client::new()))
+                .configure(init),
+        )
+        .await
+    }
+
+    /// Basic auth header value for an org
+    fn basic(id: &str, password: &str) -> String {
+        format!("Basic {}", base64::encode(format!("{}:{}", id, password)))
+    }
+
+    #[actix_web::test]
+    async fn org_and_provider() {
+        let app = test_app(Arc::new(MemoryStore::new())).await;
+
+        let org: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/org")
+                .set_json(
+                    json!({"name": "acme", "password": "pw", "owner": {"password": "ownerpw"}}),
+                )
+                .to_request(),
+        )
+        .await;
+        let id = org["id"].as_str().unwrap();
+        let auth = (header::AUTHORIZATION, basic(id, "pw"));
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/provider")
+                .insert_header((header::AUTHORIZATION, basic(id, "wrong")))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 401);
+
+        let provider: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/provider")
+                .insert_header(auth.clone())
+                .set_json(
+                    json!({"id": "client", "secret": "secret", "domain": "https://example.com"}),
+                )
+                .to_request(),
+        )
+        .await;
+        assert_eq!(provider["secret"], "secret");
+
+        let providers: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get()
+                .uri("/provider")
+                .insert_header(auth.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(providers.as_array().unwrap().len(), 1);
+        assert_eq!(providers[0]["secret"], Value::Null);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("/user/auth?provider={}", provider["key"]))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 302);
+
+        let api_key: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/org/keys")
+                .insert_header(auth.clone())
+                .set_json(json!({"name": "dashboard", "scopes": ["providers:read"]}))
+                .to_request(),
+        )
+        .await;
+        let bearer = (
+            header::AUTHORIZATION,
+            format!("Bearer {}", api_key["key"].as_str().unwrap()),
+        );
+
+        let providers: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get()
+                .uri("/provider")
+                .insert_header(bearer.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(providers.as_array().unwrap().len(), 1);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/provider")
+                .insert_header(bearer.clone())
+                .set_json(
+                    json!({"id": "other", "secret": "secret", "domain": "https://example.com"}),
+                )
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 403);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::delete()
+                .uri("/org")
+                .insert_header(bearer.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 403);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::delete()
+                .uri(&format!("/org/keys/{}", api_key["id"]))
+                .insert_header(auth.clone())
+                .to_request(),
+        )
+        .await;
+        assert!(resp.status().is_success());
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/provider")
+                .insert_header(bearer)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 401);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::delete()
+                .uri("/org")
+                .insert_header(auth.clone())
+                .to_request(),
+        )
+        .await;
+        assert!(resp.status().is_success());
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("/org/{}", id))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 404);
+
+        let metrics =
+            test::call_and_read_body(&app, test::TestRequest::get().uri("/metrics").to_request())
+                .await;
+        assert!(String::from_utf8_lossy(&metrics).contains("authrio_hash_queue_depth 0\n"));
+    }
+
+    #[actix_web::test]
+    async fn members() {
+        let app = test_app(Arc::new(MemoryStore::new())).await;
+
+        let org: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/org")
+                .set_json(
+                    json!({"name": "acme", "password": "pw", "owner": {"name": "alice", "password": "alicepw"}}),
+                )
+                .to_request(),
+        )
+        .await;
+        assert_eq!(org["owner"]["role"], "owner");
+
+        // clients which predate owners still get one, using the org password
+        let legacy: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/org")
+                .set_json(json!({"name": "legacy", "password": "pw"}))
+                .to_request(),
+        )
+        .await;
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/org/members")
+                .insert_header((
+                    header::AUTHORIZATION,
+                    basic(legacy["owner"]["id"].as_str().unwrap(), "pw"),
+                ))
+                .to_request(),
+        )
+        .await;
+        assert!(resp.status().is_success());
+
+        let owner_id = org["owner"]["id"].as_str().unwrap();
+        let owner = (header::AUTHORIZATION, basic(owner_id, "alicepw"));
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/org/members")
+                .insert_header((header::AUTHORIZATION, basic(owner_id, "pw")))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 401);
+
+        let invite: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/org/members/invite")
+                .insert_header(owner.clone())
+                .set_json(json!({"role": "viewer"}))
+                .to_request(),
+        )
+        .await;
+        let viewer: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/org/members/accept")
+                .set_json(json!({"token": invite["token"], "name": "bob", "password": "bobpw"}))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(viewer["org_id"], org["id"]);
+        let viewer = (
+            header::AUTHORIZATION,
+            basic(viewer["id"].as_str().unwrap(), "bobpw"),
+        );
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/provider")
+                .insert_header(viewer.clone())
+                .to_request(),
+        )
+        .await;
+        assert!(resp.status().is_success());
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::delete()
+                .uri("/org")
+                .insert_header(viewer.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 403);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/org/password")
+                .insert_header(viewer.clone())
+                .set_json(json!({"password": "newpw"}))
+                .to_request(),
+        )
+        .await;
+        assert!(resp.status().is_success());
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/provider")
+                .insert_header(viewer)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 401);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::patch()
+                .uri(&format!("/org/members/{}", owner_id))
+                .insert_header(owner.clone())
+                .set_json(json!({"role": "admin"}))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 409);
+
+        let members: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get()
+                .uri("/org/members")
+                .insert_header(owner)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(members.as_array().unwrap().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn totp() {
+        let app = test_app(Arc::new(MemoryStore::new())).await;
+
+        let org: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/org")
+                .set_json(
+                    json!({"name": "acme", "password": "pw", "owner": {"password": "ownerpw"}}),
+                )
+                .to_request(),
+        )
+        .await;
+        let auth = (
+            header::AUTHORIZATION,
+            basic(org["id"].as_str().unwrap(), "pw"),
+        );
+        let providers = |otp: Option<&str>| {
+            let req = test::TestRequest::get()
+                .uri("/provider")
+                .insert_header(auth.clone());
+            match otp {
+                Some(otp) => req.insert_header(("X-OTP", otp)),
+                None => req,
+            }
+            .to_request()
+        };
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::patch()
+                .uri("/org")
+                .insert_header(auth.clone())
+                .set_json(json!({"totp_required": true}))
+                .to_request(),
+        )
+        .await;
+        assert!(resp.status().is_success());
+        let resp = test::call_service(&app, providers(None)).await;
+        assert_eq!(resp.status(), 403);
+
+        let enrolled: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/org/totp")
+                .insert_header(auth.clone())
+                .to_request(),
+        )
+        .await;
+        let secret = data_encoding::BASE32_NOPAD
+            .decode(enrolled["secret"].as_str().unwrap().as_bytes())
+            .unwrap();
+        let code = hotp(&secret, (chrono::Utc::now().timestamp() / 30) as u64, 6);
+        let confirmed: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/org/totp/confirm")
+                .insert_header(auth.clone())
+                .set_json(json!({ "code": format!("{:06}", code) }))
+                .to_request(),
+        )
+        .await;
+        let recovery = confirmed["recovery_codes"][0].as_str().unwrap();
+
+        let resp = test::call_service(&app, providers(None)).await;
+        assert_eq!(resp.status(), 401);
+        let resp = test::call_service(&app, providers(Some(recovery))).await;
+        assert!(resp.status().is_success());
+        let resp = test::call_service(&app, providers(Some(recovery))).await;
+        assert_eq!(resp.status(), 401);
+
+        // a session lets one code cover many requests
+        let code = hotp(&secret, (chrono::Utc::now().timestamp() / 30 + 1) as u64, 6);
+        let session: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/org/session")
+                .insert_header(auth.clone())
+                .insert_header(("X-OTP", format!("{:06}", code)))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(session["scopes"], json!(["org:admin"]));
+        for _ in 0..2 {
+            let resp = test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri("/provider")
+                    .insert_header((
+                        header::AUTHORIZATION,
+                        format!("Bearer {}", session["key"].as_str().unwrap()),
+                    ))
+                    .to_request(),
+            )
+            .await;
+            assert!(resp.status().is_success());
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn user_flow() {
+        use crate::oauth::tests::{mock_provider, ROTATED_REFRESH};
+        use crate::store::{sqlite::tests::migrated_pool, SqliteStore};
+
+        let pool = migrated_pool().await;
+        let app = test_app(Arc::new(SqliteStore::new(pool.clone(), Config::test()))).await;
+        let mut orgs = vec![];
+        for name in ["acme", "other"] {
+            let org: Value = test::call_and_read_body_json(
+                &app,
+                test::TestRequest::post()
+                    .uri("/org")
+                    .set_json(json!({"name": name, "password": "pw"}))
+                    .to_request(),
+            )
+            .await;
+            orgs.push(org["id"].as_str().unwrap().to_string());
+        }
+        let auth = (header::AUTHORIZATION, basic(&orgs[0], "pw"));
+        let other = (header::AUTHORIZATION, basic(&orgs[1], "pw"));
+
+        let provider: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/provider")
+                .insert_header(auth.clone())
+                .set_json(json!({"id": "client", "secret": "secret", "domain": mock_provider()}))
+                .to_request(),
+        )
+        .await;
+
+        // the end user is sent to the provider, which returns them to the org
+        // with the state and a code issued to the challenge
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("/user/auth?provider={}", provider["key"]))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 302);
+        let binding = resp
+            .headers()
+            .get("X-OAuth-Binding")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let location = reqwest::Url::parse(
+            resp.headers()
+                .get(header::LOCATION)
+                .unwrap()
+                .to_str()
+                .unwrap(),
+        )
+        .unwrap();
+        let param = |key| {
+            location
+                .query_pairs()
+                .find(|(got, _)| got == key)
+                .unwrap()
+                .1
+                .into_owned()
+        };
+        let callback = json!({
+            "state": param("state"),
+            "binding": binding,
+            "code": param("code_challenge"),
+        });
+        let finish = |auth: (header::HeaderName, String), data: &Value| {
+            test::TestRequest::post()
+                .uri("/user")
+                .insert_header(auth)
+                .set_json(data)
+                .to_request()
+        };
+
+        // other orgs can't use the state, which is left for the org owning it
+        let resp = test::call_service(&app, finish(other.clone(), &callback)).await;
+        assert_eq!(resp.status(), 400);
+        let user: Value =
+            test::call_and_read_body_json(&app, finish(auth.clone(), &callback)).await;
+        assert_eq!(user["token_access"], "access");
+        let resp = test::call_service(&app, finish(auth.clone(), &callback)).await;
+        assert_eq!(resp.status(), 400);
+
+        // tokens are only stored encrypted
+        let id = user["id"].as_i64().unwrap() as i32;
+        let (access, refresh): (Vec<u8>, Vec<u8>) =
+            sqlx::query_as("SELECT token_access, token_refresh FROM user_provider WHERE id = $1")
+                .bind(id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert!(!access.windows(6).any(|part| part == b"access"));
+        assert!(!refresh.windows(12).any(|part| part == b"good-refresh"));
+
+        let refresh = |auth: (header::HeaderName, String)| {
+            test::TestRequest::post()
+                .uri("/user/refresh")
+                .insert_header(auth)
+                .set_json(json!({"id": user["id"]}))
+                .to_request()
+        };
+        let resp = test::call_service(&app, refresh(other)).await;
+        assert_eq!(resp.status(), 404);
+        let refreshed: Value = test::call_and_read_body_json(&app, refresh(auth.clone())).await;
+        assert_eq!(refreshed["token_access"], "refreshed");
+
+        let stored = SqliteStore::new(pool, Config::test())
+            .get_user_provider(orgs[0].parse().unwrap(), id)
+            .await
+            .unwrap();
+        assert_eq!(stored.token_refresh.as_deref(), Some(ROTATED_REFRESH));
+    }
 }
@@ -1,68 +1,247 @@
-use crate::{models::Org, AuthError, Config, OrgError};
+use super::api_key::ApiKeyResponse;
+use super::auth::{Authorized, OrgAdmin};
+use super::member::MemberResponse;
+use crate::{
+    hasher::Hasher,
+    models::{Org, PasswordAuth, PasswordReset},
+    notifier::Notifier,
+    store::Store,
+    AuthError, Config, OrgError,
+};
 use actix_web::{delete, get, patch, post, web, HttpResponse, Responder};
-use actix_web_httpauth::extractors::basic::BasicAuth;
-use serde::Deserialize;
-use sqlx::PgPool;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+/// Publicly viewable information of an [Org], excluding credentials
+#[derive(Serialize)]
+struct OrgResponse {
+    id: Uuid,
+    name: String,
+    created: DateTime<Utc>,
+}
+
+impl From<Org> for OrgResponse {
+    fn from(org: Org) -> Self {
+        Self {
+            id: org.id,
+            name: org.name,
+            created: org.created,
+        }
+    }
+}
+
+/// Name given to an org's first owner if none was chosen
+const DEFAULT_OWNER: &str = "owner";
+
+/// Information of a newly created [Org] alongside it's first owner
+#[derive(Serialize)]
+struct OrgCreatedResponse {
+    #[serde(flatten)]
+    org: OrgResponse,
+    owner: MemberResponse,
+}
+
+/// Name and password of an org's first owner, see [OrgPost]
+#[derive(Deserialize)]
+struct OwnerPost {
+    name: Option<String>,
+    password: String,
+}
+
 #[derive(Deserialize)]
 struct OrgPost {
     name: String,
     password: String,
+    owner: Option<OwnerPost>,
 }
 
-// TODO: finish
-#[post("/")]
+/// Creates an org alongside it's first owner, who has a password of their own
+/// which starts out as the org's if none was given
+#[post("")]
 async fn post(
-    _pool: web::Data<PgPool>,
+    store: web::Data<dyn Store>,
     config: web::Data<Config>,
+    hasher: web::Data<Hasher>,
     data: web::Json<OrgPost>,
 ) -> impl Responder {
-    match Org::new(config.get_ref(), data.name.clone(), data.password.clone()) {
-        Ok(_org) => HttpResponse::ServiceUnavailable().body("add org to db"),
+    let org = match Org::new(
+        config.get_ref(),
+        hasher.get_ref(),
+        data.name.clone(),
+        &data.password,
+    )
+    .await
+    {
+        Ok(org) => org,
+        Err(err) => return err.into(),
+    };
+
+    let (owner_name, owner_password) = match &data.owner {
+        Some(owner) => (
+            owner.name.as_deref().unwrap_or(DEFAULT_OWNER),
+            owner.password.as_str(),
+        ),
+        None => (DEFAULT_OWNER, data.password.as_str()),
+    };
+
+    match org
+        .create_with_owner(
+            store.get_ref(),
+            config.get_ref(),
+            hasher.get_ref(),
+            owner_name,
+            owner_password,
+        )
+        .await
+    {
+        Ok(owner) => HttpResponse::Created().json(OrgCreatedResponse {
+            org: OrgResponse::from(org),
+            owner: MemberResponse::from(owner),
+        }),
         Err(err) => err.into(),
     }
 }
 
-/// TODO: finish
-#[get("/<id>")]
-async fn get(_pool: web::Data<PgPool>, query_id: web::Query<String>) -> impl Responder {
-    let _id = match Uuid::parse_str(&query_id.0)
-        .map_err(|err| AuthError::new(OrgError::InvalidUuidQuery(err), query_id.0))
+#[get("/{id}")]
+async fn get(store: web::Data<dyn Store>, path_id: web::Path<String>) -> impl Responder {
+    let path_id = path_id.into_inner();
+    let id = match Uuid::parse_str(&path_id)
+        .map_err(|err| AuthError::new(OrgError::InvalidUuidQuery(err), path_id))
     {
         Ok(id) => id,
         Err(err) => return err.into(),
     };
 
-    HttpResponse::ServiceUnavailable().body("get org by id")
+    match Org::get(store.get_ref(), id).await {
+        Ok(org) => HttpResponse::Ok().json(OrgResponse::from(org)),
+        Err(err) => err.into(),
+    }
 }
 
 #[derive(Deserialize)]
 struct OrgPatch {
     name: Option<String>,
-    password: Option<String>,
+    totp_required: Option<bool>,
 }
 
-#[patch("/")]
+#[patch("")]
 async fn patch(
-    pool: web::Data<PgPool>,
-    org_auth: BasicAuth,
+    store: web::Data<dyn Store>,
+    auth: Authorized<OrgAdmin>,
     data: web::Json<OrgPatch>,
 ) -> impl Responder {
-    match Org::auth_patch(
-        pool.get_ref(),
-        org_auth,
-        data.name.clone(),
-        data.password.clone(),
-    ) {
+    let mut org = auth.org;
+    let data = data.into_inner();
+
+    match org
+        .patch(store.get_ref(), data.name, data.totp_required)
+        .await
+    {
         Ok(()) => HttpResponse::Ok().body("organisation patched successfully"),
         Err(err) => err.into(),
     }
 }
 
-#[delete("/")]
-async fn delete(pool: web::Data<PgPool>, org_auth: BasicAuth) -> impl Responder {
-    match Org::auth_delete(pool.get_ref(), org_auth) {
+#[derive(Deserialize)]
+struct PasswordPost {
+    password: String,
+}
+
+/// Changes the password of the org or member logging in, only allowing basic
+/// auth as the current password must be known
+#[post("/password")]
+async fn change_password(
+    store: web::Data<dyn Store>,
+    config: web::Data<Config>,
+    hasher: web::Data<Hasher>,
+    auth: PasswordAuth,
+    data: web::Json<PasswordPost>,
+) -> impl Responder {
+    match Org::change_password(
+        store.get_ref(),
+        config.get_ref(),
+        hasher.get_ref(),
+        auth,
+        &data.password,
+    )
+    .await
+    {
+        Ok(()) => HttpResponse::Ok().body("password changed successfully"),
+        Err(err) => err.into(),
+    }
+}
+
+/// Trades a password login, and the one-time code it may need, for a
+/// short-lived api key so repeated requests don't each need a new code
+#[post("/session")]
+async fn session(
+    store: web::Data<dyn Store>,
+    config: web::Data<Config>,
+    hasher: web::Data<Hasher>,
+    auth: PasswordAuth,
+) -> impl Responder {
+    match Org::start_session(store.get_ref(), config.get_ref(), hasher.get_ref(), auth).await {
+        Ok((api_key, key)) => HttpResponse::Created().json(ApiKeyResponse::new(api_key, Some(key))),
+        Err(err) => err.into(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ResetPost {
+    id: Uuid,
+}
+
+/// Sends a token resetting the org's shared password, not that of any member,
+/// through the [Notifier], responding identically whether or not the org
+/// exists or the token got delivered
+#[post("/password/reset")]
+async fn request_reset(
+    store: web::Data<dyn Store>,
+    notifier: web::Data<dyn Notifier>,
+    data: web::Json<ResetPost>,
+) -> impl Responder {
+    match PasswordReset::request(store.get_ref(), notifier.get_ref(), data.id).await {
+        Ok(()) => HttpResponse::Accepted().body("password reset requested"),
+        Err(err) => err.into(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ResetConfirmPost {
+    token: String,
+    password: String,
+    #[serde(default)]
+    revoke_keys: bool,
+}
+
+/// Sets a new org password using a reset token, needing no credentials as
+/// the token is one
+#[post("/password/reset/confirm")]
+async fn confirm_reset(
+    store: web::Data<dyn Store>,
+    config: web::Data<Config>,
+    hasher: web::Data<Hasher>,
+    data: web::Json<ResetConfirmPost>,
+) -> impl Responder {
+    match PasswordReset::confirm(
+        store.get_ref(),
+        config.get_ref(),
+        hasher.get_ref(),
+        &data.token,
+        &data.password,
+        data.revoke_keys,
+    )
+    .await
+    {
+        Ok(_) => HttpResponse::Ok().body("password reset successfully"),
+        Err(err) => err.into(),
+    }
+}
+
+#[delete("")]
+async fn delete(store: web::Data<dyn Store>, auth: Authorized<OrgAdmin>) -> impl Responder {
+    match auth.org.delete(store.get_ref()).await {
         Ok(()) => HttpResponse::Ok().body("organisation deleted successfully"),
         Err(err) => err.into(),
     }
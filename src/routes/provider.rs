@@ -1 +1,154 @@
-//! TODO
\ No newline at end of file
+use super::auth::{Authorized, ProvidersRead, ProvidersWrite};
+use crate::{models::Provider, store::Store};
+use actix_web::{delete, get, patch, post, web, HttpResponse, Responder};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Viewable information of a [Provider], only including the secret on creation
+#[derive(Serialize)]
+struct ProviderResponse {
+    key: i32,
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    domain: String,
+    redirect_uri: Option<String>,
+    scope: Option<String>,
+    created: DateTime<Utc>,
+}
+
+impl ProviderResponse {
+    /// Creates response from a [Provider], optionally including it's secret
+    fn new(provider: Provider, with_secret: bool) -> Self {
+        Self {
+            key: provider.key,
+            id: provider.id,
+            secret: if with_secret {
+                Some(provider.secret)
+            } else {
+                None
+            },
+            domain: provider.domain,
+            redirect_uri: provider.redirect_uri,
+            scope: provider.scope,
+            created: provider.created,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ProviderPost {
+    id: String,
+    secret: String,
+    domain: String,
+    redirect_uri: Option<String>,
+    scope: Option<String>,
+}
+
+#[post("")]
+async fn post(
+    store: web::Data<dyn Store>,
+    auth: Authorized<ProvidersWrite>,
+    data: web::Json<ProviderPost>,
+) -> impl Responder {
+    let org = auth.org;
+
+    let data = data.into_inner();
+    let provider = match Provider::new(
+        data.id,
+        data.secret,
+        data.domain,
+        data.redirect_uri,
+        data.scope,
+        org.id,
+    ) {
+        Ok(val) => val,
+        Err(err) => return err.into(),
+    };
+
+    match provider.create(store.get_ref()).await {
+        Ok(()) => HttpResponse::Created().json(ProviderResponse::new(provider, true)),
+        Err(err) => err.into(),
+    }
+}
+
+#[get("")]
+async fn get_all(store: web::Data<dyn Store>, auth: Authorized<ProvidersRead>) -> impl Responder {
+    let org = auth.org;
+
+    match Provider::list(store.get_ref(), org.id).await {
+        Ok(providers) => HttpResponse::Ok().json(
+            providers
+                .into_iter()
+                .map(|provider| ProviderResponse::new(provider, false))
+                .collect::<Vec<_>>(),
+        ),
+        Err(err) => err.into(),
+    }
+}
+
+#[get("/{id}")]
+async fn get(
+    store: web::Data<dyn Store>,
+    auth: Authorized<ProvidersRead>,
+    path_id: web::Path<String>,
+) -> impl Responder {
+    let org = auth.org;
+
+    match Provider::get(store.get_ref(), org.id, path_id.into_inner()).await {
+        Ok(provider) => HttpResponse::Ok().json(ProviderResponse::new(provider, false)),
+        Err(err) => err.into(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ProviderPatch {
+    secret: Option<String>,
+    domain: Option<String>,
+    redirect_uri: Option<String>,
+    scope: Option<String>,
+}
+
+#[patch("/{id}")]
+async fn patch(
+    store: web::Data<dyn Store>,
+    auth: Authorized<ProvidersWrite>,
+    path_id: web::Path<String>,
+    data: web::Json<ProviderPatch>,
+) -> impl Responder {
+    let org = auth.org;
+
+    let provider = match Provider::get(store.get_ref(), org.id, path_id.into_inner()).await {
+        Ok(val) => val,
+        Err(err) => return err.into(),
+    };
+
+    let data = data.into_inner();
+    match provider
+        .patch(
+            store.get_ref(),
+            data.secret,
+            data.domain,
+            data.redirect_uri,
+            data.scope,
+        )
+        .await
+    {
+        Ok(provider) => HttpResponse::Ok().json(ProviderResponse::new(provider, false)),
+        Err(err) => err.into(),
+    }
+}
+
+#[delete("/{id}")]
+async fn delete(
+    store: web::Data<dyn Store>,
+    auth: Authorized<ProvidersWrite>,
+    path_id: web::Path<String>,
+) -> impl Responder {
+    let org = auth.org;
+
+    match Provider::delete(store.get_ref(), org.id, path_id.into_inner()).await {
+        Ok(()) => HttpResponse::Ok().body("provider deleted successfully"),
+        Err(err) => err.into(),
+    }
+}
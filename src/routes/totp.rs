@@ -0,0 +1,79 @@
+use crate::{
+    hasher::Hasher,
+    models::{Org, PasswordAuth},
+    store::Store,
+    Config,
+};
+use actix_web::{delete, post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+/// Secret of a newly enrolled [Totp](crate::models::Totp), shown only once
+#[derive(Serialize)]
+struct EnrollResponse {
+    secret: String,
+    uri: String,
+}
+
+/// Starts two-factor enrollment for the org or member logging in, which only
+/// takes effect once confirmed with a code
+#[post("/totp")]
+async fn enroll(
+    store: web::Data<dyn Store>,
+    config: web::Data<Config>,
+    hasher: web::Data<Hasher>,
+    auth: PasswordAuth,
+) -> impl Responder {
+    match Org::enroll_totp(store.get_ref(), config.get_ref(), hasher.get_ref(), auth).await {
+        Ok(totp) => HttpResponse::Created().json(EnrollResponse {
+            uri: totp.uri(),
+            secret: totp.secret,
+        }),
+        Err(err) => err.into(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ConfirmPost {
+    code: String,
+}
+
+/// Recovery codes given once two-factor is enabled, shown only once
+#[derive(Serialize)]
+struct ConfirmResponse {
+    recovery_codes: Vec<String>,
+}
+
+#[post("/totp/confirm")]
+async fn confirm(
+    store: web::Data<dyn Store>,
+    config: web::Data<Config>,
+    hasher: web::Data<Hasher>,
+    auth: PasswordAuth,
+    data: web::Json<ConfirmPost>,
+) -> impl Responder {
+    match Org::confirm_totp(
+        store.get_ref(),
+        config.get_ref(),
+        hasher.get_ref(),
+        auth,
+        &data.code,
+    )
+    .await
+    {
+        Ok(recovery_codes) => HttpResponse::Ok().json(ConfirmResponse { recovery_codes }),
+        Err(err) => err.into(),
+    }
+}
+
+#[delete("/totp")]
+async fn delete(
+    store: web::Data<dyn Store>,
+    config: web::Data<Config>,
+    hasher: web::Data<Hasher>,
+    auth: PasswordAuth,
+) -> impl Responder {
+    match Org::disable_totp(store.get_ref(), config.get_ref(), hasher.get_ref(), auth).await {
+        Ok(()) => HttpResponse::Ok().body("two-factor disabled successfully"),
+        Err(err) => err.into(),
+    }
+}
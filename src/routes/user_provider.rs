@@ -1,63 +1,195 @@
-use crate::{models::Org, Config};
-use actix_web::{delete, get, patch, post, web, HttpResponse, Responder};
-use actix_web_httpauth::extractors::basic::BasicAuth;
-use sqlx::PgPool;
+use super::auth::{Authorized, UsersDelete, UsersRead, UsersWrite};
+use crate::{
+    models::{OauthState, Provider, UserProvider},
+    oauth,
+    store::Store,
+    AuthError, ProviderError, UserError,
+};
+use actix_web::{delete, get, http::header, patch, post, web, HttpResponse, Responder};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
 
-// TODO: make user cred header
-// TODO: figure out error handling
+/// Viewable information of a [UserProvider], excluding the refresh token which is
+/// only used internally
+#[derive(Serialize)]
+struct UserProviderResponse {
+    id: i32,
+    token_access: String,
+    token_expires: Option<DateTime<Utc>>,
+    provider_id: i32,
+    created: DateTime<Utc>,
+}
 
-/// TODO: finish
-#[post("/")]
-pub async fn post(_pool: web::Data<PgPool>, _config: web::Data<Config>) -> impl Responder {
-    // add optional data argument which then directly adds to db instead of redirecting to a provider?
-    HttpResponse::ServiceUnavailable().body("post user provider")
+impl From<UserProvider> for UserProviderResponse {
+    fn from(user: UserProvider) -> Self {
+        Self {
+            id: user.id,
+            token_access: user.token_access,
+            token_expires: user.token_expires,
+            provider_id: user.provider_id,
+            created: user.created,
+        }
+    }
 }
 
-/// TODO: finish
-#[get("/")]
-pub async fn get(
-    _pool: web::Data<PgPool>,
-    _config: web::Data<Config>,
-    org_auth: BasicAuth,
+/// Header of [authorise] responses holding the binding to give back to [post]
+const BINDING_HEADER: &str = "X-OAuth-Binding";
+
+#[derive(Deserialize)]
+struct UserProviderPost {
+    state: Option<String>,
+    binding: Option<String>,
+    code: String,
+}
+
+/// Exchanges an authorization code sent to the provider's redirect uri for tokens
+/// and stores them as a new user, only accepting a `state` issued by [authorise]
+/// alongside the binding it gave whoever started the flow
+#[post("")]
+pub async fn post(
+    store: web::Data<dyn Store>,
+    client: web::Data<reqwest::Client>,
+    auth: Authorized<UsersWrite>,
+    data: web::Json<UserProviderPost>,
 ) -> impl Responder {
-    let _org = match Org::from_auth(org_auth) {
-        Ok(val) => val,
+    let org = auth.org;
+
+    let state = match &data.state {
+        Some(state) => match OauthState::consume(
+            store.get_ref(),
+            org.id,
+            state,
+            data.binding.as_deref().unwrap_or_default(),
+        )
+        .await
+        {
+            Ok(val) => val,
+            Err(err) => return err.into(),
+        },
+        None => return AuthError::<String>::new(UserError::StateMissing, None).into(),
+    };
+
+    let provider = match Provider::get_by_key(store.get_ref(), state.provider_id).await {
+        Ok(val) if val.org_id == org.id => val,
+        Ok(val) => return AuthError::new(ProviderError::NotFound, val.id).into(),
         Err(err) => return err.into(),
     };
 
-    HttpResponse::ServiceUnavailable().body("get user provider")
+    let user = match oauth::exchange_code(
+        client.get_ref(),
+        &provider,
+        &data.code,
+        &state.code_verifier,
+    )
+    .await
+    {
+        Ok(token) => token.into_user_provider(provider.key),
+        Err(err) => return err.into(),
+    };
+
+    match user.create(store.get_ref()).await {
+        Ok(()) => HttpResponse::Created().json(UserProviderResponse::from(user)),
+        Err(err) => err.into(),
+    }
+}
+
+#[get("/{id}")]
+pub async fn get(
+    store: web::Data<dyn Store>,
+    auth: Authorized<UsersRead>,
+    path_id: web::Path<i32>,
+) -> impl Responder {
+    let org = auth.org;
+
+    match UserProvider::get(store.get_ref(), org.id, path_id.into_inner()).await {
+        Ok(user) => HttpResponse::Ok().json(UserProviderResponse::from(user)),
+        Err(err) => err.into(),
+    }
 }
 
 /// TODO: finish
-#[patch("/")]
-pub async fn patch(_pool: web::Data<PgPool>, _config: web::Data<Config>) -> impl Responder {
+#[patch("")]
+pub async fn patch(_store: web::Data<dyn Store>, _auth: Authorized<UsersWrite>) -> impl Responder {
     HttpResponse::ServiceUnavailable().body("patch user provider")
 }
 
-/// TODO: figure out how to auth this
-/// TODO: finish
-#[delete("/")]
+#[delete("/{id}")]
 pub async fn delete(
-    _pool: web::Data<PgPool>,
-    _config: web::Data<Config>,
-    org_auth: BasicAuth,
+    store: web::Data<dyn Store>,
+    auth: Authorized<UsersDelete>,
+    path_id: web::Path<i32>,
+) -> impl Responder {
+    let org = auth.org;
+
+    match UserProvider::delete(store.get_ref(), org.id, path_id.into_inner()).await {
+        Ok(()) => HttpResponse::Ok().body("user provider deleted successfully"),
+        Err(err) => err.into(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuthoriseQuery {
+    provider: i32,
+}
+
+/// Redirects end users to the provider in order to start an authorization code
+/// flow, which ends at the provider's redirect uri with a `state` to give to
+/// [post]. The [BINDING_HEADER] must be given back alongside it, so whoever
+/// starts the flow should fetch this without following the redirect, keep the
+/// binding within the end user's session and then send them to the location
+#[get("/auth")]
+pub async fn authorise(
+    store: web::Data<dyn Store>,
+    query: web::Query<AuthoriseQuery>,
 ) -> impl Responder {
-    let _org = match Org::from_auth(org_auth) {
+    let provider = match Provider::get_by_key(store.get_ref(), query.provider).await {
         Ok(val) => val,
         Err(err) => return err.into(),
     };
 
-    HttpResponse::ServiceUnavailable().body("delete user provider")
+    let (state, binding) = OauthState::new(provider.key);
+    match state.create(store.get_ref()).await {
+        Ok(()) => HttpResponse::Found()
+            .insert_header((BINDING_HEADER, binding))
+            .insert_header((
+                header::LOCATION,
+                provider.authorize_url(&state.state, &state.code_challenge()),
+            ))
+            .finish(),
+        Err(err) => err.into(),
+    }
 }
 
-/// TODO: finish
-#[post("/auth")]
-pub async fn authorise(_pool: web::Data<PgPool>, _config: web::Data<Config>) -> impl Responder {
-    HttpResponse::ServiceUnavailable().body("authorise user provider")
+#[derive(Deserialize)]
+struct UserProviderRefresh {
+    id: i32,
 }
 
-/// TODO: finish
+/// Refreshes a user's access token with their provider, returning the new one
 #[post("/refresh")]
-pub async fn refresh(_pool: web::Data<PgPool>, _config: web::Data<Config>) -> impl Responder {
-    HttpResponse::ServiceUnavailable().body("refresh user provider's token")
+pub async fn refresh(
+    store: web::Data<dyn Store>,
+    client: web::Data<reqwest::Client>,
+    auth: Authorized<UsersWrite>,
+    data: web::Json<UserProviderRefresh>,
+) -> impl Responder {
+    let org = auth.org;
+
+    let mut user = match UserProvider::get(store.get_ref(), org.id, data.id).await {
+        Ok(val) => val,
+        Err(err) => return err.into(),
+    };
+
+    let provider = match Provider::get_by_key(store.get_ref(), user.provider_id).await {
+        Ok(val) => val,
+        Err(err) => return err.into(),
+    };
+
+    match user
+        .refresh(store.get_ref(), client.get_ref(), &provider)
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().json(UserProviderResponse::from(user)),
+        Err(err) => err.into(),
+    }
 }
@@ -0,0 +1,61 @@
+//! Sources of secret configuration values, so they don't need to be given as
+//! plain environment variables, see [SecretSource]
+
+use std::fs;
+use zeroize::Zeroizing;
+
+/// Source which secrets can be read from by a reference to them, such as a
+/// local file or a secret manager
+pub trait SecretSource: Send + Sync {
+    /// Suffix of the variable giving a reference to read, e.g. `_FILE` for
+    /// `PEPPER_FILE` giving the reference for `PEPPER`
+    fn suffix(&self) -> &'static str;
+
+    /// Reads the secret which `reference` points to, erroring with the reason
+    /// why it couldn't be read
+    fn read(&self, reference: &str) -> Result<Zeroizing<String>, String>;
+}
+
+/// Reads secrets from local files such as Docker or Kubernetes secrets, with
+/// the reference being the path and trailing newlines being removed
+pub struct FileSource;
+
+impl SecretSource for FileSource {
+    fn suffix(&self) -> &'static str {
+        "_FILE"
+    }
+
+    fn read(&self, reference: &str) -> Result<Zeroizing<String>, String> {
+        let mut secret =
+            Zeroizing::new(fs::read_to_string(reference).map_err(|err| err.to_string())?);
+        let len = secret.trim_end_matches(&['\r', '\n'][..]).len();
+        secret.truncate(len);
+
+        Ok(secret)
+    }
+}
+
+/// All available [SecretSource]s, checked in order
+pub fn sources() -> Vec<Box<dyn SecretSource>> {
+    vec![Box::new(FileSource)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_source() {
+        let path = std::env::temp_dir().join(format!("authrio-secret-{}", std::process::id()));
+        fs::write(&path, "secret\n").unwrap();
+
+        assert_eq!(
+            FileSource
+                .read(path.to_str().unwrap())
+                .map(|val| val.to_string()),
+            Ok("secret".to_string())
+        );
+        fs::remove_file(&path).unwrap();
+        assert!(FileSource.read(path.to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,697 @@
+//! See [MemoryStore] for documentation
+
+use super::Store;
+use crate::crypto::Hash;
+use crate::models::{
+    ApiKey, OauthState, Org, OrgInvite, OrgMember, PasswordReset, Provider, Role, Totp,
+    UserProvider,
+};
+use crate::{
+    ApiKeyError, AuthError, AuthErrorKind, AuthResult, MemberError, OrgError, ProviderError,
+    UserError,
+};
+use async_trait::async_trait;
+use chrono::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+/// In-memory [Store] for tests and local development, losing everything once
+/// dropped and never encrypting secrets
+#[derive(Default)]
+pub struct MemoryStore {
+    inner: Mutex<Tables>,
+}
+
+/// All rows of a [MemoryStore], keyed by primary key
+#[derive(Default)]
+struct Tables {
+    orgs: HashMap<Uuid, Org>,
+    members: HashMap<Uuid, OrgMember>,
+    invites: HashMap<i32, OrgInvite>,
+    password_resets: HashMap<i32, PasswordReset>,
+    totps: HashMap<i32, Totp>,
+    recovery_codes: HashMap<Vec<u8>, i32>,
+    api_keys: HashMap<i32, ApiKey>,
+    providers: HashMap<i32, Provider>,
+    user_providers: HashMap<i32, UserProvider>,
+    oauth_states: HashMap<String, OauthState>,
+}
+
+impl MemoryStore {
+    /// Creates a new empty [MemoryStore]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Locks all tables, recovering from poisoning as every write is atomic
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Tables {
+    /// Finds the [Provider::key] of a provider by it's org and [Provider::id]
+    fn provider_key(&self, org_id: Uuid, id: &str) -> Option<i32> {
+        self.providers
+            .values()
+            .find(|provider| provider.org_id == org_id && provider.id == id)
+            .map(|provider| provider.key)
+    }
+
+    /// Checks if a [UserProvider] belongs to a provider of the given org
+    fn user_in_org(&self, user: &UserProvider, org_id: Uuid) -> bool {
+        self.providers
+            .get(&user.provider_id)
+            .is_some_and(|provider| provider.org_id == org_id)
+    }
+
+    /// Removes a [Provider] alongside it's users and states
+    fn remove_provider(&mut self, key: i32) {
+        self.providers.remove(&key);
+        self.user_providers
+            .retain(|_, user| user.provider_id != key);
+        self.oauth_states
+            .retain(|_, state| state.provider_id != key);
+    }
+
+    /// Checks a member is within the given org and, unless `keeps_owner`, that
+    /// another owner would remain if they stopped being one
+    fn check_other_owner(&self, org_id: Uuid, id: Uuid, keeps_owner: bool) -> AuthResult<(), Uuid> {
+        let member = match self.members.get(&id) {
+            Some(member) if member.org_id == org_id => member,
+            _ => return Err(AuthError::new(MemberError::NotFound, id)),
+        };
+
+        if keeps_owner
+            || member.role != Role::Owner
+            || self
+                .members
+                .values()
+                .any(|other| other.org_id == org_id && other.role == Role::Owner && other.id != id)
+        {
+            Ok(())
+        } else {
+            Err(AuthError::new(MemberError::LastOwner, id))
+        }
+    }
+
+    /// Removes recovery codes whose [Totp] no longer exists
+    fn remove_orphan_recovery_codes(&mut self) {
+        let totps = &self.totps;
+        self.recovery_codes
+            .retain(|_, totp_id| totps.contains_key(totp_id));
+    }
+}
+
+/// Error matching a failed foreign key constraint from a database
+fn missing_reference<Id: std::fmt::Display + Clone>(
+    table: &str,
+    id: impl Into<Option<Id>>,
+) -> AuthError<Id> {
+    AuthError::new(
+        AuthErrorKind::DatabaseError(format!("referenced {} does not exist", table)),
+        id,
+    )
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn create_org(&self, org: &Org) -> AuthResult<(), Uuid> {
+        org.validate()?;
+        let mut tables = self.tables();
+
+        if tables.orgs.contains_key(&org.id) {
+            return Err(AuthError::new(
+                AuthErrorKind::DatabaseError("org id already exists".to_string()),
+                org.id,
+            ));
+        }
+
+        tables.orgs.insert(org.id, org.clone());
+        Ok(())
+    }
+
+    async fn get_org(&self, id: Uuid) -> AuthResult<Org, Uuid> {
+        self.tables()
+            .orgs
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| AuthError::new(OrgError::NotFound, id))
+    }
+
+    async fn update_org(&self, org: &Org) -> AuthResult<(), Uuid> {
+        org.validate()?;
+
+        if let Some(existing) = self.tables().orgs.get_mut(&org.id) {
+            existing.name = org.name.clone();
+            existing.totp_required = org.totp_required;
+        }
+
+        Ok(())
+    }
+
+    async fn set_org_password(&self, id: Uuid, password: &Hash) -> AuthResult<(), Uuid> {
+        if let Some(existing) = self.tables().orgs.get_mut(&id) {
+            existing.password = password.clone();
+        }
+
+        Ok(())
+    }
+
+    async fn delete_org(&self, id: Uuid) -> AuthResult<(), Uuid> {
+        let mut tables = self.tables();
+        tables.orgs.remove(&id);
+        tables.members.retain(|_, member| member.org_id != id);
+        tables.invites.retain(|_, invite| invite.org_id != id);
+        tables.password_resets.retain(|_, reset| reset.org_id != id);
+        tables.totps.retain(|_, totp| totp.org_id != id);
+        tables.remove_orphan_recovery_codes();
+        tables.api_keys.retain(|_, api_key| api_key.org_id != id);
+
+        let keys: Vec<i32> = tables
+            .providers
+            .values()
+            .filter(|provider| provider.org_id == id)
+            .map(|provider| provider.key)
+            .collect();
+        for key in keys {
+            tables.remove_provider(key);
+        }
+
+        Ok(())
+    }
+
+    async fn create_api_key(&self, api_key: &ApiKey) -> AuthResult<(), i32> {
+        let mut tables = self.tables();
+
+        if !tables.orgs.contains_key(&api_key.org_id) {
+            return Err(missing_reference("org", api_key.id));
+        }
+
+        if tables.api_keys.contains_key(&api_key.id)
+            || tables
+                .api_keys
+                .values()
+                .any(|existing| existing.key_hash == api_key.key_hash)
+        {
+            return Err(AuthError::new(
+                AuthErrorKind::DatabaseError("api key already exists".to_string()),
+                api_key.id,
+            ));
+        }
+
+        tables.api_keys.insert(api_key.id, api_key.clone());
+        Ok(())
+    }
+
+    async fn get_api_key_by_hash(&self, key_hash: &[u8]) -> AuthResult<Option<ApiKey>, i32> {
+        Ok(self
+            .tables()
+            .api_keys
+            .values()
+            .find(|api_key| api_key.key_hash == key_hash)
+            .cloned())
+    }
+
+    async fn list_api_keys(&self, org_id: Uuid) -> AuthResult<Vec<ApiKey>, i32> {
+        let mut api_keys: Vec<ApiKey> = self
+            .tables()
+            .api_keys
+            .values()
+            .filter(|api_key| api_key.org_id == org_id)
+            .cloned()
+            .collect();
+        api_keys.sort_by_key(|api_key| api_key.created);
+
+        Ok(api_keys)
+    }
+
+    async fn touch_api_key(&self, id: i32, used: DateTime<Utc>) -> AuthResult<(), i32> {
+        if let Some(existing) = self.tables().api_keys.get_mut(&id) {
+            existing.last_used = Some(used);
+        }
+
+        Ok(())
+    }
+
+    async fn delete_api_key(&self, org_id: Uuid, id: i32) -> AuthResult<(), i32> {
+        let mut tables = self.tables();
+
+        match tables.api_keys.get(&id) {
+            Some(api_key) if api_key.org_id == org_id => {
+                tables.api_keys.remove(&id);
+                Ok(())
+            }
+            _ => Err(AuthError::new(ApiKeyError::NotFound, id)),
+        }
+    }
+
+    async fn delete_api_keys(&self, org_id: Uuid) -> AuthResult<(), Uuid> {
+        self.tables()
+            .api_keys
+            .retain(|_, api_key| api_key.org_id != org_id);
+        Ok(())
+    }
+
+    async fn create_member(&self, member: &OrgMember) -> AuthResult<(), Uuid> {
+        member.validate()?;
+        let mut tables = self.tables();
+
+        if !tables.orgs.contains_key(&member.org_id) {
+            return Err(missing_reference("org", member.id));
+        }
+
+        if tables.members.contains_key(&member.id) {
+            return Err(AuthError::new(
+                AuthErrorKind::DatabaseError("member id already exists".to_string()),
+                member.id,
+            ));
+        }
+
+        tables.members.insert(member.id, member.clone());
+        Ok(())
+    }
+
+    async fn get_member(&self, id: Uuid) -> AuthResult<OrgMember, Uuid> {
+        self.tables()
+            .members
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| AuthError::new(MemberError::NotFound, id))
+    }
+
+    async fn list_members(&self, org_id: Uuid) -> AuthResult<Vec<OrgMember>, Uuid> {
+        let mut members: Vec<OrgMember> = self
+            .tables()
+            .members
+            .values()
+            .filter(|member| member.org_id == org_id)
+            .cloned()
+            .collect();
+        members.sort_by_key(|member| member.created);
+
+        Ok(members)
+    }
+
+    async fn set_member_role(&self, org_id: Uuid, id: Uuid, role: Role) -> AuthResult<(), Uuid> {
+        let mut tables = self.tables();
+        tables.check_other_owner(org_id, id, role == Role::Owner)?;
+
+        if let Some(existing) = tables.members.get_mut(&id) {
+            existing.role = role;
+        }
+
+        Ok(())
+    }
+
+    async fn set_member_password(&self, id: Uuid, password: &Hash) -> AuthResult<(), Uuid> {
+        if let Some(existing) = self.tables().members.get_mut(&id) {
+            existing.password = password.clone();
+        }
+
+        Ok(())
+    }
+
+    async fn delete_member(&self, org_id: Uuid, id: Uuid) -> AuthResult<(), Uuid> {
+        let mut tables = self.tables();
+        tables.check_other_owner(org_id, id, false)?;
+
+        tables.members.remove(&id);
+        tables.totps.retain(|_, totp| totp.login_id != id);
+        tables.remove_orphan_recovery_codes();
+        Ok(())
+    }
+
+    async fn create_invite(&self, invite: &OrgInvite, now: DateTime<Utc>) -> AuthResult<(), i32> {
+        let mut tables = self.tables();
+        tables.invites.retain(|_, existing| existing.expires > now);
+
+        if !tables.orgs.contains_key(&invite.org_id) {
+            return Err(missing_reference("org", invite.id));
+        }
+
+        tables.invites.insert(invite.id, invite.clone());
+        Ok(())
+    }
+
+    async fn take_invite(&self, token_hash: &[u8]) -> AuthResult<Option<OrgInvite>, i32> {
+        let mut tables = self.tables();
+        let id = tables
+            .invites
+            .values()
+            .find(|invite| invite.token_hash == token_hash)
+            .map(|invite| invite.id);
+
+        Ok(id.and_then(|id| tables.invites.remove(&id)))
+    }
+
+    async fn create_password_reset(
+        &self,
+        reset: &PasswordReset,
+        now: DateTime<Utc>,
+    ) -> AuthResult<bool, i32> {
+        let mut tables = self.tables();
+        tables
+            .password_resets
+            .retain(|_, existing| existing.expires > now);
+
+        if !tables.orgs.contains_key(&reset.org_id) {
+            return Err(missing_reference("org", reset.id));
+        }
+        if tables
+            .password_resets
+            .values()
+            .any(|existing| existing.org_id == reset.org_id)
+        {
+            return Ok(false);
+        }
+
+        tables.password_resets.insert(reset.id, reset.clone());
+        Ok(true)
+    }
+
+    async fn take_password_reset(
+        &self,
+        token_hash: &[u8],
+    ) -> AuthResult<Option<PasswordReset>, i32> {
+        let mut tables = self.tables();
+        let id = tables
+            .password_resets
+            .values()
+            .find(|reset| reset.token_hash == token_hash)
+            .map(|reset| reset.id);
+
+        Ok(id.and_then(|id| tables.password_resets.remove(&id)))
+    }
+
+    async fn create_totp(&self, totp: &Totp) -> AuthResult<(), Uuid> {
+        let mut tables = self.tables();
+
+        if !tables.orgs.contains_key(&totp.org_id) {
+            return Err(missing_reference("org", totp.login_id));
+        }
+
+        tables
+            .totps
+            .retain(|_, existing| existing.login_id != totp.login_id);
+        tables.remove_orphan_recovery_codes();
+        tables.totps.insert(totp.id, totp.clone());
+        Ok(())
+    }
+
+    async fn get_totp(&self, login_id: Uuid) -> AuthResult<Option<Totp>, Uuid> {
+        Ok(self
+            .tables()
+            .totps
+            .values()
+            .find(|totp| totp.login_id == login_id)
+            .cloned())
+    }
+
+    async fn confirm_totp(&self, id: i32, code_hashes: &[Vec<u8>]) -> AuthResult<(), i32> {
+        let mut tables = self.tables();
+
+        if let Some(existing) = tables.totps.get_mut(&id) {
+            existing.confirmed = true;
+        }
+
+        tables.recovery_codes.retain(|_, totp_id| *totp_id != id);
+        for code_hash in code_hashes {
+            tables.recovery_codes.insert(code_hash.clone(), id);
+        }
+
+        Ok(())
+    }
+
+    async fn advance_totp(&self, id: i32, step: i64) -> AuthResult<bool, i32> {
+        match self.tables().totps.get_mut(&id) {
+            Some(existing) if existing.last_step < step => {
+                existing.last_step = step;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn take_recovery_code(&self, totp_id: i32, code_hash: &[u8]) -> AuthResult<bool, i32> {
+        let mut tables = self.tables();
+
+        match tables.recovery_codes.get(code_hash) {
+            Some(existing) if *existing == totp_id => {
+                tables.recovery_codes.remove(code_hash);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete_totp(&self, login_id: Uuid) -> AuthResult<(), Uuid> {
+        let mut tables = self.tables();
+        tables.totps.retain(|_, totp| totp.login_id != login_id);
+        tables.remove_orphan_recovery_codes();
+        Ok(())
+    }
+
+    async fn create_provider(&self, provider: &Provider) -> AuthResult<(), String> {
+        provider.validate()?;
+        let mut tables = self.tables();
+
+        if !tables.orgs.contains_key(&provider.org_id) {
+            return Err(missing_reference("org", provider.id.clone()));
+        }
+
+        if tables.providers.contains_key(&provider.key)
+            || tables.provider_key(provider.org_id, &provider.id).is_some()
+        {
+            return Err(AuthError::new(
+                ProviderError::AlreadyExists,
+                provider.id.clone(),
+            ));
+        }
+
+        tables.providers.insert(provider.key, provider.clone());
+        Ok(())
+    }
+
+    async fn get_provider(&self, org_id: Uuid, id: &str) -> AuthResult<Provider, String> {
+        let tables = self.tables();
+
+        tables
+            .provider_key(org_id, id)
+            .and_then(|key| tables.providers.get(&key).cloned())
+            .ok_or_else(|| AuthError::new(ProviderError::NotFound, id.to_string()))
+    }
+
+    async fn get_provider_by_key(&self, key: i32) -> AuthResult<Provider, String> {
+        self.tables()
+            .providers
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| AuthError::new(ProviderError::NotFound, key.to_string()))
+    }
+
+    async fn list_providers(&self, org_id: Uuid) -> AuthResult<Vec<Provider>, String> {
+        let mut providers: Vec<Provider> = self
+            .tables()
+            .providers
+            .values()
+            .filter(|provider| provider.org_id == org_id)
+            .cloned()
+            .collect();
+        providers.sort_by_key(|provider| provider.created);
+
+        Ok(providers)
+    }
+
+    async fn update_provider(&self, provider: &Provider) -> AuthResult<(), String> {
+        provider.validate()?;
+
+        if let Some(existing) = self.tables().providers.get_mut(&provider.key) {
+            existing.secret = provider.secret.clone();
+            existing.domain = provider.domain.clone();
+            existing.redirect_uri = provider.redirect_uri.clone();
+            existing.scope = provider.scope.clone();
+        }
+
+        Ok(())
+    }
+
+    async fn delete_provider(&self, org_id: Uuid, id: &str) -> AuthResult<(), String> {
+        let mut tables = self.tables();
+
+        match tables.provider_key(org_id, id) {
+            Some(key) => {
+                tables.remove_provider(key);
+                Ok(())
+            }
+            None => Err(AuthError::new(ProviderError::NotFound, id.to_string())),
+        }
+    }
+
+    async fn create_user_provider(&self, user: &UserProvider) -> AuthResult<(), i32> {
+        let mut tables = self.tables();
+
+        if !tables.providers.contains_key(&user.provider_id) {
+            return Err(missing_reference("provider", user.id));
+        }
+
+        if tables.user_providers.contains_key(&user.id) {
+            return Err(AuthError::new(
+                AuthErrorKind::DatabaseError("user provider id already exists".to_string()),
+                user.id,
+            ));
+        }
+
+        tables.user_providers.insert(user.id, user.clone());
+        Ok(())
+    }
+
+    async fn get_user_provider(&self, org_id: Uuid, id: i32) -> AuthResult<UserProvider, i32> {
+        let tables = self.tables();
+
+        tables
+            .user_providers
+            .get(&id)
+            .filter(|user| tables.user_in_org(user, org_id))
+            .cloned()
+            .ok_or_else(|| AuthError::new(UserError::NotFound, id))
+    }
+
+    async fn expiring_user_providers(
+        &self,
+        before: DateTime<Utc>,
+    ) -> AuthResult<Vec<UserProvider>, i32> {
+        let mut users: Vec<UserProvider> = self
+            .tables()
+            .user_providers
+            .values()
+            .filter(|user| {
+                user.token_refresh.is_some()
+                    && !user.token_refresh_dead
+                    && user.token_expires.is_some_and(|expires| expires < before)
+            })
+            .cloned()
+            .collect();
+        users.sort_by_key(|user| user.token_expires);
+
+        Ok(users)
+    }
+
+    async fn update_user_provider(
+        &self,
+        user: &UserProvider,
+        old_refresh: &str,
+    ) -> AuthResult<bool, i32> {
+        match self.tables().user_providers.get_mut(&user.id) {
+            Some(existing) if existing.token_refresh.as_deref() == Some(old_refresh) => {
+                existing.token_access = user.token_access.clone();
+                existing.token_refresh = user.token_refresh.clone();
+                existing.token_expires = user.token_expires;
+                existing.token_refresh_dead = user.token_refresh_dead;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn kill_user_provider(&self, id: i32, old_refresh: &str) -> AuthResult<bool, i32> {
+        match self.tables().user_providers.get_mut(&id) {
+            Some(existing) if existing.token_refresh.as_deref() == Some(old_refresh) => {
+                existing.token_refresh_dead = true;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete_user_provider(&self, org_id: Uuid, id: i32) -> AuthResult<(), i32> {
+        let mut tables = self.tables();
+        let found = tables
+            .user_providers
+            .get(&id)
+            .is_some_and(|user| tables.user_in_org(user, org_id));
+
+        if found {
+            tables.user_providers.remove(&id);
+            Ok(())
+        } else {
+            Err(AuthError::new(UserError::NotFound, id))
+        }
+    }
+
+    async fn create_oauth_state(
+        &self,
+        state: &OauthState,
+        expired: DateTime<Utc>,
+    ) -> AuthResult<(), String> {
+        let mut tables = self.tables();
+        tables
+            .oauth_states
+            .retain(|_, existing| existing.created >= expired);
+
+        if !tables.providers.contains_key(&state.provider_id) {
+            return Err(missing_reference("provider", None));
+        }
+
+        tables
+            .oauth_states
+            .insert(state.state.clone(), state.clone());
+        Ok(())
+    }
+
+    async fn take_oauth_state(
+        &self,
+        org_id: Uuid,
+        state: &str,
+    ) -> AuthResult<Option<OauthState>, String> {
+        let mut tables = self.tables();
+        let in_org = tables.oauth_states.get(state).is_some_and(|got| {
+            tables
+                .providers
+                .get(&got.provider_id)
+                .is_some_and(|provider| provider.org_id == org_id)
+        });
+
+        Ok(match in_org {
+            true => tables.oauth_states.remove(state),
+            false => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture;
+
+    #[tokio::test]
+    async fn cascading_delete() {
+        let (store, _, org) = fixture().await;
+
+        let provider = Provider::new("id", "secret", "domain", None, None, org.id).unwrap();
+        store.create_provider(&provider).await.unwrap();
+        assert_eq!(
+            store.create_provider(&provider).await,
+            Err(AuthError::new(
+                ProviderError::AlreadyExists,
+                "id".to_string()
+            ))
+        );
+
+        let user = UserProvider::new("access", None, None, provider.key);
+        store.create_user_provider(&user).await.unwrap();
+        assert_eq!(
+            store.get_user_provider(org.id, user.id).await,
+            Ok(user.clone())
+        );
+        assert!(store
+            .get_user_provider(Uuid::new_v4(), user.id)
+            .await
+            .is_err());
+
+        store.delete_org(org.id).await.unwrap();
+        assert!(store.get_provider_by_key(provider.key).await.is_err());
+        assert!(store.tables().user_providers.is_empty());
+    }
+}
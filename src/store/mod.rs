@@ -0,0 +1,162 @@
+//! Storage backends which models are persisted within, see [Store]
+
+#[macro_use]
+pub(crate) mod sql;
+mod memory;
+mod postgres;
+#[cfg(feature = "sqlite")]
+pub(crate) mod sqlite;
+
+pub use memory::MemoryStore;
+pub use postgres::PgStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+use crate::crypto::Hash;
+use crate::models::{
+    ApiKey, OauthState, Org, OrgInvite, OrgMember, PasswordReset, Provider, Role, Totp,
+    UserProvider,
+};
+use crate::AuthResult;
+use async_trait::async_trait;
+use chrono::prelude::*;
+use uuid::Uuid;
+
+/// Storage backend for all models, with [PgStore] being used by default,
+/// `SqliteStore` being available behind the `sqlite` feature and [MemoryStore]
+/// being available for tests and local development
+///
+/// Implementations are expected to behave identically, including cascading
+/// deletes from an [Org] to it's [OrgMember]s, [OrgInvite]s, [PasswordReset]s,
+/// [Totp]s, [ApiKey]s, [Provider]s and their [UserProvider]s and [OauthState]s,
+/// and from an [OrgMember] to it's [Totp]
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Adds a new [Org]
+    async fn create_org(&self, org: &Org) -> AuthResult<(), Uuid>;
+    /// Gets an [Org] by it's [Org::id]
+    async fn get_org(&self, id: Uuid) -> AuthResult<Org, Uuid>;
+    /// Updates the name and [Org::totp_required] of an existing [Org]
+    async fn update_org(&self, org: &Org) -> AuthResult<(), Uuid>;
+    /// Sets the password of an existing [Org], leaving those of it's
+    /// [OrgMember]s untouched
+    async fn set_org_password(&self, id: Uuid, password: &Hash) -> AuthResult<(), Uuid>;
+    /// Deletes an [Org] by it's [Org::id] alongside everything it owns
+    async fn delete_org(&self, id: Uuid) -> AuthResult<(), Uuid>;
+
+    /// Adds a new [ApiKey]
+    async fn create_api_key(&self, api_key: &ApiKey) -> AuthResult<(), i32>;
+    /// Gets an [ApiKey] by it's [ApiKey::key_hash] if it exists
+    async fn get_api_key_by_hash(&self, key_hash: &[u8]) -> AuthResult<Option<ApiKey>, i32>;
+    /// Gets all [ApiKey]s within the given org, oldest first
+    async fn list_api_keys(&self, org_id: Uuid) -> AuthResult<Vec<ApiKey>, i32>;
+    /// Sets [ApiKey::last_used] of an existing [ApiKey]
+    async fn touch_api_key(&self, id: i32, used: DateTime<Utc>) -> AuthResult<(), i32>;
+    /// Deletes an [ApiKey] by it's [ApiKey::id] within the given org
+    async fn delete_api_key(&self, org_id: Uuid, id: i32) -> AuthResult<(), i32>;
+    /// Deletes every [ApiKey] within the given org
+    async fn delete_api_keys(&self, org_id: Uuid) -> AuthResult<(), Uuid>;
+
+    /// Adds a new [OrgMember]
+    async fn create_member(&self, member: &OrgMember) -> AuthResult<(), Uuid>;
+    /// Gets an [OrgMember] by it's [OrgMember::id]
+    async fn get_member(&self, id: Uuid) -> AuthResult<OrgMember, Uuid>;
+    /// Gets all [OrgMember]s within the given org, oldest first
+    async fn list_members(&self, org_id: Uuid) -> AuthResult<Vec<OrgMember>, Uuid>;
+    /// Sets the [Role] of an [OrgMember] within the given org, erroring with
+    /// [MemberError::LastOwner](crate::MemberError::LastOwner) if they're it's
+    /// only owner and `role` isn't [Role::Owner]
+    async fn set_member_role(&self, org_id: Uuid, id: Uuid, role: Role) -> AuthResult<(), Uuid>;
+    /// Sets the password of an existing [OrgMember]
+    async fn set_member_password(&self, id: Uuid, password: &Hash) -> AuthResult<(), Uuid>;
+    /// Deletes an [OrgMember] by it's [OrgMember::id] within the given org,
+    /// erroring with [MemberError::LastOwner](crate::MemberError::LastOwner)
+    /// if they're it's only owner
+    async fn delete_member(&self, org_id: Uuid, id: Uuid) -> AuthResult<(), Uuid>;
+
+    /// Adds a new [OrgInvite], clearing out any which expired before `now`
+    async fn create_invite(&self, invite: &OrgInvite, now: DateTime<Utc>) -> AuthResult<(), i32>;
+    /// Removes and returns the [OrgInvite] for the given token hash if it exists
+    async fn take_invite(&self, token_hash: &[u8]) -> AuthResult<Option<OrgInvite>, i32>;
+
+    /// Adds a new [PasswordReset] unless it's org already has one which hasn't
+    /// expired by `now`, returning if it was added and clearing out any which
+    /// have expired
+    async fn create_password_reset(
+        &self,
+        reset: &PasswordReset,
+        now: DateTime<Utc>,
+    ) -> AuthResult<bool, i32>;
+    /// Removes and returns the [PasswordReset] for the given token hash if it exists
+    async fn take_password_reset(
+        &self,
+        token_hash: &[u8],
+    ) -> AuthResult<Option<PasswordReset>, i32>;
+
+    /// Adds a new [Totp], replacing any other for it's [Totp::login_id]
+    async fn create_totp(&self, totp: &Totp) -> AuthResult<(), Uuid>;
+    /// Gets the [Totp] of an org or member by it's [Totp::login_id] if it exists
+    async fn get_totp(&self, login_id: Uuid) -> AuthResult<Option<Totp>, Uuid>;
+    /// Marks a [Totp] as confirmed, replacing it's recovery codes with the
+    /// given hashes
+    async fn confirm_totp(&self, id: i32, code_hashes: &[Vec<u8>]) -> AuthResult<(), i32>;
+    /// Sets [Totp::last_step] if `step` is after it, returning if it was
+    async fn advance_totp(&self, id: i32, step: i64) -> AuthResult<bool, i32>;
+    /// Removes a recovery code of a [Totp] by it's hash, returning if it existed
+    async fn take_recovery_code(&self, totp_id: i32, code_hash: &[u8]) -> AuthResult<bool, i32>;
+    /// Deletes the [Totp] of an org or member alongside it's recovery codes
+    async fn delete_totp(&self, login_id: Uuid) -> AuthResult<(), Uuid>;
+
+    /// Adds a new [Provider], erroring if it's [Provider::id] is taken within it's org
+    async fn create_provider(&self, provider: &Provider) -> AuthResult<(), String>;
+    /// Gets a [Provider] by it's [Provider::id] within the given org
+    async fn get_provider(&self, org_id: Uuid, id: &str) -> AuthResult<Provider, String>;
+    /// Gets a [Provider] by it's [Provider::key]
+    async fn get_provider_by_key(&self, key: i32) -> AuthResult<Provider, String>;
+    /// Gets all [Provider]s within the given org, oldest first
+    async fn list_providers(&self, org_id: Uuid) -> AuthResult<Vec<Provider>, String>;
+    /// Updates all mutable fields of an existing [Provider]
+    async fn update_provider(&self, provider: &Provider) -> AuthResult<(), String>;
+    /// Deletes a [Provider] by it's [Provider::id] within the given org
+    async fn delete_provider(&self, org_id: Uuid, id: &str) -> AuthResult<(), String>;
+
+    /// Adds a new [UserProvider]
+    async fn create_user_provider(&self, user: &UserProvider) -> AuthResult<(), i32>;
+    /// Gets a [UserProvider] by it's [UserProvider::id], scoped to providers of
+    /// the given org
+    async fn get_user_provider(&self, org_id: Uuid, id: i32) -> AuthResult<UserProvider, i32>;
+    /// Gets all [UserProvider]s with a usable refresh token whose access token
+    /// expires before the given time, soonest first
+    async fn expiring_user_providers(
+        &self,
+        before: DateTime<Utc>,
+    ) -> AuthResult<Vec<UserProvider>, i32>;
+    /// Updates all token fields of an existing [UserProvider] if it's refresh
+    /// token is still `old_refresh`, giving if it was so concurrent refreshes
+    /// can't overwrite each other
+    async fn update_user_provider(
+        &self,
+        user: &UserProvider,
+        old_refresh: &str,
+    ) -> AuthResult<bool, i32>;
+    /// Sets [UserProvider::token_refresh_dead] of an existing [UserProvider] if
+    /// it's refresh token is still `old_refresh`, giving if it was
+    async fn kill_user_provider(&self, id: i32, old_refresh: &str) -> AuthResult<bool, i32>;
+    /// Deletes a [UserProvider] by it's [UserProvider::id], scoped to providers of
+    /// the given org
+    async fn delete_user_provider(&self, org_id: Uuid, id: i32) -> AuthResult<(), i32>;
+
+    /// Adds a new [OauthState], clearing out any created before `expired`
+    async fn create_oauth_state(
+        &self,
+        state: &OauthState,
+        expired: DateTime<Utc>,
+    ) -> AuthResult<(), String>;
+    /// Removes and returns the [OauthState] for the given `state` if it exists,
+    /// scoped to providers of the given org
+    async fn take_oauth_state(
+        &self,
+        org_id: Uuid,
+        state: &str,
+    ) -> AuthResult<Option<OauthState>, String>;
+}
@@ -0,0 +1,20 @@
+//! See [PgStore] for documentation
+
+use crate::Config;
+use sqlx::PgPool;
+
+/// Postgres-backed [Store](super::Store), encrypting secrets at rest using the
+/// master keys within it's [Config]
+pub struct PgStore {
+    pool: PgPool,
+    config: Config,
+}
+
+impl PgStore {
+    /// Creates a new [PgStore] from an already connected pool
+    pub fn new(pool: PgPool, config: Config) -> Self {
+        Self { pool, config }
+    }
+}
+
+sql_store!(PgStore);
@@ -0,0 +1,1330 @@
+//! Shared sql implementation of [Store](super::Store), see [sql_store]
+
+use crate::crypto::{row_aad, DataKey, Hash};
+use crate::models::{
+    ApiKey, IntoModel, Org, OrgInvite, OrgMember, Provider, Scope, Totp, UserProvider,
+};
+use crate::{AuthError, AuthErrorKind, AuthResult, Config};
+use chrono::prelude::*;
+use sqlx::FromRow;
+use std::convert::TryInto;
+use uuid::Uuid;
+
+/// Table whose rows hold [Provider]s, as bound into their [row_aad]
+pub(crate) const PROVIDER_TABLE: &str = "provider";
+
+/// Table whose rows hold [UserProvider]s, as bound into their [row_aad]
+pub(crate) const USER_PROVIDER_TABLE: &str = "user_provider";
+
+/// Table whose rows hold [Totp]s, as bound into their [row_aad]
+pub(crate) const TOTP_TABLE: &str = "totp";
+
+/// Field name which [Provider::secret] is encrypted under
+const SECRET_FIELD: &str = "client_secret";
+
+/// Field name which [UserProvider::token_access] is encrypted under
+const ACCESS_FIELD: &str = "token_access";
+
+/// Field name which [UserProvider::token_refresh] is encrypted under
+const REFRESH_FIELD: &str = "token_refresh";
+
+/// Field name which [Totp::secret] is encrypted under
+const TOTP_FIELD: &str = "totp_secret";
+
+/// Error codes for unique constraint violations, being postgres' code followed
+/// by sqlite's extended codes for `UNIQUE` and `PRIMARY KEY` constraints
+const UNIQUE_VIOLATIONS: [&str; 3] = ["23505", "2067", "1555"];
+
+/// Locks the row of the org `$1` until the transaction ends, so concurrent
+/// changes to it's owners queue up rather than each seeing the other's owner
+pub(super) const LOCK_ORG: &str = "UPDATE org SET name = name WHERE id = $1";
+
+/// Condition only passing for the member `$2` of the org `$1` if they aren't an
+/// owner or another owner remains, so an org always keeps one
+pub(super) const OTHER_OWNER: &str = "(role <> 'owner' OR EXISTS (SELECT 1 FROM org_member \
+    AS other WHERE other.org_id = $1 AND other.role = 'owner' AND other.id <> $2))";
+
+/// Checks if a database error came from violating a unique constraint
+pub(super) fn is_unique_violation(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Database(db_err) => db_err
+            .code()
+            .is_some_and(|code| UNIQUE_VIOLATIONS.contains(&code.as_ref())),
+        _ => false,
+    }
+}
+
+/// Implements [Store](super::Store) for a struct with a sqlx `pool` and a
+/// [Config] named `config`, using sql which is portable across backends.
+/// Secrets are encrypted at rest using the master keys within the config
+macro_rules! sql_store {
+    ($store:ty) => {
+        const _: () = {
+            use super::sql::{
+                is_unique_violation, ApiKeyInternal, OrgInternal, OrgInviteInternal,
+                OrgMemberInternal, ProviderInternal, TotpInternal, UserProviderInternal, LOCK_ORG,
+                OTHER_OWNER,
+            };
+            use super::Store;
+            use crate::crypto::Hash;
+            use crate::models::{
+                ApiKey, IntoModel, OauthState, Org, OrgInvite, OrgMember, PasswordReset, Provider,
+                Role, Totp, UserProvider,
+            };
+            use crate::{
+                ApiKeyError, AuthError, AuthResult, MemberError, OrgError, ProviderError, UserError,
+            };
+            use async_trait::async_trait;
+            use chrono::prelude::*;
+            use uuid::Uuid;
+
+            #[async_trait]
+            impl Store for $store {
+                async fn create_org(&self, org: &Org) -> AuthResult<(), Uuid> {
+                    let internal: OrgInternal = org.clone().into_model()?;
+
+                    sqlx::query(
+                        "INSERT INTO org (id, name, pw_hash, pw_salt, pw_created, totp_required, \
+                        created) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                    )
+                    .bind(internal.id)
+                    .bind(internal.name)
+                    .bind(internal.pw_hash)
+                    .bind(internal.pw_salt)
+                    .bind(internal.pw_created)
+                    .bind(internal.totp_required)
+                    .bind(internal.created)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, org.id))?;
+
+                    Ok(())
+                }
+
+                async fn get_org(&self, id: Uuid) -> AuthResult<Org, Uuid> {
+                    sqlx::query_as::<_, OrgInternal>("SELECT * FROM org WHERE id = $1")
+                        .bind(id)
+                        .fetch_optional(&self.pool)
+                        .await
+                        .map_err(|err| AuthError::new(err, id))?
+                        .ok_or_else(|| AuthError::new(OrgError::NotFound, id))?
+                        .into_model()
+                }
+
+                async fn update_org(&self, org: &Org) -> AuthResult<(), Uuid> {
+                    let internal: OrgInternal = org.clone().into_model()?;
+
+                    sqlx::query("UPDATE org SET name = $2, totp_required = $3 WHERE id = $1")
+                        .bind(internal.id)
+                        .bind(internal.name)
+                        .bind(internal.totp_required)
+                        .execute(&self.pool)
+                        .await
+                        .map_err(|err| AuthError::new(err, org.id))?;
+
+                    Ok(())
+                }
+
+                async fn set_org_password(
+                    &self,
+                    id: Uuid,
+                    password: &Hash,
+                ) -> AuthResult<(), Uuid> {
+                    sqlx::query(
+                        "UPDATE org SET pw_hash = $2, pw_salt = $3, pw_created = $4 WHERE id = $1",
+                    )
+                    .bind(id)
+                    .bind(&password.inner)
+                    .bind(password.salt.to_vec())
+                    .bind(password.created)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, id))?;
+
+                    Ok(())
+                }
+
+                async fn delete_org(&self, id: Uuid) -> AuthResult<(), Uuid> {
+                    sqlx::query("DELETE FROM org WHERE id = $1")
+                        .bind(id)
+                        .execute(&self.pool)
+                        .await
+                        .map_err(|err| AuthError::new(err, id))?;
+
+                    Ok(())
+                }
+
+                async fn create_api_key(&self, api_key: &ApiKey) -> AuthResult<(), i32> {
+                    let internal: ApiKeyInternal = api_key.clone().into_model()?;
+
+                    sqlx::query(
+                        "INSERT INTO api_key (id, name, key_hash, scopes, org_id, last_used, \
+                        expires, created) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                    )
+                    .bind(internal.id)
+                    .bind(internal.name)
+                    .bind(internal.key_hash)
+                    .bind(internal.scopes)
+                    .bind(internal.org_id)
+                    .bind(internal.last_used)
+                    .bind(internal.expires)
+                    .bind(internal.created)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, api_key.id))?;
+
+                    Ok(())
+                }
+
+                async fn get_api_key_by_hash(
+                    &self,
+                    key_hash: &[u8],
+                ) -> AuthResult<Option<ApiKey>, i32> {
+                    sqlx::query_as::<_, ApiKeyInternal>(
+                        "SELECT * FROM api_key WHERE key_hash = $1",
+                    )
+                    .bind(key_hash)
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, None))?
+                    .map(IntoModel::into_model)
+                    .transpose()
+                }
+
+                async fn list_api_keys(&self, org_id: Uuid) -> AuthResult<Vec<ApiKey>, i32> {
+                    sqlx::query_as::<_, ApiKeyInternal>(
+                        "SELECT * FROM api_key WHERE org_id = $1 ORDER BY created",
+                    )
+                    .bind(org_id)
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, None))?
+                    .into_iter()
+                    .map(IntoModel::into_model)
+                    .collect()
+                }
+
+                async fn touch_api_key(&self, id: i32, used: DateTime<Utc>) -> AuthResult<(), i32> {
+                    sqlx::query("UPDATE api_key SET last_used = $2 WHERE id = $1")
+                        .bind(id)
+                        .bind(used)
+                        .execute(&self.pool)
+                        .await
+                        .map_err(|err| AuthError::new(err, id))?;
+
+                    Ok(())
+                }
+
+                async fn delete_api_key(&self, org_id: Uuid, id: i32) -> AuthResult<(), i32> {
+                    let result = sqlx::query("DELETE FROM api_key WHERE org_id = $1 AND id = $2")
+                        .bind(org_id)
+                        .bind(id)
+                        .execute(&self.pool)
+                        .await
+                        .map_err(|err| AuthError::new(err, id))?;
+
+                    if result.rows_affected() == 0 {
+                        Err(AuthError::new(ApiKeyError::NotFound, id))
+                    } else {
+                        Ok(())
+                    }
+                }
+
+                async fn delete_api_keys(&self, org_id: Uuid) -> AuthResult<(), Uuid> {
+                    sqlx::query("DELETE FROM api_key WHERE org_id = $1")
+                        .bind(org_id)
+                        .execute(&self.pool)
+                        .await
+                        .map_err(|err| AuthError::new(err, org_id))?;
+
+                    Ok(())
+                }
+
+                async fn create_member(&self, member: &OrgMember) -> AuthResult<(), Uuid> {
+                    let internal: OrgMemberInternal = member.clone().into_model()?;
+
+                    sqlx::query(
+                        "INSERT INTO org_member (id, name, pw_hash, pw_salt, pw_created, role, \
+                        org_id, created) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                    )
+                    .bind(internal.id)
+                    .bind(internal.name)
+                    .bind(internal.pw_hash)
+                    .bind(internal.pw_salt)
+                    .bind(internal.pw_created)
+                    .bind(internal.role)
+                    .bind(internal.org_id)
+                    .bind(internal.created)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, member.id))?;
+
+                    Ok(())
+                }
+
+                async fn get_member(&self, id: Uuid) -> AuthResult<OrgMember, Uuid> {
+                    sqlx::query_as::<_, OrgMemberInternal>(
+                        "SELECT * FROM org_member WHERE id = $1",
+                    )
+                    .bind(id)
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, id))?
+                    .ok_or_else(|| AuthError::new(MemberError::NotFound, id))?
+                    .into_model()
+                }
+
+                async fn list_members(&self, org_id: Uuid) -> AuthResult<Vec<OrgMember>, Uuid> {
+                    sqlx::query_as::<_, OrgMemberInternal>(
+                        "SELECT * FROM org_member WHERE org_id = $1 ORDER BY created",
+                    )
+                    .bind(org_id)
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, None))?
+                    .into_iter()
+                    .map(IntoModel::into_model)
+                    .collect()
+                }
+
+                async fn set_member_role(
+                    &self,
+                    org_id: Uuid,
+                    id: Uuid,
+                    role: Role,
+                ) -> AuthResult<(), Uuid> {
+                    let query = "UPDATE org_member SET role = $3 WHERE org_id = $1 AND id = $2";
+                    let query = match role {
+                        Role::Owner => query.to_string(),
+                        _ => format!("{} AND {}", query, OTHER_OWNER),
+                    };
+
+                    let mut tx = self.pool.begin().await.map_err(|err| AuthError::new(err, id))?;
+                    sqlx::query(LOCK_ORG)
+                        .bind(org_id)
+                        .execute(&mut tx)
+                        .await
+                        .map_err(|err| AuthError::new(err, id))?;
+                    let result = sqlx::query(&query)
+                        .bind(org_id)
+                        .bind(id)
+                        .bind(role.as_str())
+                        .execute(&mut tx)
+                        .await
+                        .map_err(|err| AuthError::new(err, id))?;
+
+                    if result.rows_affected() == 0 {
+                        // either they're not within the org or are it's last owner
+                        let (found,): (i64,) = sqlx::query_as(
+                            "SELECT COUNT(*) FROM org_member WHERE org_id = $1 AND id = $2",
+                        )
+                        .bind(org_id)
+                        .bind(id)
+                        .fetch_one(&mut tx)
+                        .await
+                        .map_err(|err| AuthError::new(err, id))?;
+
+                        return Err(match found {
+                            0 => AuthError::new(MemberError::NotFound, id),
+                            _ => AuthError::new(MemberError::LastOwner, id),
+                        });
+                    }
+
+                    tx.commit().await.map_err(|err| AuthError::new(err, id))
+                }
+
+                async fn set_member_password(
+                    &self,
+                    id: Uuid,
+                    password: &Hash,
+                ) -> AuthResult<(), Uuid> {
+                    sqlx::query(
+                        "UPDATE org_member SET pw_hash = $2, pw_salt = $3, pw_created = $4 \
+                        WHERE id = $1",
+                    )
+                    .bind(id)
+                    .bind(&password.inner)
+                    .bind(password.salt.to_vec())
+                    .bind(password.created)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, id))?;
+
+                    Ok(())
+                }
+
+                async fn delete_member(&self, org_id: Uuid, id: Uuid) -> AuthResult<(), Uuid> {
+                    let mut tx = self.pool.begin().await.map_err(|err| AuthError::new(err, id))?;
+                    sqlx::query(LOCK_ORG)
+                        .bind(org_id)
+                        .execute(&mut tx)
+                        .await
+                        .map_err(|err| AuthError::new(err, id))?;
+                    let result = sqlx::query(&format!(
+                        "DELETE FROM org_member WHERE org_id = $1 AND id = $2 AND {}",
+                        OTHER_OWNER
+                    ))
+                    .bind(org_id)
+                    .bind(id)
+                    .execute(&mut tx)
+                    .await
+                    .map_err(|err| AuthError::new(err, id))?;
+
+                    if result.rows_affected() == 0 {
+                        // either they're not within the org or are it's last owner
+                        let (found,): (i64,) = sqlx::query_as(
+                            "SELECT COUNT(*) FROM org_member WHERE org_id = $1 AND id = $2",
+                        )
+                        .bind(org_id)
+                        .bind(id)
+                        .fetch_one(&mut tx)
+                        .await
+                        .map_err(|err| AuthError::new(err, id))?;
+
+                        return Err(match found {
+                            0 => AuthError::new(MemberError::NotFound, id),
+                            _ => AuthError::new(MemberError::LastOwner, id),
+                        });
+                    }
+
+                    // totp rows can belong to an org or a member, so can't cascade by key
+                    sqlx::query("DELETE FROM totp WHERE login_id = $1")
+                        .bind(id)
+                        .execute(&mut tx)
+                        .await
+                        .map_err(|err| AuthError::new(err, id))?;
+
+                    tx.commit().await.map_err(|err| AuthError::new(err, id))
+                }
+
+                async fn create_invite(
+                    &self,
+                    invite: &OrgInvite,
+                    now: DateTime<Utc>,
+                ) -> AuthResult<(), i32> {
+                    sqlx::query("DELETE FROM org_invite WHERE expires <= $1")
+                        .bind(now)
+                        .execute(&self.pool)
+                        .await
+                        .map_err(|err| AuthError::new(err, invite.id))?;
+
+                    let internal: OrgInviteInternal = invite.clone().into_model()?;
+
+                    sqlx::query(
+                        "INSERT INTO org_invite (id, token_hash, role, org_id, expires, created) \
+                        VALUES ($1, $2, $3, $4, $5, $6)",
+                    )
+                    .bind(internal.id)
+                    .bind(internal.token_hash)
+                    .bind(internal.role)
+                    .bind(internal.org_id)
+                    .bind(internal.expires)
+                    .bind(internal.created)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, invite.id))?;
+
+                    Ok(())
+                }
+
+                async fn take_invite(
+                    &self,
+                    token_hash: &[u8],
+                ) -> AuthResult<Option<OrgInvite>, i32> {
+                    sqlx::query_as::<_, OrgInviteInternal>(
+                        "DELETE FROM org_invite WHERE token_hash = $1 RETURNING *",
+                    )
+                    .bind(token_hash)
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, None))?
+                    .map(IntoModel::into_model)
+                    .transpose()
+                }
+
+                async fn create_password_reset(
+                    &self,
+                    reset: &PasswordReset,
+                    now: DateTime<Utc>,
+                ) -> AuthResult<bool, i32> {
+                    sqlx::query("DELETE FROM password_reset WHERE expires <= $1")
+                        .bind(now)
+                        .execute(&self.pool)
+                        .await
+                        .map_err(|err| AuthError::new(err, reset.id))?;
+
+                    let result = sqlx::query(
+                        "INSERT INTO password_reset (id, token_hash, org_id, expires, created) \
+                        SELECT $1, $2, $3, $4, $5 \
+                        WHERE NOT EXISTS (SELECT 1 FROM password_reset WHERE org_id = $3)",
+                    )
+                    .bind(reset.id)
+                    .bind(&reset.token_hash)
+                    .bind(reset.org_id)
+                    .bind(reset.expires)
+                    .bind(reset.created)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, reset.id))?;
+
+                    Ok(result.rows_affected() > 0)
+                }
+
+                async fn take_password_reset(
+                    &self,
+                    token_hash: &[u8],
+                ) -> AuthResult<Option<PasswordReset>, i32> {
+                    sqlx::query_as::<_, PasswordReset>(
+                        "DELETE FROM password_reset WHERE token_hash = $1 RETURNING *",
+                    )
+                    .bind(token_hash)
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, None))
+                }
+
+                async fn create_totp(&self, totp: &Totp) -> AuthResult<(), Uuid> {
+                    let internal: TotpInternal = (totp.clone(), &self.config).into_model()?;
+                    let err = |err| AuthError::new(err, totp.login_id);
+
+                    let mut tx = self.pool.begin().await.map_err(err)?;
+                    sqlx::query("DELETE FROM totp WHERE login_id = $1")
+                        .bind(totp.login_id)
+                        .execute(&mut tx)
+                        .await
+                        .map_err(err)?;
+
+                    sqlx::query(
+                        "INSERT INTO totp (id, login_id, org_id, secret, key_id, data_key, \
+                        confirmed, last_step, created) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                    )
+                    .bind(internal.id)
+                    .bind(internal.login_id)
+                    .bind(internal.org_id)
+                    .bind(internal.secret)
+                    .bind(internal.key_id)
+                    .bind(internal.data_key)
+                    .bind(internal.confirmed)
+                    .bind(internal.last_step)
+                    .bind(internal.created)
+                    .execute(&mut tx)
+                    .await
+                    .map_err(err)?;
+
+                    tx.commit().await.map_err(err)
+                }
+
+                async fn get_totp(&self, login_id: Uuid) -> AuthResult<Option<Totp>, Uuid> {
+                    sqlx::query_as::<_, TotpInternal>("SELECT * FROM totp WHERE login_id = $1")
+                        .bind(login_id)
+                        .fetch_optional(&self.pool)
+                        .await
+                        .map_err(|err| AuthError::new(err, login_id))?
+                        .map(|internal| (internal, &self.config).into_model())
+                        .transpose()
+                }
+
+                async fn confirm_totp(
+                    &self,
+                    id: i32,
+                    code_hashes: &[Vec<u8>],
+                ) -> AuthResult<(), i32> {
+                    let err = |err| AuthError::new(err, id);
+
+                    let mut tx = self.pool.begin().await.map_err(err)?;
+                    sqlx::query("UPDATE totp SET confirmed = $2 WHERE id = $1")
+                        .bind(id)
+                        .bind(true)
+                        .execute(&mut tx)
+                        .await
+                        .map_err(err)?;
+                    sqlx::query("DELETE FROM totp_recovery WHERE totp_id = $1")
+                        .bind(id)
+                        .execute(&mut tx)
+                        .await
+                        .map_err(err)?;
+
+                    for code_hash in code_hashes {
+                        sqlx::query(
+                            "INSERT INTO totp_recovery (code_hash, totp_id) VALUES ($1, $2)",
+                        )
+                        .bind(code_hash)
+                        .bind(id)
+                        .execute(&mut tx)
+                        .await
+                        .map_err(err)?;
+                    }
+
+                    tx.commit().await.map_err(err)
+                }
+
+                async fn advance_totp(&self, id: i32, step: i64) -> AuthResult<bool, i32> {
+                    let result = sqlx::query(
+                        "UPDATE totp SET last_step = $2 WHERE id = $1 AND last_step < $2",
+                    )
+                    .bind(id)
+                    .bind(step)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, id))?;
+
+                    Ok(result.rows_affected() > 0)
+                }
+
+                async fn take_recovery_code(
+                    &self,
+                    totp_id: i32,
+                    code_hash: &[u8],
+                ) -> AuthResult<bool, i32> {
+                    let result = sqlx::query(
+                        "DELETE FROM totp_recovery WHERE totp_id = $1 AND code_hash = $2",
+                    )
+                    .bind(totp_id)
+                    .bind(code_hash)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, totp_id))?;
+
+                    Ok(result.rows_affected() > 0)
+                }
+
+                async fn delete_totp(&self, login_id: Uuid) -> AuthResult<(), Uuid> {
+                    sqlx::query("DELETE FROM totp WHERE login_id = $1")
+                        .bind(login_id)
+                        .execute(&self.pool)
+                        .await
+                        .map_err(|err| AuthError::new(err, login_id))?;
+
+                    Ok(())
+                }
+
+                async fn create_provider(&self, provider: &Provider) -> AuthResult<(), String> {
+                    let internal: ProviderInternal = (provider.clone(), &self.config).into_model()?;
+
+                    sqlx::query(
+                        "INSERT INTO provider (id, client_id, client_secret, key_id, data_key, \
+                        domain, redirect_uri, scope, org_id, created) \
+                        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+                    )
+                    .bind(internal.id)
+                    .bind(internal.client_id)
+                    .bind(internal.client_secret)
+                    .bind(internal.key_id)
+                    .bind(internal.data_key)
+                    .bind(internal.domain)
+                    .bind(internal.redirect_uri)
+                    .bind(internal.scope)
+                    .bind(internal.org_id)
+                    .bind(internal.created)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| match err {
+                        err if is_unique_violation(&err) => {
+                            AuthError::new(ProviderError::AlreadyExists, provider.id.clone())
+                        }
+                        err => AuthError::new(err, provider.id.clone()),
+                    })?;
+
+                    Ok(())
+                }
+
+                async fn get_provider(
+                    &self,
+                    org_id: Uuid,
+                    id: &str,
+                ) -> AuthResult<Provider, String> {
+                    sqlx::query_as::<_, ProviderInternal>(
+                        "SELECT * FROM provider WHERE org_id = $1 AND client_id = $2",
+                    )
+                    .bind(org_id)
+                    .bind(id)
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, id.to_string()))?
+                    .ok_or_else(|| AuthError::new(ProviderError::NotFound, id.to_string()))
+                    .and_then(|internal| (internal, &self.config).into_model())
+                }
+
+                async fn get_provider_by_key(&self, key: i32) -> AuthResult<Provider, String> {
+                    sqlx::query_as::<_, ProviderInternal>("SELECT * FROM provider WHERE id = $1")
+                        .bind(key)
+                        .fetch_optional(&self.pool)
+                        .await
+                        .map_err(|err| AuthError::new(err, key.to_string()))?
+                        .ok_or_else(|| AuthError::new(ProviderError::NotFound, key.to_string()))
+                        .and_then(|internal| (internal, &self.config).into_model())
+                }
+
+                async fn list_providers(&self, org_id: Uuid) -> AuthResult<Vec<Provider>, String> {
+                    sqlx::query_as::<_, ProviderInternal>(
+                        "SELECT * FROM provider WHERE org_id = $1 ORDER BY created",
+                    )
+                    .bind(org_id)
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, None))?
+                    .into_iter()
+                    .map(|internal| (internal, &self.config).into_model())
+                    .collect()
+                }
+
+                async fn update_provider(&self, provider: &Provider) -> AuthResult<(), String> {
+                    let internal: ProviderInternal = (provider.clone(), &self.config).into_model()?;
+
+                    sqlx::query(
+                        "UPDATE provider SET client_secret = $2, key_id = $3, data_key = $4, \
+                        domain = $5, redirect_uri = $6, scope = $7 WHERE id = $1",
+                    )
+                    .bind(internal.id)
+                    .bind(internal.client_secret)
+                    .bind(internal.key_id)
+                    .bind(internal.data_key)
+                    .bind(internal.domain)
+                    .bind(internal.redirect_uri)
+                    .bind(internal.scope)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, provider.id.clone()))?;
+
+                    Ok(())
+                }
+
+                async fn delete_provider(&self, org_id: Uuid, id: &str) -> AuthResult<(), String> {
+                    let result = sqlx::query(
+                        "DELETE FROM provider WHERE org_id = $1 AND client_id = $2",
+                    )
+                    .bind(org_id)
+                    .bind(id)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, id.to_string()))?;
+
+                    if result.rows_affected() == 0 {
+                        Err(AuthError::new(ProviderError::NotFound, id.to_string()))
+                    } else {
+                        Ok(())
+                    }
+                }
+
+                async fn create_user_provider(&self, user: &UserProvider) -> AuthResult<(), i32> {
+                    let internal: UserProviderInternal = (user.clone(), &self.config).into_model()?;
+
+                    sqlx::query(
+                        "INSERT INTO user_provider (id, token_access, token_refresh, key_id, \
+                        data_key, token_expires, token_refresh_dead, provider_id, created) \
+                        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                    )
+                    .bind(internal.id)
+                    .bind(internal.token_access)
+                    .bind(internal.token_refresh)
+                    .bind(internal.key_id)
+                    .bind(internal.data_key)
+                    .bind(internal.token_expires)
+                    .bind(internal.token_refresh_dead)
+                    .bind(internal.provider_id)
+                    .bind(internal.created)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, user.id))?;
+
+                    Ok(())
+                }
+
+                async fn get_user_provider(
+                    &self,
+                    org_id: Uuid,
+                    id: i32,
+                ) -> AuthResult<UserProvider, i32> {
+                    sqlx::query_as::<_, UserProviderInternal>(
+                        "SELECT user_provider.* FROM user_provider \
+                        JOIN provider ON provider.id = user_provider.provider_id \
+                        WHERE user_provider.id = $1 AND provider.org_id = $2",
+                    )
+                    .bind(id)
+                    .bind(org_id)
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, id))?
+                    .ok_or_else(|| AuthError::new(UserError::NotFound, id))
+                    .and_then(|internal| (internal, &self.config).into_model())
+                }
+
+                async fn expiring_user_providers(
+                    &self,
+                    before: DateTime<Utc>,
+                ) -> AuthResult<Vec<UserProvider>, i32> {
+                    sqlx::query_as::<_, UserProviderInternal>(
+                        "SELECT * FROM user_provider WHERE token_refresh IS NOT NULL \
+                        AND NOT token_refresh_dead AND token_expires < $1 ORDER BY token_expires",
+                    )
+                    .bind(before)
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, None))?
+                    .into_iter()
+                    .map(|internal| (internal, &self.config).into_model())
+                    .collect()
+                }
+
+                async fn update_user_provider(
+                    &self,
+                    user: &UserProvider,
+                    old_refresh: &str,
+                ) -> AuthResult<bool, i32> {
+                    let stored = match self.stored_refresh(user.id, old_refresh).await? {
+                        Some(stored) => stored,
+                        None => return Ok(false),
+                    };
+                    let internal: UserProviderInternal = (user.clone(), &self.config).into_model()?;
+
+                    let result = sqlx::query(
+                        "UPDATE user_provider SET token_access = $2, token_refresh = $3, \
+                        key_id = $4, data_key = $5, token_expires = $6, token_refresh_dead = $7 \
+                        WHERE id = $1 AND token_refresh = $8",
+                    )
+                    .bind(internal.id)
+                    .bind(internal.token_access)
+                    .bind(internal.token_refresh)
+                    .bind(internal.key_id)
+                    .bind(internal.data_key)
+                    .bind(internal.token_expires)
+                    .bind(internal.token_refresh_dead)
+                    .bind(stored)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, user.id))?;
+
+                    Ok(result.rows_affected() > 0)
+                }
+
+                async fn kill_user_provider(
+                    &self,
+                    id: i32,
+                    old_refresh: &str,
+                ) -> AuthResult<bool, i32> {
+                    let stored = match self.stored_refresh(id, old_refresh).await? {
+                        Some(stored) => stored,
+                        None => return Ok(false),
+                    };
+
+                    let result = sqlx::query(
+                        "UPDATE user_provider SET token_refresh_dead = true \
+                        WHERE id = $1 AND token_refresh = $2",
+                    )
+                    .bind(id)
+                    .bind(stored)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, id))?;
+
+                    Ok(result.rows_affected() > 0)
+                }
+
+                async fn delete_user_provider(&self, org_id: Uuid, id: i32) -> AuthResult<(), i32> {
+                    let result = sqlx::query(
+                        "DELETE FROM user_provider WHERE id = $1 \
+                        AND provider_id IN (SELECT id FROM provider WHERE org_id = $2)",
+                    )
+                    .bind(id)
+                    .bind(org_id)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, id))?;
+
+                    if result.rows_affected() == 0 {
+                        Err(AuthError::new(UserError::NotFound, id))
+                    } else {
+                        Ok(())
+                    }
+                }
+
+                async fn create_oauth_state(
+                    &self,
+                    state: &OauthState,
+                    expired: DateTime<Utc>,
+                ) -> AuthResult<(), String> {
+                    sqlx::query("DELETE FROM oauth_state WHERE created < $1")
+                        .bind(expired)
+                        .execute(&self.pool)
+                        .await
+                        .map_err(|err| AuthError::new(err, None))?;
+
+                    sqlx::query(
+                        "INSERT INTO oauth_state (state, code_verifier, binding_hash, provider_id, \
+                        created) VALUES ($1, $2, $3, $4, $5)",
+                    )
+                    .bind(&state.state)
+                    .bind(&state.code_verifier)
+                    .bind(&state.binding_hash)
+                    .bind(state.provider_id)
+                    .bind(state.created)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, None))?;
+
+                    Ok(())
+                }
+
+                async fn take_oauth_state(
+                    &self,
+                    org_id: Uuid,
+                    state: &str,
+                ) -> AuthResult<Option<OauthState>, String> {
+                    sqlx::query_as::<_, OauthState>(
+                        "DELETE FROM oauth_state WHERE state = $1 \
+                        AND provider_id IN (SELECT id FROM provider WHERE org_id = $2) RETURNING *",
+                    )
+                    .bind(state)
+                    .bind(org_id)
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, None))
+                }
+            }
+
+            impl $store {
+                /// Gets the stored ciphertext of a [UserProvider]'s refresh token if
+                /// it still decrypts to `old_refresh`, to compare against whilst
+                /// updating as each encryption of it differs
+                async fn stored_refresh(
+                    &self,
+                    id: i32,
+                    old_refresh: &str,
+                ) -> AuthResult<Option<Vec<u8>>, i32> {
+                    let internal = sqlx::query_as::<_, UserProviderInternal>(
+                        "SELECT * FROM user_provider WHERE id = $1",
+                    )
+                    .bind(id)
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|err| AuthError::new(err, id))?;
+
+                    let internal = match internal {
+                        Some(internal) => internal,
+                        None => return Ok(None),
+                    };
+                    let stored = internal.token_refresh.clone();
+                    let user: UserProvider = (internal, &self.config).into_model()?;
+
+                    if user.token_refresh.as_deref() == Some(old_refresh) {
+                        Ok(stored)
+                    } else {
+                        Ok(None)
+                    }
+                }
+            }
+        };
+    };
+}
+
+/// Internal sqlx mapping for the [ApiKey] model, with scopes space-separated
+#[derive(FromRow)]
+pub(super) struct ApiKeyInternal {
+    pub(super) id: i32,
+    pub(super) name: String,
+    pub(super) key_hash: Vec<u8>,
+    pub(super) scopes: String,
+    pub(super) org_id: Uuid,
+    pub(super) last_used: Option<DateTime<Utc>>,
+    pub(super) expires: Option<DateTime<Utc>>,
+    pub(super) created: DateTime<Utc>,
+}
+
+impl IntoModel<ApiKey, i32> for ApiKeyInternal {
+    fn into_model(self) -> AuthResult<ApiKey, i32> {
+        let scopes = Scope::split(&self.scopes).map_err(|err| {
+            AuthError::new(
+                AuthErrorKind::DatabaseError(format!("{} for api key", err)),
+                self.id,
+            )
+        })?;
+
+        Ok(ApiKey {
+            id: self.id,
+            name: self.name,
+            key_hash: self.key_hash,
+            scopes,
+            org_id: self.org_id,
+            last_used: self.last_used,
+            expires: self.expires,
+            created: self.created,
+        })
+    }
+}
+
+impl IntoModel<ApiKeyInternal, i32> for ApiKey {
+    fn into_model(self) -> AuthResult<ApiKeyInternal, i32> {
+        Ok(ApiKeyInternal {
+            id: self.id,
+            name: self.name,
+            key_hash: self.key_hash,
+            scopes: Scope::join(&self.scopes),
+            org_id: self.org_id,
+            last_used: self.last_used,
+            expires: self.expires,
+            created: self.created,
+        })
+    }
+}
+
+/// Internal sqlx mapping for the [Org] model
+#[derive(FromRow)]
+pub(super) struct OrgInternal {
+    pub(super) id: Uuid,
+    pub(super) name: String,
+    pub(super) pw_hash: Vec<u8>,
+    pub(super) pw_salt: Vec<u8>,
+    pub(super) pw_created: DateTime<Utc>,
+    pub(super) totp_required: bool,
+    pub(super) created: DateTime<Utc>,
+}
+
+/// Rebuilds a password [Hash] from it's stored columns, erroring with the
+/// `table` it came from if the salt is corrupt
+fn hash_from_columns(
+    pw_hash: Vec<u8>,
+    pw_salt: Vec<u8>,
+    pw_created: DateTime<Utc>,
+    table: &str,
+    id: Uuid,
+) -> AuthResult<Hash, Uuid> {
+    Ok(Hash {
+        inner: pw_hash,
+        salt: pw_salt.try_into().map_err(|_| {
+            AuthError::new(
+                AuthErrorKind::DatabaseError(format!("salt length invalid for {}", table)),
+                id,
+            )
+        })?,
+        created: pw_created,
+    })
+}
+
+impl IntoModel<Org, Uuid> for OrgInternal {
+    fn into_model(self) -> AuthResult<Org, Uuid> {
+        Ok(Org {
+            id: self.id,
+            name: self.name,
+            password: hash_from_columns(
+                self.pw_hash,
+                self.pw_salt,
+                self.pw_created,
+                "org",
+                self.id,
+            )?,
+            totp_required: self.totp_required,
+            created: self.created,
+        })
+    }
+}
+
+impl IntoModel<OrgInternal, Uuid> for Org {
+    fn into_model(self) -> AuthResult<OrgInternal, Uuid> {
+        self.validate()?;
+
+        Ok(OrgInternal {
+            id: self.id,
+            name: self.name,
+            pw_hash: self.password.inner,
+            pw_salt: self.password.salt.to_vec(),
+            pw_created: self.password.created,
+            totp_required: self.totp_required,
+            created: self.created,
+        })
+    }
+}
+
+/// Internal sqlx mapping for the [OrgMember] model
+#[derive(FromRow)]
+pub(super) struct OrgMemberInternal {
+    pub(super) id: Uuid,
+    pub(super) name: String,
+    pub(super) pw_hash: Vec<u8>,
+    pub(super) pw_salt: Vec<u8>,
+    pub(super) pw_created: DateTime<Utc>,
+    pub(super) role: String,
+    pub(super) org_id: Uuid,
+    pub(super) created: DateTime<Utc>,
+}
+
+impl IntoModel<OrgMember, Uuid> for OrgMemberInternal {
+    fn into_model(self) -> AuthResult<OrgMember, Uuid> {
+        let role = self.role.parse().map_err(|err| {
+            AuthError::new(
+                AuthErrorKind::DatabaseError(format!("{} for member", err)),
+                self.id,
+            )
+        })?;
+
+        Ok(OrgMember {
+            id: self.id,
+            name: self.name,
+            password: hash_from_columns(
+                self.pw_hash,
+                self.pw_salt,
+                self.pw_created,
+                "member",
+                self.id,
+            )?,
+            role,
+            org_id: self.org_id,
+            created: self.created,
+        })
+    }
+}
+
+impl IntoModel<OrgMemberInternal, Uuid> for OrgMember {
+    fn into_model(self) -> AuthResult<OrgMemberInternal, Uuid> {
+        self.validate()?;
+
+        Ok(OrgMemberInternal {
+            id: self.id,
+            name: self.name,
+            pw_hash: self.password.inner,
+            pw_salt: self.password.salt.to_vec(),
+            pw_created: self.password.created,
+            role: self.role.to_string(),
+            org_id: self.org_id,
+            created: self.created,
+        })
+    }
+}
+
+/// Internal sqlx mapping for the [OrgInvite] model
+#[derive(FromRow)]
+pub(super) struct OrgInviteInternal {
+    pub(super) id: i32,
+    pub(super) token_hash: Vec<u8>,
+    pub(super) role: String,
+    pub(super) org_id: Uuid,
+    pub(super) expires: DateTime<Utc>,
+    pub(super) created: DateTime<Utc>,
+}
+
+impl IntoModel<OrgInvite, i32> for OrgInviteInternal {
+    fn into_model(self) -> AuthResult<OrgInvite, i32> {
+        let role = self.role.parse().map_err(|err| {
+            AuthError::new(
+                AuthErrorKind::DatabaseError(format!("{} for invite", err)),
+                self.id,
+            )
+        })?;
+
+        Ok(OrgInvite {
+            id: self.id,
+            token_hash: self.token_hash,
+            role,
+            org_id: self.org_id,
+            expires: self.expires,
+            created: self.created,
+        })
+    }
+}
+
+impl IntoModel<OrgInviteInternal, i32> for OrgInvite {
+    fn into_model(self) -> AuthResult<OrgInviteInternal, i32> {
+        Ok(OrgInviteInternal {
+            id: self.id,
+            token_hash: self.token_hash,
+            role: self.role.to_string(),
+            org_id: self.org_id,
+            expires: self.expires,
+            created: self.created,
+        })
+    }
+}
+
+/// Internal sqlx mapping for the [Totp] model, with [Totp::secret] encrypted
+/// by a [DataKey] which is wrapped by the [MasterKey](crate::crypto::MasterKey)
+/// of id `key_id`
+#[derive(FromRow)]
+pub(super) struct TotpInternal {
+    pub(super) id: i32,
+    pub(super) login_id: Uuid,
+    pub(super) org_id: Uuid,
+    pub(super) secret: Vec<u8>,
+    pub(super) key_id: i32,
+    pub(super) data_key: Vec<u8>,
+    pub(super) confirmed: bool,
+    pub(super) last_step: i64,
+    pub(super) created: DateTime<Utc>,
+}
+
+impl IntoModel<Totp, Uuid> for (TotpInternal, &Config) {
+    fn into_model(self) -> AuthResult<Totp, Uuid> {
+        let (internal, config) = self;
+        let err = |_| AuthError::new(AuthErrorKind::EncryptionError, internal.login_id);
+        let row = row_aad(TOTP_TABLE, internal.org_id, internal.id);
+        let data_key = DataKey::unwrap_by_id(config, internal.key_id, &internal.data_key, &row)
+            .map_err(err)?;
+
+        Ok(Totp {
+            id: internal.id,
+            login_id: internal.login_id,
+            org_id: internal.org_id,
+            secret: data_key
+                .decrypt(&internal.secret, TOTP_FIELD)
+                .map_err(err)?,
+            confirmed: internal.confirmed,
+            last_step: internal.last_step,
+            created: internal.created,
+        })
+    }
+}
+
+impl IntoModel<TotpInternal, Uuid> for (Totp, &Config) {
+    fn into_model(self) -> AuthResult<TotpInternal, Uuid> {
+        let (totp, config) = self;
+        let err = |_| AuthError::new(AuthErrorKind::EncryptionError, totp.login_id);
+        let row = row_aad(TOTP_TABLE, totp.org_id, totp.id);
+        let (data_key, wrapped) =
+            DataKey::generate_wrapped(&config.master_key, &row).map_err(err)?;
+
+        Ok(TotpInternal {
+            id: totp.id,
+            login_id: totp.login_id,
+            org_id: totp.org_id,
+            secret: data_key.encrypt(&totp.secret, TOTP_FIELD).map_err(err)?,
+            key_id: config.master_key.id,
+            data_key: wrapped,
+            confirmed: totp.confirmed,
+            last_step: totp.last_step,
+            created: totp.created,
+        })
+    }
+}
+
+/// Internal sqlx mapping for the [Provider] model, with [Provider::secret]
+/// encrypted by a [DataKey] which is wrapped by the [MasterKey](crate::crypto::MasterKey)
+/// of id `key_id`
+#[derive(FromRow)]
+pub(super) struct ProviderInternal {
+    pub(super) id: i32,
+    pub(super) client_id: String,
+    pub(super) client_secret: Vec<u8>,
+    pub(super) key_id: i32,
+    pub(super) data_key: Vec<u8>,
+    pub(super) domain: String,
+    pub(super) redirect_uri: Option<String>,
+    pub(super) scope: Option<String>,
+    pub(super) org_id: Uuid,
+    pub(super) created: DateTime<Utc>,
+}
+
+impl IntoModel<Provider, String> for (ProviderInternal, &Config) {
+    fn into_model(self) -> AuthResult<Provider, String> {
+        let (internal, config) = self;
+        let err = |_| AuthError::new(AuthErrorKind::EncryptionError, internal.client_id.clone());
+        let row = row_aad(PROVIDER_TABLE, internal.org_id, internal.id);
+        let data_key = DataKey::unwrap_by_id(config, internal.key_id, &internal.data_key, &row)
+            .map_err(err)?;
+
+        Ok(Provider {
+            key: internal.id,
+            secret: data_key
+                .decrypt(&internal.client_secret, SECRET_FIELD)
+                .map_err(err)?,
+            id: internal.client_id,
+            domain: internal.domain,
+            redirect_uri: internal.redirect_uri,
+            scope: internal.scope,
+            org_id: internal.org_id,
+            created: internal.created,
+        })
+    }
+}
+
+impl IntoModel<ProviderInternal, String> for (Provider, &Config) {
+    fn into_model(self) -> AuthResult<ProviderInternal, String> {
+        let (provider, config) = self;
+        provider.validate()?;
+
+        let err = |_| AuthError::new(AuthErrorKind::EncryptionError, provider.id.clone());
+        let row = row_aad(PROVIDER_TABLE, provider.org_id, provider.key);
+        let (data_key, wrapped) =
+            DataKey::generate_wrapped(&config.master_key, &row).map_err(err)?;
+
+        Ok(ProviderInternal {
+            id: provider.key,
+            client_secret: data_key
+                .encrypt(&provider.secret, SECRET_FIELD)
+                .map_err(err)?,
+            key_id: config.master_key.id,
+            data_key: wrapped,
+            client_id: provider.id,
+            domain: provider.domain,
+            redirect_uri: provider.redirect_uri,
+            scope: provider.scope,
+            org_id: provider.org_id,
+            created: provider.created,
+        })
+    }
+}
+
+/// Internal sqlx mapping for the [UserProvider] model, with tokens encrypted by a
+/// [DataKey] which is wrapped by the [MasterKey](crate::crypto::MasterKey) of id
+/// `key_id`
+#[derive(FromRow)]
+pub(super) struct UserProviderInternal {
+    pub(super) id: i32,
+    pub(super) token_access: Vec<u8>,
+    pub(super) token_refresh: Option<Vec<u8>>,
+    pub(super) key_id: i32,
+    pub(super) data_key: Vec<u8>,
+    pub(super) token_expires: Option<DateTime<Utc>>,
+    pub(super) token_refresh_dead: bool,
+    pub(super) provider_id: i32,
+    pub(super) created: DateTime<Utc>,
+}
+
+impl IntoModel<UserProvider, i32> for (UserProviderInternal, &Config) {
+    fn into_model(self) -> AuthResult<UserProvider, i32> {
+        let (internal, config) = self;
+        let err = |_| AuthError::new(AuthErrorKind::EncryptionError, internal.id);
+        let row = row_aad(USER_PROVIDER_TABLE, internal.provider_id, internal.id);
+        let data_key = DataKey::unwrap_by_id(config, internal.key_id, &internal.data_key, &row)
+            .map_err(err)?;
+
+        Ok(UserProvider {
+            id: internal.id,
+            token_access: data_key
+                .decrypt(&internal.token_access, ACCESS_FIELD)
+                .map_err(err)?,
+            token_refresh: match &internal.token_refresh {
+                Some(val) => Some(data_key.decrypt(val, REFRESH_FIELD).map_err(err)?),
+                None => None,
+            },
+            token_expires: internal.token_expires,
+            token_refresh_dead: internal.token_refresh_dead,
+            provider_id: internal.provider_id,
+            created: internal.created,
+        })
+    }
+}
+
+impl IntoModel<UserProviderInternal, i32> for (UserProvider, &Config) {
+    fn into_model(self) -> AuthResult<UserProviderInternal, i32> {
+        let (user, config) = self;
+        let err = |_| AuthError::new(AuthErrorKind::EncryptionError, user.id);
+        let row = row_aad(USER_PROVIDER_TABLE, user.provider_id, user.id);
+        let (data_key, wrapped) =
+            DataKey::generate_wrapped(&config.master_key, &row).map_err(err)?;
+
+        Ok(UserProviderInternal {
+            id: user.id,
+            token_access: data_key
+                .encrypt(&user.token_access, ACCESS_FIELD)
+                .map_err(err)?,
+            token_refresh: match &user.token_refresh {
+                Some(val) => Some(data_key.encrypt(val, REFRESH_FIELD).map_err(err)?),
+                None => None,
+            },
+            key_id: config.master_key.id,
+            data_key: wrapped,
+            token_expires: user.token_expires,
+            token_refresh_dead: user.token_refresh_dead,
+            provider_id: user.provider_id,
+            created: user.created,
+        })
+    }
+}
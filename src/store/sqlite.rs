@@ -0,0 +1,276 @@
+//! See [SqliteStore] for documentation
+
+use crate::Config;
+use sqlx::SqlitePool;
+
+/// SQLite-backed [Store](super::Store) for single-instance deployments,
+/// encrypting secrets at rest using the master keys within it's [Config]
+pub struct SqliteStore {
+    pool: SqlitePool,
+    config: Config,
+}
+
+impl SqliteStore {
+    /// Creates a new [SqliteStore] from an already connected pool, which must
+    /// have foreign keys enabled for deletes to cascade
+    pub fn new(pool: SqlitePool, config: Config) -> Self {
+        Self { pool, config }
+    }
+}
+
+sql_store!(SqliteStore);
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::crypto::Hash;
+    use crate::migrate;
+    use crate::models::{
+        create_org, ApiKey, OauthState, OrgInvite, OrgMember, PasswordReset, Provider, Role, Scope,
+        Totp, UserProvider,
+    };
+    use crate::store::Store;
+    use crate::{AuthError, AuthErrorKind, MemberError, ProviderError};
+    use chrono::prelude::*;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    /// Opens a migrated in-memory database, on a single connection which is
+    /// never closed as each connection gets a database of it's own
+    pub(crate) async fn migrated_pool() -> SqlitePool {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:")
+            .unwrap()
+            .foreign_keys(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await
+            .unwrap();
+
+        migrate::sqlite::up(&pool).await.unwrap();
+        pool
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn migrations_reversible() {
+        let pool = migrated_pool().await;
+        let total = migrate::sqlite::MIGRATIONS.len();
+        assert_eq!(migrate::sqlite::version(&pool).await.unwrap(), total as i32);
+
+        assert_eq!(migrate::sqlite::down(&pool, 0).await.unwrap(), total);
+        assert_eq!(migrate::sqlite::version(&pool).await.unwrap(), 0);
+        assert_eq!(migrate::sqlite::up(&pool).await.unwrap(), total);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn secrets_bound_to_row() {
+        let config = Config::test();
+        let pool = migrated_pool().await;
+        let store = SqliteStore::new(pool.clone(), config.clone());
+        let org = create_org(&store, &config).await;
+        let provider = Provider::new("id", "secret", "domain", None, None, org.id).unwrap();
+        store.create_provider(&provider).await.unwrap();
+        let (victim, other) = (
+            UserProvider::new("victim", None, None, provider.key),
+            UserProvider::new("other", None, None, provider.key),
+        );
+        store.create_user_provider(&victim).await.unwrap();
+        store.create_user_provider(&other).await.unwrap();
+
+        // copying a token alongside it's wrapped key onto another row can't decrypt
+        sqlx::query(
+            "UPDATE user_provider SET (token_access, key_id, data_key) = \
+            (SELECT token_access, key_id, data_key FROM user_provider WHERE id = $1) \
+            WHERE id = $2",
+        )
+        .bind(victim.id)
+        .bind(other.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            store.get_user_provider(org.id, other.id).await,
+            Err(AuthError::new(AuthErrorKind::EncryptionError, other.id))
+        );
+        assert_eq!(
+            store
+                .get_user_provider(org.id, victim.id)
+                .await
+                .unwrap()
+                .token_access,
+            "victim"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn queries_and_cascades() {
+        let config = Config::test();
+        let store = SqliteStore::new(migrated_pool().await, config.clone());
+        let org = create_org(&store, &config).await;
+        assert_eq!(store.get_org(org.id).await.unwrap().password, org.password);
+
+        let member = OrgMember::new("alice", org.password.clone(), Role::Owner, org.id).unwrap();
+        store.create_member(&member).await.unwrap();
+        assert_eq!(store.list_members(org.id).await.unwrap().len(), 1);
+
+        // an org always keeps an owner
+        let last_owner = Err(AuthError::new(MemberError::LastOwner, member.id));
+        assert_eq!(
+            store.set_member_role(org.id, member.id, Role::Admin).await,
+            last_owner
+        );
+        assert_eq!(store.delete_member(org.id, member.id).await, last_owner);
+        let other = OrgMember::new("bob", org.password.clone(), Role::Viewer, org.id).unwrap();
+        store.create_member(&other).await.unwrap();
+        store
+            .set_member_role(org.id, other.id, Role::Owner)
+            .await
+            .unwrap();
+        store
+            .set_member_role(org.id, member.id, Role::Admin)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.delete_member(Uuid::new_v4(), member.id).await,
+            Err(AuthError::new(MemberError::NotFound, member.id))
+        );
+
+        let password = Hash::from_password(&config.hash, &config.pepper, "new").unwrap();
+        store
+            .set_member_password(member.id, &password)
+            .await
+            .unwrap();
+        let got = store.get_member(member.id).await.unwrap();
+        assert_eq!((got.password, got.role), (password, Role::Admin));
+
+        let (api_key, _) = ApiKey::new("key", vec![Scope::UsersRead], None, org.id).unwrap();
+        store.create_api_key(&api_key).await.unwrap();
+        let got = store.get_api_key_by_hash(&api_key.key_hash).await.unwrap();
+        assert_eq!(got.unwrap().scopes, api_key.scopes);
+
+        // every consumer returns it's row once and only once
+        let (invite, _) = OrgInvite::new(Role::Viewer, org.id);
+        store.create_invite(&invite, Utc::now()).await.unwrap();
+        let taken = store.take_invite(&invite.token_hash).await.unwrap();
+        assert_eq!(taken.map(|got| got.role), Some(Role::Viewer));
+        assert!(store
+            .take_invite(&invite.token_hash)
+            .await
+            .unwrap()
+            .is_none());
+
+        // an org only ever has one unexpired reset
+        let (reset, _) = PasswordReset::new(org.id);
+        assert!(store
+            .create_password_reset(&reset, Utc::now())
+            .await
+            .unwrap());
+        let (other, _) = PasswordReset::new(org.id);
+        assert!(!store
+            .create_password_reset(&other, Utc::now())
+            .await
+            .unwrap());
+        let taken = store.take_password_reset(&reset.token_hash).await.unwrap();
+        assert_eq!(taken.map(|got| got.org_id), Some(org.id));
+        assert!(store
+            .take_password_reset(&reset.token_hash)
+            .await
+            .unwrap()
+            .is_none());
+
+        let provider = Provider::new("id", "secret", "domain", None, None, org.id).unwrap();
+        store.create_provider(&provider).await.unwrap();
+        assert_eq!(
+            store.create_provider(&provider).await,
+            Err(AuthError::new(
+                ProviderError::AlreadyExists,
+                "id".to_string()
+            ))
+        );
+        assert_eq!(
+            store.get_provider(org.id, "id").await.unwrap().secret,
+            "secret"
+        );
+
+        let (state, _) = OauthState::new(provider.key);
+        store.create_oauth_state(&state, Utc::now()).await.unwrap();
+        assert!(store
+            .take_oauth_state(Uuid::new_v4(), &state.state)
+            .await
+            .unwrap()
+            .is_none());
+        let taken = store.take_oauth_state(org.id, &state.state).await.unwrap();
+        assert_eq!(
+            taken.map(|got| got.code_verifier),
+            Some(state.code_verifier)
+        );
+
+        let user = UserProvider::new("access", Some("refresh".to_string()), None, provider.key);
+        store.create_user_provider(&user).await.unwrap();
+        let got = store.get_user_provider(org.id, user.id).await.unwrap();
+        assert_eq!(got.token_refresh, user.token_refresh);
+
+        // token updates only apply over the refresh token they replace
+        let rotated = UserProvider {
+            token_refresh: Some("rotated".to_string()),
+            ..user.clone()
+        };
+        assert!(!store.update_user_provider(&rotated, "stale").await.unwrap());
+        assert!(store
+            .update_user_provider(&rotated, "refresh")
+            .await
+            .unwrap());
+        assert!(!store.kill_user_provider(user.id, "refresh").await.unwrap());
+        assert!(store.kill_user_provider(user.id, "rotated").await.unwrap());
+        let got = store.get_user_provider(org.id, user.id).await.unwrap();
+        assert_eq!(
+            (got.token_refresh, got.token_refresh_dead),
+            (rotated.token_refresh, true)
+        );
+
+        // two-factor of the member goes alongside it
+        let totp = Totp::new(member.id, org.id);
+        store.create_totp(&totp).await.unwrap();
+        store
+            .confirm_totp(totp.id, &[vec![1], vec![2]])
+            .await
+            .unwrap();
+        assert!(store.advance_totp(totp.id, 5).await.unwrap());
+        assert!(!store.advance_totp(totp.id, 5).await.unwrap());
+        assert!(store.take_recovery_code(totp.id, &[1]).await.unwrap());
+        assert!(!store.take_recovery_code(totp.id, &[1]).await.unwrap());
+        let got = store.get_totp(member.id).await.unwrap().unwrap();
+        assert_eq!((got.secret, got.confirmed), (totp.secret, true));
+
+        store.delete_member(org.id, member.id).await.unwrap();
+        assert!(store.get_totp(member.id).await.unwrap().is_none());
+
+        // everything else goes alongside the org
+        let org_totp = Totp::new(org.id, org.id);
+        store.create_totp(&org_totp).await.unwrap();
+        let (invite, _) = OrgInvite::new(Role::Viewer, org.id);
+        store.create_invite(&invite, Utc::now()).await.unwrap();
+        let (state, _) = OauthState::new(provider.key);
+        store.create_oauth_state(&state, Utc::now()).await.unwrap();
+
+        store.delete_org(org.id).await.unwrap();
+        assert!(store.get_org(org.id).await.is_err());
+        assert!(store.get_provider_by_key(provider.key).await.is_err());
+        assert!(store.get_user_provider(org.id, user.id).await.is_err());
+        assert!(store.list_api_keys(org.id).await.unwrap().is_empty());
+        assert!(store.get_totp(org.id).await.unwrap().is_none());
+        assert!(store
+            .take_invite(&invite.token_hash)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store
+            .take_oauth_state(org.id, &state.state)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
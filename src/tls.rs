@@ -0,0 +1,218 @@
+//! Https serving using rustls, with the certificate being reloaded from disk on
+//! `SIGHUP` or whenever it's files change, so rotations don't need restarts
+
+use crate::config::TlsConfig;
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use std::{fmt, fs};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Seconds between checking if the certificate files have changed
+const WATCH_INTERVAL: u64 = 10;
+
+/// Error whilst loading a certificate and it's private key
+#[derive(Debug)]
+pub enum TlsError {
+    /// Certificate chain couldn't be read or contained no certificates
+    InvalidCert(PathBuf),
+    /// Private key couldn't be read
+    InvalidKey(PathBuf),
+    /// Private key isn't supported or doesn't match the certificate
+    Rustls(rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::InvalidCert(path) => write!(
+                f,
+                "No pem certificates could be read from {}",
+                path.display()
+            ),
+            TlsError::InvalidKey(path) => write!(
+                f,
+                "No pem private key could be read from {}",
+                path.display()
+            ),
+            TlsError::Rustls(err) => write!(f, "The certificate or key is unusable, {}", err),
+        }
+    }
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(err: rustls::Error) -> Self {
+        Self::Rustls(err)
+    }
+}
+
+/// Resolves the certificate for every handshake, which is swapped out by
+/// [CertResolver::reload] without affecting existing connections
+#[derive(Debug)]
+pub struct CertResolver {
+    cert: PathBuf,
+    key: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    /// Creates a new [CertResolver], loading the certificate for the first time
+    pub fn new(tls: &TlsConfig) -> Result<Self, TlsError> {
+        Ok(Self {
+            current: RwLock::new(Arc::new(load(&tls.cert, &tls.key)?)),
+            cert: tls.cert.clone(),
+            key: tls.key.clone(),
+        })
+    }
+
+    /// Loads the certificate from disk again, keeping the current one if the new
+    /// one is unusable such as when only half of it has been written
+    pub fn reload(&self) -> Result<(), TlsError> {
+        let certified = load(&self.cert, &self.key)?;
+        *self.current.write().unwrap_or_else(|err| err.into_inner()) = Arc::new(certified);
+        Ok(())
+    }
+
+    /// Gets when the certificate and key files were last modified
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        Some((modified(&self.cert)?, modified(&self.key)?))
+    }
+
+    /// Reloads whenever `SIGHUP` is received or the certificate files change,
+    /// running forever
+    pub async fn watch(self: Arc<Self>) {
+        let mut hangup = signal(SignalKind::hangup()).expect("❌ Couldn't listen for SIGHUP");
+        let mut interval = tokio::time::interval(Duration::from_secs(WATCH_INTERVAL));
+        let mut modified = self.modified();
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let now = self.modified();
+                    if now == modified {
+                        continue;
+                    }
+                    modified = now;
+                }
+                _ = hangup.recv() => (),
+            }
+
+            match self.reload() {
+                Ok(()) => println!("🔒 Reloaded tls certificate!"),
+                Err(err) => eprintln!(
+                    "❌ Couldn't reload tls certificate, keeping the current one, {}",
+                    err
+                ),
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.current
+                .read()
+                .unwrap_or_else(|err| err.into_inner())
+                .clone(),
+        )
+    }
+}
+
+/// Creates the rustls [ServerConfig] serving certificates from a [CertResolver]
+pub fn server_config(resolver: Arc<CertResolver>) -> Result<ServerConfig, TlsError> {
+    Ok(ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver))
+}
+
+/// Redirects plain http requests to the same path of [Config::advertised_url](crate::Config::advertised_url)
+pub async fn redirect(req: HttpRequest, url: web::Data<String>) -> HttpResponse {
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, format!("{}{}", url.get_ref(), path)))
+        .finish()
+}
+
+/// Gets the crypto provider used for all tls
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+/// Loads a certificate chain and it's matching private key from pem files
+fn load(cert: &Path, key: &Path) -> Result<CertifiedKey, TlsError> {
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|_| TlsError::InvalidCert(cert.to_path_buf()))?;
+    if chain.is_empty() {
+        return Err(TlsError::InvalidCert(cert.to_path_buf()));
+    }
+
+    let private =
+        PrivateKeyDer::from_pem_file(key).map_err(|_| TlsError::InvalidKey(key.to_path_buf()))?;
+    Ok(CertifiedKey::from_der(chain, private, &provider())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_files() {
+        let tls = TlsConfig {
+            cert: "missing-cert.pem".into(),
+            key: "missing-key.pem".into(),
+            redirect: vec![],
+        };
+
+        assert!(matches!(
+            CertResolver::new(&tls),
+            Err(TlsError::InvalidCert(_))
+        ));
+    }
+
+    #[test]
+    fn reload_keeps_current() {
+        let dir = std::env::temp_dir().join(format!("authrio-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let tls = TlsConfig {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+            redirect: vec![],
+        };
+        let write = |generated: &rcgen::CertifiedKey| {
+            fs::write(&tls.cert, generated.cert.pem()).unwrap();
+            fs::write(&tls.key, generated.key_pair.serialize_pem()).unwrap();
+            generated.cert.der().clone()
+        };
+        let served = |resolver: &CertResolver| resolver.current.read().unwrap().cert[0].clone();
+
+        let first = write(&rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap());
+        let resolver = CertResolver::new(&tls).unwrap();
+        assert_eq!(served(&resolver), first);
+
+        let second = write(&rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap());
+        resolver.reload().unwrap();
+        assert_eq!(served(&resolver), second);
+
+        fs::write(&tls.cert, "-----BEGIN CERTIFICATE-----\nhalf").unwrap();
+        assert!(matches!(resolver.reload(), Err(TlsError::InvalidCert(_))));
+        assert_eq!(served(&resolver), second);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}